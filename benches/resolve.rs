@@ -0,0 +1,64 @@
+use autoresolve::battle::{BattleData, BattleType};
+use autoresolve::generator::{BattleGenerator, BattleSource, GeneratorProfile};
+use autoresolve::output::OutcomeCounts;
+use autoresolve::resolver::BatchResolver;
+use autoresolve::roster::Roster;
+use autoresolve::rng;
+use autoresolve::treasure::Treasure;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+
+/// Number of battles resolved in each benchmark iteration
+const BATTLES : u32 = 1000;
+
+/// Compare resolving full battles with sampling only their outcomes
+fn resolve_benchmark(c : &mut Criterion){
+    let roster = Roster::embedded();
+    let treasure = Treasure::embedded();
+    let resolver = BatchResolver::default();
+    rng::seed(1);
+    let normal = BattleGenerator::new(BattleSource::Random(Some(BattleType::Normal)), GeneratorProfile::default(), None);
+    let sources = [
+        ("fixed", BattleSource::Fixed(Box::new(normal.next_battle(&roster, &treasure)))),
+        ("random", BattleSource::Random(None)),
+    ];
+
+    for (name, source) in sources{
+        let generator = BattleGenerator::new(source, GeneratorProfile::default(), None);
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Elements(BATTLES as u64));
+
+        group.bench_function("full", |b| b.iter(|| {
+            let mut counts = OutcomeCounts::default();
+            for _ in 0..BATTLES{
+                let mut battle = generator.next_battle(&roster, &treasure);
+                let mut data = BattleData::new(&roster);
+                counts.add(battle.autoresolve(&treasure, &mut data).get_outcome());
+            }
+            counts
+        }));
+
+        group.bench_function("batch", |b| b.iter(|| {
+            let mut counts = OutcomeCounts::default();
+            let mut r = rng::thread_rng();
+            for _ in 0..BATTLES{
+                let total = generator.next_battle(&roster, &treasure).get_deterministic_total();
+                counts.add(&resolver.sample_outcome(total, &mut r));
+            }
+            counts
+        }));
+
+        // a fixed battle's deterministic total only needs calculating once
+        if let Some(battle) = generator.get_fixed_battle(){
+            let total = battle.get_deterministic_total();
+            group.bench_function("batch_fixed", |b| b.iter(|| {
+                let mut counts = OutcomeCounts::default();
+                resolver.resolve(total, BATTLES, &mut rng::thread_rng(), &mut counts);
+                counts
+            }));
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, resolve_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,384 @@
+use crate::battle::BattleOutcome;
+use crate::database::QueryTable;
+use crate::generator::parse_range;
+use crate::output::{OutcomeCounts, Statistics};
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Rows of previously captured battle data read from CSV files
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaptureTable{
+    columns : Vec<String>,
+    rows : Vec<Vec<String>>,
+}
+
+impl CaptureTable{
+    /// Read captured data from CSV files, merging their columns by name, rows of files without a column have it empty
+    pub fn from_files(files : &[String]) -> Self{
+        let mut table : Option<CaptureTable> = None;
+        for f in files{
+            let content = fs::read_to_string(f).unwrap_or_else(|e| panic!("Unable to read {}: {}", f, e));
+            let t = CaptureTable::from_csv(&content, f);
+            match table.as_mut(){
+                None => table = Some(t),
+                Some(existing) => existing.merge(t),
+            }
+        }
+        table.expect("No files to analyze")
+    }
+
+    /// Add another table's rows, matching columns by name and the order of repeated names
+    pub fn merge(&mut self, other : CaptureTable){
+        let keys = |columns : &[String]| columns.iter().enumerate()
+            .map(|(i, c)| (c.clone(), columns[..i].iter().filter(|p| *p == c).count()))
+            .collect::<Vec<(String, usize)>>();
+        let mut existing = keys(&self.columns);
+        let added = keys(&other.columns);
+        for k in added.iter(){
+            if !existing.contains(k){
+                existing.push(k.clone());
+                self.columns.push(k.0.clone());
+                self.rows.iter_mut().for_each(|r| r.push(String::new()));
+            }
+        }
+        let positions = existing.iter().map(|k| added.iter().position(|a| a == k)).collect::<Vec<Option<usize>>>();
+        for row in other.rows{
+            self.rows.push(positions.iter().map(|p| p.map(|i| row[i].clone()).unwrap_or_default()).collect());
+        }
+    }
+
+    /// Parse CSV content with a header line, source is used in error messages
+    pub fn from_csv(content : &str, source : &str) -> Self{
+        let mut lines = content.lines().filter(|l| !l.trim().is_empty());
+        let columns = lines.next().unwrap_or_else(|| panic!("{} has no header", source))
+            .split(',').map(|c| c.trim().to_string()).collect::<Vec<String>>();
+        let rows = lines.enumerate().map(|(i, l)| {
+            let row = l.split(',').map(|v| v.trim().to_string()).collect::<Vec<String>>();
+            if row.len() != columns.len(){
+                panic!("Row {} of {} has {} values, expected {}", i + 1, source, row.len(), columns.len());
+            }
+            row
+        }).collect();
+        CaptureTable{ columns, rows }
+    }
+
+    /// Get index of a column
+    fn column_index(&self, column : &str) -> usize{
+        self.columns.iter().position(|c| c == column)
+            .unwrap_or_else(|| panic!("Unknown column {}", column))
+    }
+
+    /// Get number of rows
+    pub fn get_row_count(&self) -> usize{
+        self.rows.len()
+    }
+}
+
+/// Comparison of a column value in a Filter
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Comparison{
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison{
+    /// Operators in the order they are searched for when parsing
+    const OPERATORS : [(&'static str, Comparison); 6] = [
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessEqual),
+        (">=", Comparison::GreaterEqual),
+        ("=", Comparison::Equal),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    /// Compare a value to the expected value, numerically if both are numbers
+    fn compare(&self, value : &str, expected : &str) -> bool{
+        let ordering = match (value.parse::<f64>(), expected.parse::<f64>()){
+            (Ok(a), Ok(b)) => a.partial_cmp(&b),
+            _ => Some(value.cmp(expected)),
+        };
+        match ordering{
+            None => false,
+            Some(o) => match self{
+                Comparison::Equal => o.is_eq(),
+                Comparison::NotEqual => o.is_ne(),
+                Comparison::Less => o.is_lt(),
+                Comparison::LessEqual => o.is_le(),
+                Comparison::Greater => o.is_gt(),
+                Comparison::GreaterEqual => o.is_ge(),
+            }
+        }
+    }
+}
+
+/// Conditions a row must meet to be analyzed, matches if any of its columns meet all conditions
+#[derive(Debug, Clone, PartialEq)]
+pub struct Filter{
+    columns : Vec<String>,
+    conditions : Vec<(Comparison, String)>,
+}
+
+impl Filter{
+    /// Create new Filter
+    pub fn new(columns : &[&str], comparison : Comparison, value : &str) -> Self{
+        Filter{
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+            conditions: vec![(comparison, String::from(value))],
+        }
+    }
+
+    /// Parse filter in the form COLUMN=VALUE, also supports !=, <, <=, >, >=
+    pub fn parse(filter : &str) -> Self{
+        Comparison::OPERATORS.iter()
+            .find_map(|(op, c)| filter.split_once(op).map(|(col, val)| Filter::new(&[col.trim()], *c, val.trim())))
+            .unwrap_or_else(|| panic!("Invalid filter {}, expected COLUMN=VALUE", filter))
+    }
+
+    /// Filter for a faction on either side
+    pub fn faction(faction : &str) -> Self{
+        Filter::new(&["AFaction", "DFaction"], Comparison::Equal, faction)
+    }
+
+    /// Filter for a battle type
+    pub fn battle_type(battle_type : &str) -> Self{
+        Filter::new(&["BattleType"], Comparison::Equal, battle_type)
+    }
+
+    /// Filter for a general's rank on either side in a range, e.g. "3-5", or a single rank
+    pub fn rank(range : &str) -> Self{
+        let (min, max) = parse_range(range);
+        let mut filter = Filter::new(&["AGenRank", "DGenRank"], Comparison::GreaterEqual, &min.to_string());
+        filter.conditions.push((Comparison::LessEqual, max.to_string()));
+        filter
+    }
+
+    /// Get indexes of the filter's columns in a table
+    fn column_indexes(&self, table : &CaptureTable) -> Vec<usize>{
+        self.columns.iter().map(|c| table.column_index(c)).collect()
+    }
+
+    /// Get if a row meets the conditions, given the indexes of the filter's columns
+    fn matches(&self, indexes : &[usize], row : &[String]) -> bool{
+        indexes.iter().any(|i| self.conditions.iter().all(|(comparison, value)| comparison.compare(&row[*i], value)))
+    }
+}
+
+/// Indexes of the columns GroupStats reads from each row
+struct StatColumns{
+    outcome : usize,
+    total : usize,
+    attacker_soldier_casualties : usize,
+    attacker_unit_casualties : usize,
+    defender_soldier_casualties : usize,
+    defender_unit_casualties : usize,
+}
+
+impl StatColumns{
+    /// Find the columns in a table
+    fn new(table : &CaptureTable) -> Self{
+        StatColumns{
+            outcome: table.column_index("Outcome"),
+            total: table.column_index("EndingTot"),
+            attacker_soldier_casualties: table.column_index("ASoldierCas"),
+            attacker_unit_casualties: table.column_index("AUnitCas"),
+            defender_soldier_casualties: table.column_index("DSoldierCas"),
+            defender_unit_casualties: table.column_index("DUnitCas"),
+        }
+    }
+}
+
+/// Outcome distribution and statistics of a group of captured battles
+#[derive(Debug, Clone, Default)]
+pub struct GroupStats{
+    battles : u64,
+    outcomes : OutcomeCounts,
+    attacker_wins : u64,
+    total : Statistics,
+    attacker_soldier_casualties : Statistics,
+    attacker_unit_casualties : Statistics,
+    defender_soldier_casualties : Statistics,
+    defender_unit_casualties : Statistics,
+}
+
+impl GroupStats{
+    /// Add a captured row to the group
+    fn add(&mut self, table : &CaptureTable, columns : &StatColumns, row : &[String]){
+        let number = |i : usize| row[i].parse::<f64>()
+            .unwrap_or_else(|_| panic!("Invalid number {} in column {}", row[i], table.columns[i]));
+
+        let outcome = BattleOutcome::from_name(&row[columns.outcome]);
+        self.battles += 1;
+        self.outcomes.add(&outcome);
+        if outcome.is_victory(){
+            self.attacker_wins += 1;
+        }
+        self.total.add(number(columns.total));
+        self.attacker_soldier_casualties.add(number(columns.attacker_soldier_casualties));
+        self.attacker_unit_casualties.add(number(columns.attacker_unit_casualties));
+        self.defender_soldier_casualties.add(number(columns.defender_soldier_casualties));
+        self.defender_unit_casualties.add(number(columns.defender_unit_casualties));
+    }
+
+    /// Get number of battles in the group
+    pub fn get_battles(&self) -> u64{
+        self.battles
+    }
+
+    /// Get outcome counts
+    pub fn get_outcomes(&self) -> &OutcomeCounts{
+        &self.outcomes
+    }
+
+    /// Get attacker soldier casualty statistics
+    pub fn get_attacker_soldier_casualties(&self) -> &Statistics{
+        &self.attacker_soldier_casualties
+    }
+
+    /// Convert to printable report
+    pub fn stats_output(&self) -> String{
+        let percent = |n : u64| format!("{:.1}%", 100.0 * n as f64 / self.battles.max(1) as f64);
+        let outcomes = QueryTable{
+            columns: vec![String::from("Outcome (for attacker)"), String::from("Battles"), String::from("Percent")],
+            rows: BattleOutcome::ALL.iter()
+                .map(|o| vec![format!("{:?}", o), self.outcomes.get(o).to_string(), percent(self.outcomes.get(o))])
+                .collect(),
+        };
+        let stats = |name : &str, s : &Statistics| vec![name.to_string(), format!("{:.2}", s.get_mean()),
+                                                       format!("{:.2}", s.get_std_dev()), s.get_min().to_string(), s.get_max().to_string()];
+        let statistics = QueryTable{
+            columns: ["Statistic", "Mean", "Std Dev", "Min", "Max"].iter().map(|c| c.to_string()).collect(),
+            rows: vec![
+                stats("Ending Total", &self.total),
+                stats("Attacker Soldier Casualties", &self.attacker_soldier_casualties),
+                stats("Attacker Unit Casualties", &self.attacker_unit_casualties),
+                stats("Defender Soldier Casualties", &self.defender_soldier_casualties),
+                stats("Defender Unit Casualties", &self.defender_unit_casualties),
+            ],
+        };
+        format!("{}\nAttacker Win Rate: {}\n\n{}", outcomes.table_output(), percent(self.attacker_wins), statistics.table_output())
+    }
+}
+
+/// Filters and grouping used to analyze captured data
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Analysis{
+    pub files : Vec<String>,
+    pub filters : Vec<Filter>,
+    pub group_by : Option<String>,
+}
+
+impl Analysis{
+    /// Calculate stats for each group of rows meeting all filters, keyed by the group_by column's value
+    pub fn analyze(&self, table : &CaptureTable) -> BTreeMap<String, GroupStats>{
+        // find every column before reading rows so unknown columns are reported even without rows
+        let filters = self.filters.iter().map(|f| (f, f.column_indexes(table))).collect::<Vec<(&Filter, Vec<usize>)>>();
+        let group_index = self.group_by.as_ref().map(|c| table.column_index(c));
+        let columns = StatColumns::new(table);
+        let mut groups : BTreeMap<String, GroupStats> = BTreeMap::new();
+        for row in table.rows.iter().filter(|r| filters.iter().all(|(f, indexes)| f.matches(indexes, r))){
+            let key = group_index.map(|i| row[i].clone()).unwrap_or_else(|| String::from("All"));
+            groups.entry(key).or_default().add(table, &columns, row);
+        }
+        groups
+    }
+
+    /// Read files, analyze them and print the results
+    pub fn run(&self){
+        let table = CaptureTable::from_files(&self.files);
+        let groups = self.analyze(&table);
+        println!("Analyzed {} of {} battles from {}", groups.values().map(|g| g.get_battles()).sum::<u64>(),
+                 table.get_row_count(), self.files.join(", "));
+
+        // order numeric groups by value rather than as text
+        let mut keys = groups.keys().collect::<Vec<&String>>();
+        if keys.iter().all(|k| k.parse::<f64>().is_ok()){
+            keys.sort_by(|a, b| a.parse::<f64>().unwrap().partial_cmp(&b.parse::<f64>().unwrap()).unwrap());
+        }
+        for k in keys{
+            let g = &groups[k];
+            match &self.group_by{
+                Some(c) => println!("\n{} = {} ({} battles)", c, k, g.get_battles()),
+                None => println!(),
+            }
+            println!("{}", g.stats_output());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const CSV : &str = "BattleType,EndingTot,Outcome,AGenRank,AFaction,ASoldierCas,AUnitCas,DGenRank,DFaction,DSoldierCas,DUnitCas
+Normal,10,DecisiveVictory,3,Rebel,2,0,2,Menoriad,10,1
+Normal,-10,CrushingDefeat,7,Menoriad,12,2,1,Rebel,1,0
+Siege,0,Draw,5,Lerastir,4,0,4,Beladimir,4,0
+Siege,2.5,HeroicVictory,1,Rebel,3,0,9,Lerastir,6,1
+";
+
+    #[test]
+    fn test_parse_filter(){
+        assert_eq!(Filter::new(&["AGenRank"], Comparison::GreaterEqual, "5"), Filter::parse("AGenRank>=5"));
+        assert_eq!(Filter::new(&["Outcome"], Comparison::NotEqual, "Draw"), Filter::parse("Outcome != Draw"));
+        assert_eq!(Filter::new(&["EndingTot"], Comparison::Less, "-2"), Filter::parse("EndingTot<-2"));
+        assert!(Comparison::Less.compare("9", "10"));
+        assert!(!Comparison::Less.compare("Siege", "Naval"));
+    }
+
+    #[test]
+    fn test_filters(){
+        let t = CaptureTable::from_csv(CSV, "test");
+        let a = Analysis{ filters: vec![Filter::faction("Rebel")], ..Default::default() };
+        assert_eq!(3, a.analyze(&t)["All"].get_battles());
+
+        let a = Analysis{ filters: vec![Filter::battle_type("Siege"), Filter::parse("EndingTot>0")], ..Default::default() };
+        let g = a.analyze(&t);
+        assert_eq!(1, g["All"].get_battles());
+        assert_eq!(1, g["All"].get_outcomes().get(&BattleOutcome::HeroicVictory));
+
+        let a = Analysis{ filters: vec![Filter::rank("4-7")], ..Default::default() };
+        assert_eq!(2, a.analyze(&t)["All"].get_battles());
+        let a = Analysis{ filters: vec![Filter::rank("2")], ..Default::default() };
+        assert_eq!(1, a.analyze(&t)["All"].get_battles());
+    }
+
+    #[test]
+    fn test_group_by(){
+        let t = CaptureTable::from_csv(CSV, "test");
+        let a = Analysis{ group_by: Some(String::from("BattleType")), ..Default::default() };
+        let g = a.analyze(&t);
+        assert_eq!(2, g.len());
+        assert_eq!(2, g["Normal"].get_battles());
+        assert_eq!(7.0, g["Normal"].get_attacker_soldier_casualties().get_mean());
+        assert!(g["Siege"].stats_output().contains("Attacker Win Rate: 50.0%"));
+    }
+
+    #[test]
+    fn test_merge(){
+        let mut t = CaptureTable::from_csv("A,B,B\n1,2,3\n", "a");
+        t.merge(CaptureTable::from_csv("B,C,A\n4,5,6\n", "b"));
+        assert_eq!(vec!["A", "B", "B", "C"], t.columns);
+        assert_eq!(vec![vec!["1", "2", "3", ""], vec!["6", "4", "", "5"]], t.rows);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown column")]
+    fn test_unknown_column(){
+        let t = CaptureTable::from_csv(CSV, "test");
+        let a = Analysis{ group_by: Some(String::from("Missing")), ..Default::default() };
+        a.analyze(&t);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown column Missing")]
+    fn test_unknown_filter_column_without_rows(){
+        let t = CaptureTable::from_csv(CSV.lines().next().unwrap(), "test");
+        let a = Analysis{ filters: vec![Filter::parse("Missing=1")], ..Default::default() };
+        a.analyze(&t);
+    }
+}
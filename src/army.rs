@@ -0,0 +1,189 @@
+use crate::resources::{self, EMBEDDED_PREFIX};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory army files are looked for in by name, in the battle file's directory, the user config directory
+/// and the embedded resources
+pub const ARMY_DIR : &str = "armies";
+
+/// Key of the army referenced by a player object, its other fields override the army's
+pub const ARMY_KEY : &str = "army";
+
+/// Player slots of battle JSON that can reference armies
+pub const SIDES : [&str; 2] = ["attacker", "defender"];
+
+/// Randomized player, never an army name
+const RANDOM : &str = "Random";
+
+/// Get directory army references in a battle file are resolved against, None for embedded files
+pub fn base_dir(file_path : &str) -> Option<PathBuf>{
+    match file_path.starts_with(EMBEDDED_PREFIX){
+        true => None,
+        false => Some(Path::new(file_path).parent().map(Path::to_path_buf).unwrap_or_default()),
+    }
+}
+
+/// Get if an army reference is a file path rather than a name
+fn is_path(reference : &str) -> bool{
+    reference.starts_with(EMBEDDED_PREFIX) || reference.ends_with(".json") || reference.contains('/') || reference.contains('\\')
+}
+
+/// Get directories searched for army files by name, from highest to lowest precedence
+fn army_dirs(base_dir : Option<&Path>) -> Vec<PathBuf>{
+    base_dir.map(Path::to_path_buf).into_iter().chain(resources::config_dir()).map(|d| d.join(ARMY_DIR)).collect()
+}
+
+/// Get names of the armies that can be referenced from a battle file in base_dir
+pub fn army_names(base_dir : Option<&Path>) -> Vec<String>{
+    let mut names : Vec<String> = army_dirs(base_dir).iter()
+        .flat_map(|d| fs::read_dir(d).into_iter().flatten())
+        .filter_map(|e| e.ok()?.file_name().to_str()?.strip_suffix(".json").map(String::from))
+        .chain(resources::embedded_names().iter()
+            .filter_map(|n| n.strip_prefix(ARMY_DIR)?.strip_prefix('/')?.strip_suffix(".json").map(String::from)))
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Find the army file for a reference. Relative paths are resolved against base_dir, names are looked for
+/// as armies/NAME.json in base_dir, then the user config directory, then the embedded armies
+pub fn locate_army(reference : &str, base_dir : Option<&Path>) -> Result<String, String>{
+    if is_path(reference){
+        let path = Path::new(reference);
+        return Ok(match (reference.starts_with(EMBEDDED_PREFIX) || path.is_absolute(), base_dir){
+            (false, Some(d)) => d.join(path).to_string_lossy().to_string(),
+            _ => String::from(reference),
+        });
+    }
+    let file = format!("{}.json", reference);
+    let embedded = format!("{}/{}", ARMY_DIR, file);
+    army_dirs(base_dir).into_iter().map(|d| d.join(&file)).find(|p| p.is_file())
+        .map(|p| p.to_string_lossy().to_string())
+        .or_else(|| resources::embedded(&embedded).map(|_| format!("{}{}", EMBEDDED_PREFIX, embedded)))
+        .ok_or_else(|| format!("No army named {}, expected a path or one of {}", reference, army_names(base_dir).join(", ")))
+}
+
+/// Read and parse an army file
+fn read_army(path : &str) -> Result<Value, String>{
+    let content = match path.strip_prefix(EMBEDDED_PREFIX){
+        Some(name) => resources::embedded(name).map(String::from).ok_or_else(|| format!("No embedded army {}", name))?,
+        None => fs::read_to_string(path).map_err(|e| format!("Unable to read army {}: {}", path, e))?,
+    };
+    serde_json::from_str(&content).map_err(|e| format!("Unable to parse army {}: {}", path, e))
+}
+
+/// Override fields of base, objects are merged field by field and other values replaced
+pub fn merge(base : &mut Value, overrides : Map<String, Value>){
+    let fields = match base{
+        Value::Object(fields) => fields,
+        _ => {
+            *base = Value::Object(overrides);
+            return;
+        },
+    };
+    for (key, value) in overrides{
+        match (fields.get_mut(&key), value){
+            (Some(b @ Value::Object(_)), Value::Object(o)) => merge(b, o),
+            (_, v) => { fields.insert(key, v); },
+        }
+    }
+}
+
+/// Replace an army reference in a player slot with the army, either a name or path, or an object with the
+/// reference under ARMY_KEY and fields overriding the army's. Army files can reference other armies,
+/// chain holds the army files being expanded to detect cycles.
+fn expand_player(slot : &mut Value, base_dir : Option<&Path>, chain : &mut Vec<String>) -> Result<(), String>{
+    let (reference, overrides) = match slot{
+        Value::String(s) if s.as_str() != RANDOM => (s.clone(), None),
+        Value::Object(fields) => match fields.get(ARMY_KEY){
+            // the slot is left unchanged if the army can't be expanded
+            Some(Value::String(s)) => (s.clone(), Some(fields.iter().filter(|(k, _)| *k != ARMY_KEY).map(|(k, v)| (k.clone(), v.clone())).collect())),
+            Some(v) => return Err(format!("Army reference {} is not a name or path", v)),
+            None => return Ok(()),
+        },
+        _ => return Ok(()),
+    };
+    let path = locate_army(&reference, base_dir)?;
+    if chain.contains(&path){
+        return Err(format!("Army {} references itself through {}", path, chain.join(" -> ")));
+    }
+    chain.push(path.clone());
+    let mut army = read_army(&path)?;
+    expand_player(&mut army, base_dir, chain)?;
+    chain.pop();
+    if let Some(o) = overrides{
+        if !army.is_object(){
+            return Err(format!("Unable to override fields of army {}, it is not an object", path));
+        }
+        merge(&mut army, o);
+    }
+    *slot = army;
+    Ok(())
+}
+
+/// Replace an army reference of one side in battle JSON with the army
+pub fn expand_side(battle : &mut Value, side : &str, base_dir : Option<&Path>) -> Result<(), String>{
+    match battle.get_mut(side){
+        Some(slot) => expand_player(slot, base_dir, &mut vec![]),
+        None => Ok(()),
+    }
+}
+
+/// Replace army references of the attacker and defender in battle JSON with the armies
+pub fn expand_battle(battle : &mut Value, base_dir : Option<&Path>) -> Result<(), String>{
+    for side in SIDES.iter(){
+        expand_side(battle, side, base_dir).map_err(|e| format!("Invalid {}: {}", side, e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::testing::TestDir;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge(){
+        let mut army = json!({"general" : {"weapon" : 10, "rank" : 3}, "units" : [1, 2, 3], "faction" : "Beladimir"});
+        let overrides = json!({"general" : {"rank" : 4}, "units" : [4]});
+        merge(&mut army, overrides.as_object().unwrap().clone());
+        assert_eq!(json!({"general" : {"weapon" : 10, "rank" : 4}, "units" : [4], "faction" : "Beladimir"}), army);
+    }
+
+    #[test]
+    fn test_locate(){
+        assert_eq!(Ok(String::from("embedded:armies/beladimir_vanguard.json")), locate_army("beladimir_vanguard", None));
+        assert_eq!(Ok(String::from("./ResourceFiles/armies/beladimir_vanguard.json")),
+                   locate_army("beladimir_vanguard", Some(Path::new("./ResourceFiles"))));
+        assert_eq!(Ok(String::from("dir/a.json")), locate_army("a.json", Some(Path::new("dir"))));
+        assert_eq!(Ok(String::from("/a/b")), locate_army("/a/b", Some(Path::new("dir"))));
+        assert!(locate_army("missing", None).unwrap_err().starts_with("No army named missing, expected a path or one of"));
+        assert!(army_names(None).contains(&String::from("menoriad_garrison")));
+        assert_eq!(Some(PathBuf::from("dir")), base_dir("dir/battle.json"));
+        assert_eq!(None, base_dir("embedded:normal_battle_template.json"));
+    }
+
+    #[test]
+    fn test_expand(){
+        let test_dir = TestDir::new("army");
+        let dir = test_dir.path();
+        test_dir.write(&format!("{}/veterans.json", ARMY_DIR),
+                       r#"{"army" : "beladimir_vanguard", "general" : {"rank" : 5}, "reinforcements" : 2}"#);
+        let looped = test_dir.write("loop.json", r#"{"army" : "loop.json"}"#);
+
+        let mut battle = json!({"battle_type" : "Normal", "attacker" : {"army" : "veterans", "units" : [1]},
+            "defender" : "Random"});
+        expand_battle(&mut battle, Some(dir)).unwrap();
+        assert_eq!(json!({"armor" : 0, "weapon" : 10, "banner" : 28, "follower" : 0, "trinket" : 19, "rank" : 5}),
+                   battle["attacker"]["general"]);
+        assert_eq!((&json!([1]), &json!(2)), (&battle["attacker"]["units"], &battle["attacker"]["reinforcements"]));
+        assert_eq!(json!("Random"), battle["defender"]);
+
+        let mut battle = json!({"attacker" : "loop.json"});
+        let e = expand_battle(&mut battle, Some(dir)).unwrap_err();
+        assert!(e.starts_with(&format!("Invalid attacker: Army {} references itself", looped)));
+    }
+}
@@ -3,12 +3,17 @@ use crate::player::{Player, PlayerJSONObject};
 use crate::monster::MonsterType;
 use crate::equipment::{Equipment, EquipmentType};
 use crate::general::{GeneralState};
+use crate::faction::Faction;
 use rand::Rng;
 use crate::roster::Roster;
-use std::path::Path;
-use std::fs::{OpenOptions};
+use crate::generator::{GeneratorProfile, Randomizable, RandomMarker};
+use crate::output::open_locked;
+use crate::resources;
+use crate::army;
+use crate::schema;
 use std::io::Write;
 use std::fs;
+use std::collections::BTreeMap;
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone)]
@@ -34,7 +39,7 @@ impl Battle{
         match self.battle_type{
             BattleType::Monster { .. } => {
                 data.collect_initial_battle_data(&self);
-                let outcome = self.monster_outcome(data);
+                let outcome = self.calculate_outcome(data);
                 let mut casualties = self.calculate_casualties(&outcome);
                 Self::assign_casualties(&mut casualties.attacker, &mut self.attacker);
                 let treasure_results = self.treasure_results(treasure);
@@ -66,11 +71,9 @@ impl Battle{
         }
     }
 
-    /// Calculate the outcome for a Monster Battle type
-    fn monster_outcome(&mut self, data : &mut BattleData) -> BattleOutcome{
-        let mut total : f32 = 0.0;
-        // add attacker bonus
-        total += self.attacker.get_autoresolve_bonus() as f32;
+    /// Calculate the outcome of the battle based on each Player's statistics
+    fn calculate_outcome(&mut self, data : &mut BattleData) -> BattleOutcome {
+        let mut total : f32 = self.get_deterministic_total();
 
         // add random bonuses
         let att_rand = self.battle_randoms() as f32;
@@ -78,28 +81,25 @@ impl Battle{
         total += att_rand;
         total -= def_rand;
 
-        // add BattleType bonuses
-        total += self.battle_type.get_calculation() as f32;
-
         // determine outcome
         data.collect_battle_calculations(att_rand,def_rand,total);
         BattleOutcome::determine_outcome(total)
     }
 
-    /// Calculate the outcome of the battle based on each Player's statistics
-    fn calculate_outcome(&mut self, data : &mut BattleData) -> BattleOutcome {
+    /// Get the part of the outcome total that does not depend on random rolls
+    pub fn get_deterministic_total(&self) -> f32{
         let mut total : f32 = 0.0;
 
-        // get player autoresolve bonuses
+        // add attacker bonus
         total += self.attacker.get_autoresolve_bonus() as f32;
-        total -= self.defender.get_autoresolve_bonus() as f32;
 
-        // add random bonuses
-        let att_rand = self.battle_randoms() as f32;
-        let def_rand = self.battle_randoms() as f32;
-        total += att_rand;
-        total -= def_rand;
+        // Monster battles only use the attacker's bonus
+        if let BattleType::Monster { .. } = self.battle_type{
+            return total + self.battle_type.get_calculation() as f32;
+        }
 
+        // subtract defender bonus
+        total -= self.defender.get_autoresolve_bonus() as f32;
 
         // calculate RPS bonuses
         total += 1.5 * (self.attacker.get_cavalry_bonus() - self.defender.get_ranged_bonus()) as f32;
@@ -108,15 +108,12 @@ impl Battle{
 
         // add BattleType bonuses
         total += self.battle_type.get_calculation() as f32;
-
-        // determine outcome
-        data.collect_battle_calculations(att_rand,def_rand,total);
-        BattleOutcome::determine_outcome(total)
+        total
     }
 
     /// Calculate casualties for attacker and defender based on battle outcome
     fn calculate_casualties(&self, outcome : &BattleOutcome) -> BattleCasualties {
-        let mut rng = rand::thread_rng();
+        let mut rng = crate::rng::thread_rng();
 
         // Attacker Casualties
         let att_tot = self.attacker.get_soldier_count();
@@ -173,7 +170,7 @@ impl Battle{
 
     /// Assign casualties to a player
     fn assign_casualties(casualties : &mut Casualties, player : &mut Player){
-        let mut rng = rand::thread_rng();
+        let mut rng = crate::rng::thread_rng();
 
         // If casualties > player's soldier count, assign all units to max casualties
         if casualties.casualties >= player.get_soldier_count() || casualties.unit_casualties >= player.get_units().len() as i32{
@@ -235,7 +232,7 @@ impl Battle{
 
     /// Determine if treasure is found by a given player
     fn find_treasure(&self, player : &Player, treasure : &Treasure) -> Option<Equipment>{
-        let mut rng = rand::thread_rng();
+        let mut rng = crate::rng::thread_rng();
         let bonus = player.get_general().get_equipment(EquipmentType::Follower).map(|e| e.get_bonus()).unwrap_or_else(|| 0);
         if rng.gen_range(1..9) + bonus >= 5{
             return Some(treasure.battle_find_equipment().clone());
@@ -246,7 +243,7 @@ impl Battle{
     /// Generate random modifiers for battle autoresolving
     fn battle_randoms(&self) -> i32
     {
-        let mut rng = rand::thread_rng();
+        let mut rng = crate::rng::thread_rng();
         // sum of 10x random in range 1-10
         let mut sum = 0;
         for _ in 0..10 {
@@ -257,15 +254,15 @@ impl Battle{
 
 
     /// Generate random battle
-    pub fn generate_random_battle(roster : &Roster, treasure : &Treasure, equipment_ratio : u32, rank_cap: u32, reinforcement_cap: u32, battle_type : Option<BattleType>) -> Self{
+    pub fn generate_random_battle(roster : &Roster, treasure : &Treasure, profile : &GeneratorProfile, battle_type : Option<BattleType>) -> Self{
         // create battle type
-        let b_type= battle_type.unwrap_or_else(|| BattleType::generate_random_battle_type()).get_random_values_for_type();
+        let b_type= battle_type.unwrap_or_else(|| profile.random_battle_type()).get_random_values_for_type();
 
         // create attacker
-        let attacker = Player::generate_random_player(equipment_ratio,rank_cap,roster,reinforcement_cap,treasure);
+        let attacker = Player::generate_random_player(profile,roster,treasure);
 
         // create defender
-        let defender = Player::generate_random_player(equipment_ratio,rank_cap,roster,reinforcement_cap,treasure);
+        let defender = Player::generate_random_player(profile,roster,treasure);
 
         Battle{
             battle_type: b_type,
@@ -282,7 +279,7 @@ impl Battle{
 
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct BattleResults{
     battle_type : BattleType,
     outcome: BattleOutcome,
@@ -314,23 +311,35 @@ impl BattleResults{
     pub fn get_outcome(&self) -> &BattleOutcome{
         &self.outcome
     }
+
+    /// Get treasure received by the attacker
+    pub fn get_attacker_treasure(&self) -> Option<&Equipment>{
+        self.treasure.attacker.as_ref()
+    }
+
+    /// Get treasure received by the defender
+    pub fn get_defender_treasure(&self) -> Option<&Equipment>{
+        self.treasure.defender.as_ref()
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct BattleCasualties{
     attacker : Casualties,
     defender : Casualties,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct Casualties {
+    #[serde(rename = "general_state")]
     state : GeneralState,
     upgrades : i32,
+    #[serde(rename = "soldier_casualties")]
     casualties : i32,
     unit_casualties : i32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 struct TreasureResults{
     attacker : Option<Equipment>,
     defender : Option<Equipment>,
@@ -346,6 +355,9 @@ pub enum BattleType {
 }
 
 impl BattleType {
+    /// Names of all battle types
+    pub const NAMES : [&'static str; 5] = ["Normal", "Siege", "Raid", "Naval", "Monster"];
+
     /// Calculate the autoresolve modifier for the type of battle
     fn get_calculation(&self) -> i32{
         match &self{
@@ -394,39 +406,13 @@ impl BattleType {
             "Raid" => BattleType::Raid { defenses: Default::default() },
             "Naval" => BattleType::Naval { attacker_ships: 0, defender_ships: 0 },
             "Monster" => BattleType::Monster { monster: MonsterType::Minotaur },
-            _ => panic!()
-        }
-    }
-
-    /// Create randomized BattleType
-    fn generate_random_battle_type() -> Self{
-        let mut rng = rand::thread_rng();
-
-        match rng.gen_range(1..=5){
-            1 => BattleType::Normal,
-            2 => {
-                let b = BattleType::Siege {rams:0,catapults:0,siege_towers:0,defenses:TownStats::default()};
-                b.get_random_values_for_type()
-            },
-            3 =>{
-                let b = BattleType::Raid{defenses: TownStats::default()};
-                b.get_random_values_for_type()
-            },
-            4 =>{
-                let b = BattleType::Naval{attacker_ships:0,defender_ships:0};
-                b.get_random_values_for_type()
-            },
-            5 =>{
-                let b = BattleType::Monster{monster:MonsterType::Minotaur};
-                b.get_random_values_for_type()
-            },
-            _ => panic!("Invalid number generated")
+            _ => panic!("Invalid BattleType name {}", name)
         }
     }
 
     /// Fill BattleType with random values
     fn get_random_values_for_type(self) -> Self{
-        let mut rng = rand::thread_rng();
+        let mut rng = crate::rng::thread_rng();
 
         match self{
             BattleType::Normal => BattleType::Normal,
@@ -492,7 +478,7 @@ impl TownStats {
 
     /// Create randomized TownStats
     pub fn get_random_town_stats() -> Self{
-        let mut rng = rand::thread_rng();
+        let mut rng = crate::rng::thread_rng();
         TownStats{
             supplies: rng.gen_range(0..=10),
             defenses: match rng.gen_range(1..=5) {
@@ -516,7 +502,13 @@ pub enum TownDefenses {
     StoneWallAndMoat,
 }
 
-#[derive(Debug,Eq, PartialEq, Copy, Clone)]
+impl TownDefenses{
+    /// All town defenses, from weakest to strongest
+    pub const ALL : [TownDefenses; 5] = [TownDefenses::None, TownDefenses::WoodenWall, TownDefenses::WoodenWallAndMoat,
+        TownDefenses::StoneWall, TownDefenses::StoneWallAndMoat];
+}
+
+#[derive(Debug,Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum BattleOutcome {
     DecisiveVictory = 1,
     HeroicVictory,
@@ -528,8 +520,31 @@ pub enum BattleOutcome {
 }
 
 impl BattleOutcome {
+    /// All outcomes, from best to worst for the attacker
+    pub const ALL : [BattleOutcome; 7] = [BattleOutcome::DecisiveVictory, BattleOutcome::HeroicVictory, BattleOutcome::CloseVictory,
+        BattleOutcome::Draw, BattleOutcome::CloseDefeat, BattleOutcome::ValiantDefeat, BattleOutcome::CrushingDefeat];
+
+    /// Get enum from name
+    pub fn from_name(name : &str) -> Self{
+        match name{
+            "DecisiveVictory" => BattleOutcome::DecisiveVictory,
+            "HeroicVictory" => BattleOutcome::HeroicVictory,
+            "CloseVictory" => BattleOutcome::CloseVictory,
+            "Draw" => BattleOutcome::Draw,
+            "CloseDefeat" => BattleOutcome::CloseDefeat,
+            "ValiantDefeat" => BattleOutcome::ValiantDefeat,
+            "CrushingDefeat" => BattleOutcome::CrushingDefeat,
+            _ => panic!("Invalid BattleOutcome name {}", name)
+        }
+    }
+
+    /// Get if the outcome is a victory for the attacker
+    pub fn is_victory(&self) -> bool{
+        (*self as i32) <= 3
+    }
+
     /// Determine which outcome based on f32 result
-    fn determine_outcome(result : f32) -> BattleOutcome {
+    pub fn determine_outcome(result : f32) -> BattleOutcome {
         //All results are in relation to the attacker.
         //Victory
         if result > 2.0 {
@@ -559,188 +574,470 @@ impl BattleOutcome {
 
 #[derive(Debug, Clone)]
 pub struct BattleData{
-    data : Vec<String>,
     unit_names : Vec<String>,
+    battle_type : BattleType,
+    attacker_random : f32,
+    defender_random : f32,
+    total : f32,
+    outcome : BattleOutcome,
+    attacker : PlayerData,
+    defender : PlayerData,
     got_initial : bool,
     got_calculations : bool,
     got_results : bool,
 }
 
-impl BattleData{
-    /// Create new BattleData. If output_file is None, uses default for each kind of BattleType.
-    pub fn new(roster : &Roster) -> Self{
+/// Data collected for one side of a battle, casualty and treasure fields are left out of
+/// serialized records as they are part of the BattleResults
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerData{
+    general_rank : i32,
+    general_bonus : i32,
+    armor_bonus : i32,
+    weapon_bonus : i32,
+    follower_bonus : i32,
+    banner_bonus : i32,
+    trinket_bonus : i32,
+    adv_combat : bool,
+    begin : ArmyBonuses,
+    end : ArmyBonuses,
+    /// Count of each unit, in the same order as the BattleData unit names
+    #[serde(skip)]
+    unit_counts : Vec<i32>,
+    total_units : i32,
+    reinforcements : i32,
+    #[serde(skip)]
+    upgrades : i32,
+    #[serde(skip)]
+    unit_casualties : i32,
+    #[serde(skip)]
+    soldier_casualties : i32,
+    #[serde(skip)]
+    general_state : GeneralState,
+    #[serde(skip)]
+    treasure_received : bool,
+    faction : Faction,
+}
 
-        BattleData{
-            data : vec![String::new();141],
-            unit_names : roster.get_all_unit_names(),
-            got_initial : false,
-            got_calculations : false,
-            got_results : false,
+/// Autoresolve bonuses and soldiers of a Player at a point in a battle
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ArmyBonuses{
+    unit_bonus : i32,
+    melee_bonus : i32,
+    ranged_bonus : i32,
+    cavalry_bonus : i32,
+    soldiers : i32,
+}
+
+impl ArmyBonuses{
+    /// Get current bonuses of a Player
+    fn from_player(player : &Player) -> Self{
+        ArmyBonuses{
+            unit_bonus: player.get_melee_bonus() + player.get_cavalry_bonus() + player.get_ranged_bonus(),
+            melee_bonus: player.get_melee_bonus(),
+            ranged_bonus: player.get_ranged_bonus(),
+            cavalry_bonus: player.get_cavalry_bonus(),
+            soldiers: player.get_soldier_count(),
         }
     }
 
-    /// Get outcome
-    pub fn get_outcome(&self) -> BattleOutcome{
-        if !self.got_calculations{
-            panic!();
-        }
-        BattleOutcome::determine_outcome(self.data[3].parse().unwrap())
+    /// Get combined autoresolve bonus of all units
+    pub fn get_unit_bonus(&self) -> i32{
+        self.unit_bonus
     }
 
-    /// Get battle type
-    pub fn get_battle_type(&self) -> BattleType{
-        if !self.got_initial{
-            panic!();
+    /// Get number of soldiers
+    pub fn get_soldiers(&self) -> i32{
+        self.soldiers
+    }
+}
+
+impl PlayerData{
+    fn new(unit_count : usize) -> Self{
+        PlayerData{
+            general_rank: 0,
+            general_bonus: 0,
+            armor_bonus: 0,
+            weapon_bonus: 0,
+            follower_bonus: 0,
+            banner_bonus: 0,
+            trinket_bonus: 0,
+            adv_combat: false,
+            begin: ArmyBonuses::default(),
+            end: ArmyBonuses::default(),
+            unit_counts: vec![0; unit_count],
+            total_units: 0,
+            reinforcements: 0,
+            upgrades: 0,
+            unit_casualties: 0,
+            soldier_casualties: 0,
+            general_state: GeneralState::Unharmed,
+            treasure_received: false,
+            faction: Faction::Rebel,
         }
-        BattleType::from_name(&*self.data[0])
     }
 
-    /// Save initial battle data before running autoresolve
-    fn collect_initial_battle_data(&mut self, battle : &Battle){
+    /// Save Player data before running autoresolve
+    fn collect_initial(&mut self, player : &Player, unit_names : &[String]){
+        let gen = player.get_general();
+        let equipment_bonus = |t : EquipmentType| gen.get_equipment(t).map(|e| e.get_bonus()).unwrap_or(0);
+        self.general_rank = gen.get_rank();
+        self.general_bonus = gen.get_bonus();
+        self.armor_bonus = equipment_bonus(EquipmentType::Armor);
+        self.weapon_bonus = equipment_bonus(EquipmentType::Weapon);
+        self.follower_bonus = equipment_bonus(EquipmentType::Follower);
+        self.banner_bonus = equipment_bonus(EquipmentType::Banner);
+        self.trinket_bonus = equipment_bonus(EquipmentType::Trinket);
+        self.adv_combat = player.has_advanced_combat_deck();
+        self.begin = ArmyBonuses::from_player(player);
+        self.unit_counts = unit_names.iter().map(|n| player.get_unit_count_by_name(n)).collect();
+        self.total_units = player.get_units().len() as i32;
+        self.reinforcements = player.get_reinforcements();
+    }
 
+    /// Save Player data after running autoresolve
+    fn collect_results(&mut self, player : &Player, casualties : &Casualties, treasure : &Option<Equipment>){
+        self.end = ArmyBonuses::from_player(player);
+        self.upgrades = casualties.upgrades;
+        self.unit_casualties = casualties.unit_casualties;
+        self.soldier_casualties = casualties.casualties;
+        self.general_state = casualties.state.clone();
+        self.treasure_received = treasure.is_some();
+        self.faction = *player.get_faction();
+    }
 
-        // Battle type
-        self.data[0] = battle.battle_type.get_name();
-        // Supplies
-        self.data[6] = match battle.battle_type {
-            BattleType::Siege { defenses , ..} => defenses.supplies.to_string(),
-            BattleType::Raid { defenses,.. } => defenses.supplies.to_string(),
-            _ => String::from("0"),
-        };
+    /// Get soldier casualties
+    pub fn get_soldier_casualties(&self) -> i32{
+        self.soldier_casualties
+    }
 
-        // Attacker fields
-        self.data[7] = battle.attacker.get_general().get_rank().to_string();
-        self.data[8] = battle.attacker.get_general().get_bonus().to_string();
-        self.data[9] = battle.attacker.get_general().get_equipment(EquipmentType::Armor).map(|e| e.get_bonus().to_string()).unwrap_or_else(|| String::from("0"));
-        self.data[10] = battle.attacker.get_general().get_equipment(EquipmentType::Weapon).map(|e| e.get_bonus().to_string()).unwrap_or_else(|| String::from("0"));
-        self.data[11] = battle.attacker.get_general().get_equipment(EquipmentType::Follower).map(|e| e.get_bonus().to_string()).unwrap_or_else(|| String::from("0"));
-        self.data[12] = battle.attacker.get_general().get_equipment(EquipmentType::Banner).map(|e| e.get_bonus().to_string()).unwrap_or_else(|| String::from("0"));
-        self.data[13] = battle.attacker.get_general().get_equipment(EquipmentType::Trinket).map(|e| e.get_bonus().to_string()).unwrap_or_else(|| String::from("0"));
-        self.data[14] = battle.attacker.has_advanced_combat_deck().to_string();
-        self.data[15] = (battle.attacker.get_melee_bonus() + battle.attacker.get_cavalry_bonus() + battle.attacker.get_ranged_bonus()).to_string();
-        self.data[16] = battle.attacker.get_melee_bonus().to_string();
-        self.data[17] = battle.attacker.get_ranged_bonus().to_string();
-        self.data[18] = battle.attacker.get_cavalry_bonus().to_string();
-        self.data[19] = battle.attacker.get_soldier_count().to_string();
-
-        // Attacker units
-        // This works because the units names are in the same order as the output file
-        for (i,j) in self.unit_names.iter().enumerate(){
-            self.data[25+i] = battle.attacker.get_unit_count_by_name(j).to_string();
+    /// Get unit casualties
+    pub fn get_unit_casualties(&self) -> i32{
+        self.unit_casualties
+    }
+
+    /// Get upgrades received
+    pub fn get_upgrades(&self) -> i32{
+        self.upgrades
+    }
+
+    /// Get state of the General after the battle
+    pub fn get_general_state(&self) -> &GeneralState{
+        &self.general_state
+    }
+
+    /// Get if treasure was received
+    pub fn get_treasure_received(&self) -> bool{
+        self.treasure_received
+    }
+
+    /// Get rank of the General
+    pub fn get_general_rank(&self) -> i32{
+        self.general_rank
+    }
+
+    /// Get autoresolve bonus of the General
+    pub fn get_general_bonus(&self) -> i32{
+        self.general_bonus
+    }
+
+    /// Get autoresolve bonus of the General's equipment of a type
+    pub fn get_equipment_bonus(&self, equipment_type : EquipmentType) -> i32{
+        match equipment_type{
+            EquipmentType::Armor => self.armor_bonus,
+            EquipmentType::Weapon => self.weapon_bonus,
+            EquipmentType::Follower => self.follower_bonus,
+            EquipmentType::Banner => self.banner_bonus,
+            EquipmentType::Trinket => self.trinket_bonus,
         }
+    }
 
-        self.data[63] = battle.attacker.get_units().len().to_string();
-        self.data[64] = battle.attacker.get_reinforcements().to_string();
+    /// Get if the Player has the advanced combat deck
+    pub fn get_adv_combat(&self) -> bool{
+        self.adv_combat
+    }
 
-        // Defender fields
-        self.data[75] = battle.defender.get_general().get_rank().to_string();
-        self.data[76] = battle.defender.get_general().get_bonus().to_string();
-        self.data[77] = battle.defender.get_general().get_equipment(EquipmentType::Armor).map(|e| e.get_bonus().to_string()).unwrap_or_else(|| String::from("0"));
-        self.data[78] = battle.defender.get_general().get_equipment(EquipmentType::Weapon).map(|e| e.get_bonus().to_string()).unwrap_or_else(|| String::from("0"));
-        self.data[79] = battle.defender.get_general().get_equipment(EquipmentType::Follower).map(|e| e.get_bonus().to_string()).unwrap_or_else(|| String::from("0"));
-        self.data[80] = battle.defender.get_general().get_equipment(EquipmentType::Banner).map(|e| e.get_bonus().to_string()).unwrap_or_else(|| String::from("0"));
-        self.data[81] = battle.defender.get_general().get_equipment(EquipmentType::Trinket).map(|e| e.get_bonus().to_string()).unwrap_or_else(|| String::from("0"));
-        self.data[82] = battle.defender.has_advanced_combat_deck().to_string();
-        self.data[83] = (battle.defender.get_melee_bonus() + battle.defender.get_cavalry_bonus() + battle.defender.get_ranged_bonus()).to_string();
-        self.data[84] = battle.defender.get_melee_bonus().to_string();
-        self.data[85] = battle.defender.get_ranged_bonus().to_string();
-        self.data[86] = battle.defender.get_cavalry_bonus().to_string();
-        self.data[87] = battle.defender.get_soldier_count().to_string();
-
-        // Defender units
-        // This works because the units names are in the same order as the output file
-        for (i,j) in self.unit_names.iter().enumerate(){
-            self.data[25+i] = battle.defender.get_unit_count_by_name(j).to_string();
+    /// Get bonuses before the battle
+    pub fn get_begin(&self) -> &ArmyBonuses{
+        &self.begin
+    }
+
+    /// Get bonuses after the battle
+    pub fn get_end(&self) -> &ArmyBonuses{
+        &self.end
+    }
+
+    /// Get number of units
+    pub fn get_total_units(&self) -> i32{
+        self.total_units
+    }
+
+    /// Get number of reinforcements
+    pub fn get_reinforcements(&self) -> i32{
+        self.reinforcements
+    }
+
+    /// Get faction
+    pub fn get_faction(&self) -> Faction{
+        self.faction
+    }
+
+    /// Get count of each unit by name, only including units in the army
+    fn unit_map<'a>(&self, unit_names : &'a [String]) -> BTreeMap<&'a str, i32>{
+        unit_names.iter().zip(self.unit_counts.iter())
+            .filter(|(_,c)| **c > 0)
+            .map(|(n,c)| (n.as_str(), *c))
+            .collect()
+    }
+
+    /// Get column names and values for the Player, names are prefixed with prefix
+    fn fields(&self, prefix : &str, unit_names : &[String]) -> Vec<(String, String)>{
+        let mut f = vec![
+            ("GenRank", self.general_rank.to_string()),
+            ("GenARBonus", self.general_bonus.to_string()),
+            ("GenArmorBonus", self.armor_bonus.to_string()),
+            ("GenWeaponBonus", self.weapon_bonus.to_string()),
+            ("GenFollowerBonus", self.follower_bonus.to_string()),
+            ("GenBannerBonus", self.banner_bonus.to_string()),
+            ("GenTrinketBonus", self.trinket_bonus.to_string()),
+            ("AdvancedCombatDeck", self.adv_combat.to_string()),
+            ("BeginARUnitBonus", self.begin.unit_bonus.to_string()),
+            ("BeginMeleeBonus", self.begin.melee_bonus.to_string()),
+            ("BeginRangedBonus", self.begin.ranged_bonus.to_string()),
+            ("BeginCavalryBonus", self.begin.cavalry_bonus.to_string()),
+            ("BeginSoldiers", self.begin.soldiers.to_string()),
+            ("EndARUnitBonus", self.end.unit_bonus.to_string()),
+            ("EndMeleeBonus", self.end.melee_bonus.to_string()),
+            ("EndRangedBonus", self.end.ranged_bonus.to_string()),
+            ("EndCavalryBonus", self.end.cavalry_bonus.to_string()),
+            ("EndSoldiers", self.end.soldiers.to_string()),
+        ].into_iter().map(|(n,v)| (BattleData::legacy_column(format!("{}{}", prefix, n)), v)).collect::<Vec<(String, String)>>();
+
+        // one column for each unit in the roster
+        f.extend(unit_names.iter().zip(self.unit_counts.iter())
+            .map(|(n,c)| (BattleData::unit_column(prefix, n), c.to_string())));
+
+        f.extend(vec![
+            ("TotUnits", self.total_units.to_string()),
+            ("TotReinforcements", self.reinforcements.to_string()),
+            ("Upgrades", self.upgrades.to_string()),
+            ("UnitCas", self.unit_casualties.to_string()),
+            ("SoldierCas", self.soldier_casualties.to_string()),
+            ("EndGenState", format!("{:?}", self.general_state)),
+            ("TreasureReceived", self.treasure_received.to_string()),
+            ("Faction", format!("{:?}", self.faction)),
+        ].into_iter().map(|(n,v)| (format!("{}{}", prefix, n), v)));
+        f
+    }
+}
+
+/// Columns of captures saved before the header was generated from the roster, as (generated name, saved name).
+/// The saved names are kept so the base roster's header matches existing capture files
+const LEGACY_COLUMNS : [(&str, &str); 27] = [
+    ("ARebelRangersOfTheForests", "ARebelRangerOfForest"),
+    ("ARebelAxemenOfTheVale", "ARebelAxemenOfVale"),
+    ("ARebelRidersOfTheWood", "ARebelRidersOfWood"),
+    ("AGuardsOfBeladimir", "AGuardOfBeladimir"),
+    ("ARidersOfTheWood", "ARidersOfWood"),
+    ("AAxemenOfTheVale", "AAxemenOfVale"),
+    ("ACrossbowmenOfTheSouth", "ACrossbowmenOfSouth"),
+    ("AShieldmaidensOfTheNorth", "AShieldmaidensOfNorth"),
+    ("AAxemenOfTheWastes", "AAxemenOfWastes"),
+    ("ARangersOfTheForests", "ARangersOfForest"),
+    ("AHuntersOfTheNorth", "AHuntersOfNorth"),
+    ("DGenARBonus", "DGenTotARBonus"),
+    ("DGenTrinketBonus", "DGenTinketBonus"),
+    ("DRebelRangersOfTheForests", "DRebelRangerOfForest"),
+    ("DRebelAxemenOfTheVale", "DRebelAxemenOfVale"),
+    ("DRebelLightWoodmen", "DLightWoodmen"),
+    ("DRebelRidersOfTheWood", "DRebelRidersOfWood"),
+    ("DGuardsOfBeladimir", "DGuardOfBeladimir"),
+    ("DCoastalGuards", "DCoastalGuard"),
+    ("DRidersOfTheWood", "DRidersOfWood"),
+    ("DLerastirSpears", "DLerastirSpearmen"),
+    ("DAxemenOfTheVale", "DAxemenOfVale"),
+    ("DCrossbowmenOfTheSouth", "DCrossbowmenOfSouth"),
+    ("DShieldmaidensOfTheNorth", "DShieldmaidensOfNorth"),
+    ("DAxemenOfTheWastes", "DAxemenOfWastes"),
+    ("DRangersOfTheForests", "DRangerOfForest"),
+    ("DHuntersOfTheNorth", "DHuntersOfNorth"),
+];
+
+impl BattleData{
+    /// Create new BattleData with a column for every unit in the roster
+    pub fn new(roster : &Roster) -> Self{
+        // only one column per unit name as units are counted by name
+        let mut unit_names : Vec<String> = vec![];
+        for n in roster.get_all_unit_names(){
+            if !unit_names.contains(&n){
+                unit_names.push(n);
+            }
         }
+        let unit_count = unit_names.len();
 
-        self.data[131] = battle.defender.get_units().len().to_string();
-        self.data[132] = battle.defender.get_reinforcements().to_string();
+        BattleData{
+            unit_names,
+            battle_type : BattleType::Normal,
+            attacker_random : 0.0,
+            defender_random : 0.0,
+            total : 0.0,
+            outcome : BattleOutcome::Draw,
+            attacker : PlayerData::new(unit_count),
+            defender : PlayerData::new(unit_count),
+            got_initial : false,
+            got_calculations : false,
+            got_results : false,
+        }
+    }
 
+    /// Get CSV header line for data collected using a roster
+    pub fn csv_header(roster : &Roster) -> String{
+        BattleData::new(roster).fields().into_iter().map(|(n,_)| n).collect::<Vec<String>>().join(",")
+    }
 
-        self.got_initial = true;
+    /// Get the column of a unit's count for a side prefix, e.g. "A" and "Riders of the Wood" to "ARidersOfWood"
+    fn unit_column(prefix : &str, unit_name : &str) -> String{
+        Self::legacy_column(format!("{}{}", prefix, Self::column_name(unit_name)))
     }
 
-    /// Save battle data after running autoresolve
-    fn collect_battle_results(&mut self, results : &BattleResults, battle : &Battle){
-        // Outcome
-        self.data[4] = format!{"{:?}",results.outcome};
-        // Attacker won (bool)
-        self.data[5] = if results.outcome as i32 > 3 {false.to_string()} else {true.to_string()};
+    /// Get the name a column is saved with, its name in captures saved before the header was generated if it differs
+    fn legacy_column(column : String) -> String{
+        match LEGACY_COLUMNS.iter().find(|(generated, _)| *generated == column){
+            Some((_, saved)) => String::from(*saved),
+            None => column,
+        }
+    }
+
+    /// Convert a unit name to a column name, e.g. "Riders of the Wood" to "RidersOfTheWood"
+    fn column_name(unit_name : &str) -> String{
+        unit_name.split_whitespace()
+            .map(|w| {
+                let mut c = w.chars().filter(|c| c.is_alphanumeric());
+                match c.next(){
+                    Some(first) => first.to_uppercase().chain(c).collect::<String>(),
+                    None => String::new(),
+                }
+            })
+            .collect()
+    }
+
+    /// Get column names and values in output order
+    fn fields(&self) -> Vec<(String, String)>{
+        let mut f : Vec<(String, String)> = vec![
+            (String::from("BattleType"), self.battle_type.get_name()),
+            (String::from("ARandomTot"), self.attacker_random.to_string()),
+            (String::from("DRandomTot"), self.defender_random.to_string()),
+            (String::from("EndingTot"), self.total.to_string()),
+            (String::from("Outcome"), format!("{:?}", self.outcome)),
+            (String::from("AWon"), self.outcome.is_victory().to_string()),
+            (String::from("Supplies"), match self.battle_type {
+                BattleType::Siege { defenses , ..} => defenses.supplies.to_string(),
+                BattleType::Raid { defenses,.. } => defenses.supplies.to_string(),
+                _ => String::from("0"),
+            }),
+        ];
 
         // Attacker fields
-        self.data[20] = (battle.attacker.get_melee_bonus() + battle.attacker.get_cavalry_bonus() + battle.attacker.get_ranged_bonus()).to_string();
-        self.data[21] = battle.attacker.get_melee_bonus().to_string();
-        self.data[22] = battle.attacker.get_ranged_bonus().to_string();
-        self.data[23] = battle.attacker.get_cavalry_bonus().to_string();
-        self.data[24] = battle.attacker.get_soldier_count().to_string();
-        self.data[65] = results.casualties.attacker.upgrades.to_string();
-        self.data[66] = results.casualties.attacker.unit_casualties.to_string();
-        self.data[67] = results.casualties.attacker.casualties.to_string();
-        self.data[68] = format!{"{:?}",results.casualties.attacker.state};
-        self.data[69] = match results.treasure.attacker {
-            None => false.to_string(),
-            Some(_) => true.to_string(),
-        };
-        self.data[70] = format!("{:?}", battle.attacker.get_faction());
-        self.data[71] = match battle.battle_type{
+        f.extend(self.attacker.fields("A", &self.unit_names));
+        f.push((String::from("AShips"), match self.battle_type{
             BattleType::Naval {attacker_ships,..} => attacker_ships.to_string(),
             _ => String::from("0"),
+        }));
+        let (rams, siege_towers, catapults) = match self.battle_type{
+            BattleType::Siege {rams, siege_towers, catapults, ..} => (rams, siege_towers, catapults),
+            _ => (0, 0, 0),
         };
-        self.data[72] = match battle.battle_type{
-            BattleType::Siege {rams,..} => rams.to_string(),
-            _ => String::from("0"),
-        };
-        self.data[73] = match battle.battle_type{
-            BattleType::Siege {siege_towers,..} => siege_towers.to_string(),
-            _ => String::from("0"),
-        };
-        self.data[74] = match battle.battle_type{
-            BattleType::Siege {catapults,..} => catapults.to_string(),
-            _ => String::from("0"),
-        };
+        f.push((String::from("ARams"), rams.to_string()));
+        f.push((String::from("ASiegeTowers"), siege_towers.to_string()));
+        f.push((String::from("ACatapults"), catapults.to_string()));
 
         // Defender fields
-        self.data[88] = (battle.defender.get_melee_bonus() + battle.defender.get_cavalry_bonus() + battle.defender.get_ranged_bonus()).to_string();
-        self.data[89] = battle.defender.get_melee_bonus().to_string();
-        self.data[90] = battle.defender.get_ranged_bonus().to_string();
-        self.data[91] = battle.defender.get_cavalry_bonus().to_string();
-        self.data[92] = battle.defender.get_soldier_count().to_string();
-        self.data[133] = results.casualties.defender.upgrades.to_string();
-        self.data[134] = results.casualties.defender.unit_casualties.to_string();
-        self.data[135] = results.casualties.defender.casualties.to_string();
-        self.data[136] = format!{"{:?}",results.casualties.defender.state};
-        self.data[137] = match results.treasure.defender {
-            None => false.to_string(),
-            Some(_) => true.to_string(),
-        };
-        self.data[138] = format!("{:?}", battle.defender.get_faction());
-        self.data[139] = match battle.battle_type{
+        f.extend(self.defender.fields("D", &self.unit_names));
+        f.push((String::from("DShips"), match self.battle_type{
             BattleType::Naval {defender_ships,..} => defender_ships.to_string(),
             _ => String::from("0"),
-        };
-        self.data[140] = match battle.battle_type{
+        }));
+        f.push((String::from("DTownLevel"), match self.battle_type{
             BattleType::Siege {defenses,..} => format!("{:?}",defenses.defenses),
             BattleType::Raid {defenses,..} => format!("{:?}",defenses.defenses),
             _ => String::from("0"),
-        };
+        }));
+        f
+    }
 
-        self.got_results = true;
+    /// Get outcome
+    pub fn get_outcome(&self) -> BattleOutcome{
+        if !self.got_calculations{
+            panic!("Battle calculations not yet collected");
+        }
+        self.outcome
+    }
+
+    /// Get ending total
+    pub fn get_total(&self) -> f32{
+        if !self.got_calculations{
+            panic!("Battle calculations not yet collected");
+        }
+        self.total
+    }
+
+    /// Get attacker's and defender's battle randoms
+    pub fn get_randoms(&self) -> (f32, f32){
+        if !self.got_calculations{
+            panic!("Battle calculations not yet collected");
+        }
+        (self.attacker_random, self.defender_random)
+    }
+
+    /// Get count of each unit of a Player by name, only including units in the army
+    pub fn get_unit_counts(&self, player : &PlayerData) -> BTreeMap<&str, i32>{
+        player.unit_map(&self.unit_names)
+    }
+
+    /// Get attacker data
+    pub fn get_attacker(&self) -> &PlayerData{
+        &self.attacker
+    }
+
+    /// Get defender data
+    pub fn get_defender(&self) -> &PlayerData{
+        &self.defender
+    }
+
+    /// Get battle type
+    pub fn get_battle_type(&self) -> BattleType{
+        if !self.got_initial{
+            panic!("Initial battle data not yet collected");
+        }
+        self.battle_type
+    }
+
+    /// Save initial battle data before running autoresolve
+    fn collect_initial_battle_data(&mut self, battle : &Battle){
+        self.battle_type = battle.battle_type;
+        self.attacker.collect_initial(&battle.attacker, &self.unit_names);
+        self.defender.collect_initial(&battle.defender, &self.unit_names);
+        self.got_initial = true;
     }
 
+    /// Save battle data after running autoresolve
+    fn collect_battle_results(&mut self, results : &BattleResults, battle : &Battle){
+        self.attacker.collect_results(&battle.attacker, &results.casualties.attacker, &results.treasure.attacker);
+        self.defender.collect_results(&battle.defender, &results.casualties.defender, &results.treasure.defender);
+        self.got_results = true;
+    }
 
     /// Save calculations made while calculating a battle's outcome
     fn collect_battle_calculations(&mut self, attacker : f32, defender : f32, total: f32){
-        // Attacker random total
-        self.data[1] = attacker.to_string();
-        // Defender random total
-        self.data[2] = defender.to_string();
-        // Ending total
-        self.data[3] = total.to_string();
+        self.attacker_random = attacker;
+        self.defender_random = defender;
+        self.total = total;
+        self.outcome = BattleOutcome::determine_outcome(total);
         self.got_calculations = true;
     }
 
     /// Save results to disk, return if operation was successful
-    pub fn save_to_file(&self, output_file : &String) -> bool{
+    pub fn save_to_file(&self, output_file : &str) -> bool{
         if !self.got_calculations || !self.got_results || !self.got_initial{
             println!("Unable to write because not all data yet set\n\t\
             Initial:{}\n\tRandoms:{}\n\tResults:{}"
@@ -748,67 +1045,218 @@ impl BattleData{
             return false;
         }
 
-        let file_path = Path::new(&output_file);
-        // If output file doesn't exist, create by copying template
-        if !Path::exists(file_path){
-            println!("Creating output file at {} for battle data",output_file);
-            fs::copy("./ResourceFiles/data_capture_template.txt", &output_file).unwrap();
-        }
-
-        // Write lines to file
-        let mut f = OpenOptions::new().write(true).append(true).open(file_path).unwrap();
-        // Write each data entry, write the first before to ensure proper comma alignment
-        write!(f,"{}",self.data[0]).unwrap();
-        for line in self.data.iter().skip(1){
-            // Write each cell, separating with commas
-            write!(f,",{}",line).unwrap();
-        }
-        write!(f,"\n").unwrap();
+        // Lock file while writing line, creating it with header if it doesn't exist
+        let (mut f, _) = open_locked(output_file, Some(&self.header()));
+        writeln!(f, "{}", self.format_output()).unwrap();
         true
     }
 
-    /// Format output line
-    pub fn format_output(&self) -> String{
+    /// Get CSV header line matching format_output
+    pub fn header(&self) -> String{
+        self.fields().into_iter().map(|(n,_)| n).collect::<Vec<String>>().join(",")
+    }
+
+    /// Panic if not all data has been collected
+    fn check_complete(&self){
         if !self.got_calculations || !self.got_results || !self.got_initial{
             println!("Unable to write because not all data yet set\n\t\
             Initial:{}\n\tRandoms:{}\n\tResults:{}"
                      ,self.got_initial,self.got_calculations,self.got_results);
             panic!();
         }
-        let mut s = String::new();
+    }
 
-        // Write each data entry, write the first before to ensure proper comma alignment
-        s.push_str(format!("{}",self.data[0]).as_str());
-        for line in self.data.iter().skip(1){
-            // Write each cell, separating with commas
-            s.push_str(format!(",{}",line).as_str());
+    /// Format output line
+    pub fn format_output(&self) -> String{
+        self.check_complete();
+        self.fields().into_iter().map(|(_,v)| v).collect::<Vec<String>>().join(",")
+    }
+
+    /// Create full record of the battle from the data and the battle's results
+    pub fn to_record<'a>(&'a self, results : &'a BattleResults) -> BattleRecord<'a>{
+        self.check_complete();
+        BattleRecord{
+            battle_type: &self.battle_type,
+            outcome: &self.outcome,
+            attacker_random: self.attacker_random,
+            defender_random: self.defender_random,
+            total: self.total,
+            attacker: PlayerRecord{ data: &self.attacker, units: self.attacker.unit_map(&self.unit_names) },
+            defender: PlayerRecord{ data: &self.defender, units: self.defender.unit_map(&self.unit_names) },
+            casualties: &results.casualties,
+            treasure: &results.treasure,
         }
-        s
     }
 
+    /// Format output line as JSON record
+    pub fn format_json(&self, results : &BattleResults) -> String{
+        serde_json::to_string(&self.to_record(results)).unwrap()
+    }
+}
+
+/// Full record of a battle, serialized as one line of JSON Lines output
+#[derive(Debug, Serialize)]
+pub struct BattleRecord<'a>{
+    battle_type : &'a BattleType,
+    outcome : &'a BattleOutcome,
+    attacker_random : f32,
+    defender_random : f32,
+    total : f32,
+    attacker : PlayerRecord<'a>,
+    defender : PlayerRecord<'a>,
+    casualties : &'a BattleCasualties,
+    treasure : &'a TreasureResults,
+}
 
+/// PlayerData with unit counts by name for serializing
+#[derive(Debug, Serialize)]
+pub struct PlayerRecord<'a>{
+    #[serde(flatten)]
+    data : &'a PlayerData,
+    units : BTreeMap<&'a str, i32>,
 }
 
 /// Holds Battle struct in a format for serializing/deserializing
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BattleJSONObject{
-    battle_type : BattleType,
-    attacker : PlayerJSONObject,
-    defender : PlayerJSONObject
+    battle_type : BattleTypeJSONObject,
+    attacker : Randomizable<PlayerJSONObject>,
+    defender : Randomizable<PlayerJSONObject>,
 }
 
 impl BattleJSONObject{
-    /// Produce Battle object from self
-    pub fn produce_battle(self, roster : &Roster, treasure : &Treasure) -> Battle{
+    /// Create BattleJSONObject from its battle type and players
+    pub fn new(battle_type : BattleTypeJSONObject, attacker : Randomizable<PlayerJSONObject>, defender : Randomizable<PlayerJSONObject>) -> Self{
+        BattleJSONObject{ battle_type, attacker, defender }
+    }
+
+    /// Produce Battle object from self, randomized fields are generated using profile
+    pub fn produce_battle(&self, roster : &Roster, treasure : &Treasure, profile : &GeneratorProfile) -> Battle{
+        let produce_player = |p : &Randomizable<PlayerJSONObject>| match p{
+            Randomizable::Random(_) => Player::generate_random_player(profile, roster, treasure),
+            Randomizable::Fixed(p) => p.produce_player(roster, treasure, profile),
+        };
         Battle{
-            battle_type: self.battle_type,
-            attacker: self.attacker.produce_player(roster,treasure),
-            defender: self.defender.produce_player(roster,treasure),
+            battle_type: self.battle_type.produce_battle_type(profile),
+            attacker: produce_player(&self.attacker),
+            defender: produce_player(&self.defender),
         }
     }
+
+    /// Get BattleType, None if the type is randomized. Randomized parameters use default values.
+    pub fn get_battle_type(&self) -> Option<BattleType>{
+        self.battle_type.get_battle_type()
+    }
+
+    /// Get if any field is randomized, in which case a new Battle should be produced for every run
+    pub fn has_random(&self) -> bool{
+        let player_random = |p : &Randomizable<PlayerJSONObject>| p.fixed().map(|p| p.has_random()).unwrap_or(true);
+        self.battle_type.has_random() || player_random(&self.attacker) || player_random(&self.defender)
+    }
+    /// Get attacker and defender, None if the whole player is randomized
+    pub fn get_players(&self) -> [Option<&PlayerJSONObject>; 2]{
+        [self.attacker.fixed(), self.defender.fixed()]
+    }
+
+    /// Create fixed BattleJSONObject from a Battle, should be used before the Battle is autoresolved
+    pub fn from_battle(battle : &Battle) -> Self{
+        BattleJSONObject{
+            battle_type: BattleTypeJSONObject::Fixed(battle.battle_type),
+            attacker: Randomizable::Fixed(PlayerJSONObject::from_player(&battle.attacker)),
+            defender: Randomizable::Fixed(PlayerJSONObject::from_player(&battle.defender)),
+        }
+    }
+
+    /// Parse battle JSON read from file_path, with army references of the attacker and defender replaced by the armies
+    pub fn parse_value(content : &str, file_path : &str) -> Result<serde_json::Value, String>{
+        let mut j = serde_json::from_str(content).map_err(|e| e.to_string())?;
+        army::expand_battle(&mut j, army::base_dir(file_path).as_deref())?;
+        Ok(j)
+    }
+
+    /// Read battle JSON file, with army references replaced by the armies
+    pub fn read_value(file_path : &str) -> serde_json::Value{
+        Self::parse_value(&resources::read_to_string(file_path), file_path)
+            .unwrap_or_else(|e| panic!("Unable to parse battle {}: {}", file_path, e))
+    }
+
     /// Read JSON file and convert to self
     pub fn from_json(file_path : &str) -> Self{
-        serde_json::from_str(&*fs::read_to_string(file_path).unwrap()).unwrap()
+        Self::from_value(Self::read_value(file_path))
+            .unwrap_or_else(|e| panic!("Unable to parse battle {}: {}", file_path, e))
+    }
+
+    /// Convert battle JSON to self, errors name the JSON path of the first problem found
+    pub fn from_value(value : serde_json::Value) -> Result<Self, String>{
+        serde_json::from_value(value.clone()).map_err(|e| match schema::validate(&value, &schema::battle_schema()).first(){
+            Some(p) => p.problem_output(),
+            None => e.to_string(),
+        })
+    }
+
+    /// Write self to JSON file
+    pub fn to_json(&self, file_path : &str){
+        fs::write(file_path, serde_json::to_string_pretty(self).unwrap())
+            .unwrap_or_else(|e| panic!("Unable to write {}: {}", file_path, e));
+    }
+}
+
+/// Holds BattleType in a format for serializing/deserializing.
+/// Either the whole type ("Random") or only its parameters ({"Random": "Siege"}) can be randomized.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum BattleTypeJSONObject{
+    Random(RandomMarker),
+    RandomParameters(RandomBattleTypeParameters),
+    Fixed(BattleType),
+}
+
+/// BattleType name whose parameters are randomized
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RandomBattleTypeParameters{
+    Random(BattleTypeName),
+}
+
+/// Name of a BattleType, checked when battle JSON is read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum BattleTypeName{
+    Normal,
+    Siege,
+    Raid,
+    Naval,
+    Monster,
+}
+
+impl BattleTypeName{
+    /// Get BattleType of the name using default values
+    pub fn battle_type(&self) -> BattleType{
+        BattleType::from_name(&format!("{:?}", self))
+    }
+}
+
+impl BattleTypeJSONObject{
+    /// Produce BattleType from self, randomized values are generated using profile
+    pub fn produce_battle_type(&self, profile : &GeneratorProfile) -> BattleType{
+        match self{
+            BattleTypeJSONObject::Random(_) => profile.random_battle_type().get_random_values_for_type(),
+            BattleTypeJSONObject::RandomParameters(RandomBattleTypeParameters::Random(name)) =>
+                name.battle_type().get_random_values_for_type(),
+            BattleTypeJSONObject::Fixed(b) => *b,
+        }
+    }
+
+    /// Get BattleType, None if the type is randomized. Randomized parameters use default values.
+    pub fn get_battle_type(&self) -> Option<BattleType>{
+        match self{
+            BattleTypeJSONObject::Random(_) => None,
+            BattleTypeJSONObject::RandomParameters(RandomBattleTypeParameters::Random(name)) => Some(name.battle_type()),
+            BattleTypeJSONObject::Fixed(b) => Some(*b),
+        }
+    }
+
+    /// Get if the type or its parameters are randomized
+    pub fn has_random(&self) -> bool{
+        !matches!(self, BattleTypeJSONObject::Fixed(_))
     }
 }
 
@@ -921,7 +1369,8 @@ mod battle_tests{
     use crate::player::Player;
     use crate::unit::Unit;
     use crate::general::{General, GeneralState};
-    use crate::battle::{Casualties, Battle};
+    use crate::battle::{Casualties, Battle, BattleType};
+    use crate::monster::MonsterType;
 
     // assign_casualties
     #[test]
@@ -973,6 +1422,22 @@ mod battle_tests{
         assert_eq!(0,p.get_soldier_count());
     }
 
+    #[test]
+    fn test_deterministic_total(){
+        let melee = Unit::new("rebel", String::new(), "melee", 2, 5,0);
+        let cavalry = Unit::new("rebel", String::new(), "cavalry", 3, 5,0);
+        let attacker = Player::new(vec![melee.clone(), melee], General::default());
+        let defender = Player::new(vec![cavalry], General::default());
+
+        // 4 - 3 + 1.5 * ((0 - 0) + (4 - 3) + (0 - 0))
+        let b = Battle::new(attacker.clone(), defender.clone(), BattleType::Normal);
+        assert_eq!(2.5, b.get_deterministic_total());
+
+        // monster battles ignore the defender
+        let b = Battle::new(attacker, defender, BattleType::Monster { monster: MonsterType::Minotaur });
+        assert_eq!(-16.0, b.get_deterministic_total());
+    }
+
     #[test]
     fn test_assign_casualties_one_less_unit_casualty(){
         let u = Unit::new("rebel", String::new(), "melee", 0, 5,0);
@@ -994,22 +1459,23 @@ mod battle_tests{
 
 #[cfg(test)]
 mod battle_data_tests{
-    use crate::battle::BattleData;
+    use crate::battle::{Battle, BattleData, BattleType, TownStats, TownDefenses};
+    use crate::roster::Roster;
+    use crate::treasure::Treasure;
+    use crate::player::Player;
+    use crate::general::General;
+    use crate::testing::TestDir;
     use std::path::Path;
     use std::fs;
 
     #[test]
     fn test_write_to_file(){
-        let mut b = BattleData{
-            data: vec![String::new()],
-            unit_names: vec![],
-            got_initial: false,
-            got_calculations: false,
-            got_results: false
-        };
-
-        let output_location = "./DataCapture/test.csv".to_string();
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let mut b = BattleData::new(&r);
 
+        let dir = TestDir::new("battle_data");
+        let output_location = dir.file("test.csv");
 
         assert_eq!(false, b.save_to_file(&output_location));
         b.got_results = true;
@@ -1020,27 +1486,96 @@ mod battle_data_tests{
         assert_eq!(false, b.save_to_file(&output_location));
         b.got_initial = true;
 
-        assert_eq!(false,Path::exists(Path::new("./DataCapture/test.csv")));
+        assert_eq!(false,Path::exists(Path::new(&output_location)));
+        let mut battle = Battle::generate_random_battle(&r, &t, &Default::default(), None);
+        let mut b = BattleData::new(&r);
+        battle.autoresolve(&t, &mut b);
         assert_eq!(true, b.save_to_file(&output_location));
-        assert_eq!(true,Path::exists(Path::new("./DataCapture/test.csv")));
+        assert_eq!(true,Path::exists(Path::new(&output_location)));
 
-        let t : String = fs::read_to_string("./ResourceFiles/data_capture_template.txt").unwrap().trim().parse().unwrap();
-        let f : String = fs::read_to_string(&output_location).unwrap().trim().parse().unwrap();
-        assert_eq!(t,f);
-
-        b.data = vec![String::from("0"),String::from("1"),String::from("2")];
-        fs::remove_file(Path::new(&output_location)).unwrap();
-        assert_eq!(true,b.save_to_file(&output_location));
         let f : String = fs::read_to_string(&output_location).unwrap();
-        assert_eq!(f.lines().nth(1).unwrap().trim().parse::<String>().unwrap(), "0,1,2");
+        assert_eq!(f.lines().next().unwrap(), BattleData::csv_header(&r));
+        assert_eq!(f.lines().nth(1).unwrap(), b.format_output());
 
         assert_eq!(true,b.save_to_file(&output_location));
         let f : String = fs::read_to_string(&output_location).unwrap();
-        assert_eq!(f.lines().nth(1).unwrap().trim().parse::<String>().unwrap(), "0,1,2");
-        assert_eq!(f.lines().nth(2).unwrap().trim().parse::<String>().unwrap(), "0,1,2");
+        assert_eq!(3, f.lines().count());
+        assert_eq!(f.lines().nth(2).unwrap(), b.format_output());
+    }
+
+    #[test]
+    fn test_json_record(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let attacker = Player::new(vec![r.get_unit_by_id(1).clone(), r.get_unit_by_id(1).clone()], General::default());
+        let defender = Player::new(vec![r.get_unit_by_id(2).clone()], General::default());
+        let mut battle = Battle::new(attacker, defender, BattleType::Normal);
+        let mut b = BattleData::new(&r);
+        let res = battle.autoresolve(&t, &mut b);
+
+        let j : serde_json::Value = serde_json::from_str(&b.format_json(&res)).unwrap();
+        assert_eq!("Normal", j["battle_type"]);
+        assert_eq!(format!("{:?}", b.get_outcome()), j["outcome"]);
+        let names = r.get_all_unit_names();
+        assert_eq!(2, j["attacker"]["units"][&names[0]]);
+        assert_eq!(1, j["defender"]["units"].as_object().unwrap().len());
+        assert!(j["attacker"]["begin"]["soldiers"].as_i64().unwrap() > 0);
+        assert_eq!(b.get_attacker().get_soldier_casualties(), j["casualties"]["attacker"]["soldier_casualties"].as_i64().unwrap() as i32);
+        assert!(j["treasure"].get("attacker").is_some());
+    }
 
-        // clean up
-        fs::remove_file(Path::new(&output_location)).unwrap();
+    #[test]
+    fn test_template_matches_default_roster(){
+        let r = Roster::embedded();
+        let t : String = fs::read_to_string("./ResourceFiles/data_capture_template.txt").unwrap().trim().parse().unwrap();
+        assert_eq!(t, BattleData::csv_header(&r));
+        assert_eq!(141, t.split(',').count());
+    }
+
+    #[test]
+    fn test_header_from_roster(){
+        let r = Roster::embedded();
+        let header = BattleData::csv_header(&r);
+        let columns = header.split(',').collect::<Vec<&str>>();
+        assert_eq!("BattleType", columns[0]);
+        assert_eq!("ARebelRivermenMercenaries", columns[25]);
+        assert_eq!("DRebelRivermenMercenaries", columns[93]);
+        assert_eq!("DTownLevel", columns[140]);
+        assert_eq!("RidersOfTheWood", BattleData::column_name("Riders of the Wood"));
+        // the base roster keeps the columns of existing capture files
+        assert_eq!("ARidersOfWood", BattleData::unit_column("A", "Riders of the Wood"));
+        assert_eq!("DGenTinketBonus", columns[81]);
+        assert_eq!("AFrostGiants", BattleData::unit_column("A", "Frost Giants"));
+    }
+
+    #[test]
+    fn test_unit_columns(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let names = r.get_all_unit_names();
+        let attacker = Player::new(vec![r.get_unit_by_id(1).clone(), r.get_unit_by_id(1).clone()], General::default());
+        let defender = Player::new(vec![r.get_unit_by_id(2).clone()], General::default());
+        let mut battle = Battle::new(attacker, defender, BattleType::Raid {defenses: TownStats::new(3, TownDefenses::StoneWall)});
+        let mut b = BattleData::new(&r);
+        battle.autoresolve(&t, &mut b);
+
+        let header = BattleData::csv_header(&r);
+        let row = b.format_output();
+        let value = |col : &str| {
+            let i = header.split(',').position(|c| c == col).unwrap();
+            row.split(',').nth(i).unwrap().to_string()
+        };
+        let attacker_unit = BattleData::unit_column("A", &names[0]);
+        let defender_unit = BattleData::unit_column("D", &names[1]);
+
+        // defender units are written to the defender's columns
+        assert_eq!("2", value(&attacker_unit));
+        assert_eq!("0", value(&BattleData::unit_column("D", &names[0])));
+        assert_eq!("1", value(&defender_unit));
+        assert_eq!("0", value(&BattleData::unit_column("A", &names[1])));
+        assert_eq!("3", value("Supplies"));
+        assert_eq!("StoneWall", value("DTownLevel"));
+        assert_eq!("Raid", value("BattleType"));
     }
 }
 
@@ -1052,12 +1587,14 @@ mod test_battle_json{
     use crate::faction::Faction;
     use crate::equipment::EquipmentType;
     use crate::monster::MonsterType;
+    use crate::generator::GeneratorProfile;
+    use crate::testing::TestDir;
 
     #[test]
     fn normal_deserialize(){
-        let r = Roster::new(None);
-        let t = Treasure::new(None);
-        let b = BattleJSONObject::from_json("./ResourceFiles/normal_battle_template.json").produce_battle(&r, &t);
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let b = BattleJSONObject::from_json("./ResourceFiles/normal_battle_template.json").produce_battle(&r, &t, &GeneratorProfile::default());
 
         assert_eq!(BattleType::Normal, b.battle_type);
 
@@ -1086,9 +1623,9 @@ mod test_battle_json{
 
     #[test]
     fn siege_deserialize(){
-        let r = Roster::new(None);
-        let t = Treasure::new(None);
-        let b = BattleJSONObject::from_json("./ResourceFiles/siege_battle_template.json").produce_battle(&r, &t);
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let b = BattleJSONObject::from_json("./ResourceFiles/siege_battle_template.json").produce_battle(&r, &t, &GeneratorProfile::default());
 
         assert_eq!(BattleType::Siege {
             rams : 3,
@@ -1125,9 +1662,9 @@ mod test_battle_json{
 
     #[test]
     fn raid_deserialize(){
-        let r = Roster::new(None);
-        let t = Treasure::new(None);
-        let b = BattleJSONObject::from_json("./ResourceFiles/raid_battle_template.json").produce_battle(&r, &t);
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let b = BattleJSONObject::from_json("./ResourceFiles/raid_battle_template.json").produce_battle(&r, &t, &GeneratorProfile::default());
 
         assert_eq!(BattleType::Raid {
             defenses : TownStats{
@@ -1161,9 +1698,9 @@ mod test_battle_json{
 
     #[test]
     fn naval_deserialize(){
-        let r = Roster::new(None);
-        let t = Treasure::new(None);
-        let b = BattleJSONObject::from_json("./ResourceFiles/naval_battle_template.json").produce_battle(&r, &t);
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let b = BattleJSONObject::from_json("./ResourceFiles/naval_battle_template.json").produce_battle(&r, &t, &GeneratorProfile::default());
 
         assert_eq!(BattleType::Naval{
             attacker_ships : 5,
@@ -1196,9 +1733,9 @@ mod test_battle_json{
 
     #[test]
     fn monster_deserialize(){
-        let r = Roster::new(None);
-        let t = Treasure::new(None);
-        let b = BattleJSONObject::from_json("./ResourceFiles/monster_battle_template.json").produce_battle(&r, &t);
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let b = BattleJSONObject::from_json("./ResourceFiles/monster_battle_template.json").produce_battle(&r, &t, &GeneratorProfile::default());
 
         assert_eq!(BattleType::Monster{
             monster:MonsterType::Troll
@@ -1229,6 +1766,81 @@ mod test_battle_json{
     }
 
 
+    #[test]
+    fn template_has_no_random(){
+        for f in ["normal","siege","raid","naval","monster"]{
+            let j = BattleJSONObject::from_json(&format!("./ResourceFiles/{}_battle_template.json", f));
+            assert!(!j.has_random());
+        }
+    }
 
+    #[test]
+    fn partial_random_deserialize(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let p = GeneratorProfile{ rank_range: (4,6), ..Default::default() };
+        let j = BattleJSONObject::from_json("./ResourceFiles/partial_random_battle_template.json");
+        assert!(j.has_random());
+
+        for _ in 0..20{
+            let b = j.produce_battle(&r, &t, &p);
+            assert_eq!(BattleType::Siege {
+                rams : 3,
+                catapults:0,
+                siege_towers:4,
+                defenses:TownStats{
+                    supplies: 2,
+                    defenses:TownDefenses::StoneWall}
+            }, b.battle_type);
+
+            // attacker is fixed except for general rank
+            assert_eq!(3, b.attacker.get_units().len());
+            assert_eq!(Faction::Beladimir, *b.attacker.get_faction());
+            assert!((4..=6).contains(&b.attacker.get_general().get_rank()));
+
+            // defender is randomized
+            assert!((4..=6).contains(&b.defender.get_general().get_rank()));
+            assert!(b.defender.get_units().iter().all(|u| u.get_faction() == b.defender.get_faction()));
+        }
+    }
+
+    #[test]
+    fn random_battle_type_deserialize(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let mut j = BattleJSONObject::read_value("./ResourceFiles/normal_battle_template.json");
+
+        j["battle_type"] = serde_json::json!({"Random":"Naval"});
+        let b : BattleJSONObject = serde_json::from_value(j.clone()).unwrap();
+        assert!(b.has_random());
+        assert_eq!(Some(BattleType::Naval{attacker_ships:0,defender_ships:0}), b.get_battle_type());
+        assert_eq!("Naval", b.produce_battle(&r, &t, &GeneratorProfile::default()).battle_type.get_name());
+
+        j["battle_type"] = serde_json::json!("Random");
+        let b : BattleJSONObject = serde_json::from_value(j).unwrap();
+        assert!(b.has_random());
+        assert_eq!(None, b.get_battle_type());
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn army_references(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let dir = TestDir::new("army_battle");
+        let path = &dir.write("battle.json", r#"{"battle_type" : "Normal",
+            "attacker" : {"army" : "beladimir_vanguard", "general" : {"rank" : 4}, "remove_units" : [3]},
+            "defender" : "menoriad_garrison"}"#);
+        let b = BattleJSONObject::from_json(path).produce_battle(&r, &t, &GeneratorProfile::default());
+        assert_eq!(4, b.attacker.get_general().get_rank());
+        assert_eq!(vec![1, 2], b.attacker.get_units().iter().map(|u| u.get_id()).collect::<Vec<u32>>());
+        assert_eq!(5, b.defender.get_units().len());
+        assert_eq!(Faction::Menoriad, *b.defender.get_faction());
+    }
+
+    #[test]
+    fn unknown_random_battle_type(){
+        let j = serde_json::json!({"battle_type" : {"Random" : "Sieg"}, "attacker" : "Random", "defender" : "Random"});
+        assert_eq!(Err(String::from("$.battle_type.Random: unknown value \"Sieg\", did you mean \"Siege\"?")),
+                   BattleJSONObject::from_value(j).map(|_| ()));
+    }
+}
@@ -0,0 +1,110 @@
+use crate::battle::{BattleJSONObject, BattleOutcome};
+use crate::database::QueryTable;
+use crate::output::OutcomeCounts;
+use serde_json::Value;
+
+/// Runs a battle JSON file once for each value of one of its fields
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sweep{
+    pub file : String,
+    /// Field to set, as a dotted path, e.g. attacker.general.rank, or a JSON pointer, e.g. /attacker/general/rank
+    pub field : String,
+    pub values : Vec<Value>,
+}
+
+impl Sweep{
+    /// Parse sweep values from an integer range, e.g. 1-10, or a comma separated list, e.g. 1,5,10 or true,false.
+    /// List items that aren't JSON are used as strings
+    pub fn parse_values(values : &str) -> Vec<Value>{
+        let range = values.split_once('-').and_then(|(min, max)| min.trim().parse::<i64>().ok().zip(max.trim().parse::<i64>().ok()));
+        match range{
+            Some((min, max)) if min <= max => (min..=max).map(Value::from).collect(),
+            Some(_) => panic!("Invalid sweep range {}", values),
+            None => values.split(',')
+                .map(|v| serde_json::from_str(v.trim()).unwrap_or_else(|_| Value::from(v.trim())))
+                .collect(),
+        }
+    }
+
+    /// Get the field as a JSON pointer
+    pub fn get_pointer(&self) -> String{
+        match self.field.starts_with('/'){
+            true => self.field.clone(),
+            false => format!("/{}", self.field.replace('.', "/")),
+        }
+    }
+
+    /// Read the battle JSON file being swept, with army references replaced by the armies
+    pub fn read_base(&self) -> Value{
+        BattleJSONObject::read_value(&self.file)
+    }
+
+    /// Get the battle JSON with the field set to value
+    pub fn produce(&self, base : &Value, value : &Value) -> Value{
+        let mut j = base.clone();
+        match j.pointer_mut(&self.get_pointer()){
+            Some(v) => *v = value.clone(),
+            None => panic!("Battle {} has no field {}", self.file, self.field),
+        }
+        // check the battle is still valid with the new value
+        serde_json::from_value::<BattleJSONObject>(j.clone())
+            .unwrap_or_else(|e| panic!("Invalid value {} for field {}: {}", value, self.field, e));
+        j
+    }
+}
+
+/// Get table of each labelled run's battles, attacker win rate and outcome counts
+pub fn outcome_table(label : &str, results : &[(String, OutcomeCounts)]) -> QueryTable{
+    let mut columns = vec![String::from(label), String::from("Battles"), String::from("AttackerWinRate")];
+    columns.extend(BattleOutcome::ALL.iter().map(|o| format!("{:?}", o)));
+    let rows = results.iter().map(|(name, counts)| {
+        let wins : u64 = BattleOutcome::ALL.iter().filter(|o| o.is_victory()).map(|o| counts.get(o)).sum();
+        let mut row = vec![name.clone(), counts.get_total().to_string(),
+                           format!("{:.4}", wins as f64 / counts.get_total().max(1) as f64)];
+        row.extend(BattleOutcome::ALL.iter().map(|o| counts.get(o).to_string()));
+        row
+    }).collect();
+    QueryTable{ columns, rows }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_values(){
+        assert_eq!(vec![json!(1), json!(2), json!(3)], Sweep::parse_values("1-3"));
+        assert_eq!(vec![json!(true), json!("Random"), json!(5)], Sweep::parse_values("true, Random,5"));
+    }
+
+    #[test]
+    fn test_produce(){
+        let s = Sweep{ file: String::from("embedded:normal_battle_template.json"), field: String::from("attacker.general.rank"), values: vec![] };
+        assert_eq!("/attacker/general/rank", s.get_pointer());
+        let base = s.read_base();
+        let j = s.produce(&base, &json!(7));
+        assert_eq!(json!(7), j["attacker"]["general"]["rank"]);
+        assert_eq!(json!(3), base["attacker"]["general"]["rank"]);
+
+        let s = Sweep{ field: String::from("/defender/units/0"), ..s };
+        assert_eq!(json!(20), s.produce(&base, &json!(20))["defender"]["units"][0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "has no field")]
+    fn test_missing_field(){
+        let s = Sweep{ file: String::from("embedded:normal_battle_template.json"), field: String::from("attacker.height"), values: vec![] };
+        s.produce(&s.read_base(), &json!(1));
+    }
+
+    #[test]
+    fn test_outcome_table(){
+        let mut c = OutcomeCounts::default();
+        c.add(&BattleOutcome::DecisiveVictory);
+        c.add(&BattleOutcome::Draw);
+        let t = outcome_table("Battle", &[(String::from("a.json"), c)]);
+        assert_eq!(10, t.columns.len());
+        assert_eq!(vec!["a.json", "2", "0.5000", "1", "0", "0", "1", "0", "0", "0"], t.rows[0]);
+    }
+}
@@ -1,18 +1,72 @@
 
-use clap::{App, Arg, ArgMatches};
-use crate::battle::{BattleType, TownStats, Battle, BattleJSONObject, BattleOutcome, BattleData, BattleResults};
+use clap::{App, Arg, ArgMatches, SubCommand};
+use crate::battle::{BattleType, TownStats, Battle, BattleJSONObject, BattleTypeJSONObject, BattleOutcome};
 use crate::monster::MonsterType;
-use crate::roster::Roster;
-use crate::treasure::Treasure;
-use crate::player::Player;
-use std::io::{BufWriter, Write};
+use crate::roster::{Roster, RosterCommand, UnitFields};
+use crate::faction::Faction;
+use crate::unit::UnitType;
+use crate::treasure::{Treasure, TreasureCommand, ItemFilter, ItemFields};
+use crate::equipment::EquipmentType;
+use crate::player::{Player, PlayerJSONObject};
+use crate::notation;
+use crate::manifest::RunManifest;
+use crate::pack::{ContentPack, PackRecord};
+use crate::rng;
+use crate::export::BattleExporter;
+use crate::output::{OutputFormat, WriteMode, BattleSummary, RunWriter, OutcomeCounts};
+use crate::executor::Executor;
+use crate::progress::Progress;
+use crate::precision::{Precision, PrecisionTarget};
+use crate::interrupt;
+use crate::resources::{self, Resource, ResourceLocation};
+use crate::settings::{self, ConfigFile, Setting, SettingOrigin};
+use crate::database::{BattleDatabase, BuiltinQuery, DEFAULT_DATABASE};
+use crate::analyze::{Analysis, Filter};
+use crate::compare::{self, Sweep};
+use crate::validate;
+use crate::schema;
+use crate::generator::{BattleSource, BattleGenerator, BalanceSettings, GeneratorProfile, FactionWeights, UnitTypeWeights, BattleTypeWeights, RandomMarker,
+                       Randomizable, parse_range, parse_signed_range};
+use std::collections::BTreeMap;
+use std::ops::ControlFlow;
 use std::path::Path;
-use std::fs;
-use std::fs::OpenOptions;
-use std::sync::mpsc::{channel, Sender, Receiver};
-use threadpool::ThreadPool;
-use std::sync::{Mutex, Arc};
 
+/// Action performed by the application, chosen by subcommand
+#[derive(Debug, Clone, PartialEq)]
+enum Command{
+    /// Run battles from the battle file or random settings
+    Run,
+    /// Run each battle file with the same settings and compare their results
+    Compare(Vec<String>),
+    /// Run a battle file once for every value of one of its fields
+    Sweep(Sweep),
+    /// List scenarios that can be run by name
+    Scenarios,
+    /// Analyze captured data
+    Analyze(Analysis),
+    /// List, show, add, edit or validate units in the roster
+    Roster(RosterCommand),
+    /// List, search, add or validate items in the treasure
+    Treasure(TreasureCommand),
+    /// Check battle JSON files against the schema, roster and treasure
+    Validate(Vec<String>),
+    /// Print JSON Schema of battle JSON files
+    Schema,
+    /// Print resource files used
+    Resources,
+    /// Print option values merged from config files and CLI args
+    ShowConfig,
+    /// Print a built-in query on the database at a path
+    Query(BuiltinQuery, String),
+}
+
+/// Battles run by run_battles and how they are labelled and recorded
+struct RunTarget{
+    label : Option<String>,
+    battle_file : Option<String>,
+    /// Battle JSON to run instead of the battle file's contents
+    battle_json : Option<serde_json::Value>,
+}
 
 pub struct Config {
     roster : Roster,
@@ -21,69 +75,320 @@ pub struct Config {
     save_data : bool,
     log : bool,
     output_file_override : Option<String>,
+    format : OutputFormat,
+    write_mode : WriteMode,
+    command : Command,
+    resources : Vec<ResourceLocation>,
+    /// Content packs merged into the roster and treasure, in load order
+    packs : Vec<ContentPack>,
     run_count: u32,
+    precision : Option<Precision>,
     battle_type : Option<BattleType>,
+    /// Battle type was given in notation, random battles keep its parameters instead of randomizing them
+    fixed_battle_parameters : bool,
+    /// Attacker and defender given in army notation
+    armies : [Option<PlayerJSONObject>; 2],
     battle_file : Option<String>,
-    multithread : bool
+    multithread : bool,
+    threads : Option<usize>,
+    progress : bool,
+    /// Only sample battle outcomes with a BatchResolver
+    fast_outcomes : bool,
+    profile : GeneratorProfile,
+    balance : Option<BalanceSettings>,
+    export_dir : Option<String>,
+    export_filter : Option<Vec<BattleOutcome>>,
+    seed : Option<u64>,
+    cli_args : Vec<String>,
+    config_files : Vec<String>,
+    config_profile : Option<String>,
+    /// Value of every option set, by long flag
+    settings : Vec<(String, Setting)>,
+}
+
+/// Options that can be set in config files and are read the same before or after a subcommand, by key, which is
+/// their long flag, with the name of their arg and if they are a flag set with true or false
+const RESOURCE_OPTIONS : &[(&str, &str, bool)] = &[
+    ("unit", "roster_file", false), ("treasure", "treasure_file", false), ("profile", "profile_file", false),
+    ("pack", "packs", false),
+];
+
+/// Options for running battles that can be set in config files, by key, with the name of their arg and if they are a flag
+const RUN_OPTIONS : &[(&str, &str, bool)] = &[
+    ("save", "save", true), ("file", "output_file", false), ("format", "format", false),
+    ("write-mode", "write_mode", false), ("count", "run_count", false), ("log", "log", true),
+    ("multithread", "multithread", true), ("threads", "threads", false), ("precision", "precision", false),
+    ("precision-target", "precision_target", false), ("max-count", "max_count", false), ("progress", "progress", true),
+    ("fast", "fast_outcomes", true), ("equipment-ratio", "equipment_ratio", false), ("rank-range", "rank_range", false),
+    ("reinforcement-cap", "reinforcement_cap", false), ("army-size", "army_size", false),
+    ("adv-combat-chance", "adv_combat_chance", false), ("faction-weights", "faction_weights", false),
+    ("unit-weights", "unit_weights", false), ("battle-weights", "battle_weights", false),
+    ("balanced", "balance_margin", false), ("max-attempts", "max_attempts", false), ("export", "export_dir", false),
+    ("export-filter", "export_filter", false), ("seed", "seed", false), ("random", "random", true),
+    ("battle", "battle_type", false), ("json", "battle_file", false), ("attacker", "attacker", false),
+    ("defender", "defender", false),
+];
+
+/// Pairs of option args that conflict, matching the conflicts of the args
+const CONFLICTING_OPTIONS : &[(&str, &str)] = &[
+    ("random", "battle_file"), ("battle_type", "battle_file"), ("attacker", "random"), ("attacker", "battle_file"),
+    ("defender", "random"), ("defender", "battle_file"), ("fast_outcomes", "save"), ("fast_outcomes", "log"),
+    ("fast_outcomes", "export_dir"),
+];
+
+/// Get every option that can be set in config files, by key, with the name of its arg and if it is a flag
+fn config_options() -> impl Iterator<Item = &'static (&'static str, &'static str, bool)>{
+    RESOURCE_OPTIONS.iter().chain(RUN_OPTIONS.iter())
+}
+
+/// Option values from CLI args, falling back to values from config files, then the args' default values
+struct Options<'a>{
+    matches : &'a ArgMatches<'a>,
+    /// Config file values by arg name
+    settings : BTreeMap<&'static str, Setting>,
+}
+
+impl<'a> Options<'a>{
+    /// Create Options from CLI matches and config file values by key, checking each value is for a known option
+    fn new(matches : &'a ArgMatches<'a>, values : &BTreeMap<String, Setting>) -> Options<'a>{
+        let settings = values.iter().map(|(key, s)| {
+            let (_, name, flag) = config_options().find(|(k, _, _)| k == key).unwrap_or_else(|| {
+                let keys : Vec<&str> = config_options().map(|(k, _, _)| *k).collect();
+                panic!("Unknown option {} ({}), expected one of {}", key, s.origin_output(), keys.join(", "))
+            });
+            if *flag && s.value.parse::<bool>().is_err(){
+                panic!("Option {} ({}) must be true or false", key, s.origin_output());
+            }
+            (*name, s.clone())
+        }).collect();
+        Options{ matches, settings }
+    }
+
+    /// Get value of an arg and where it was set. A config file value is ignored if a conflicting arg is set with
+    /// higher precedence
+    fn get(&self, name : &str) -> Option<Setting>{
+        if self.matches.occurrences_of(name) > 0{
+            // args given several times are comma separated, like lists in config files
+            let values : Vec<&str> = self.matches.values_of(name).into_iter().flatten().collect();
+            let value = if values.is_empty(){ String::from("true") } else { values.join(",") };
+            return Some(Setting{ value, origin: SettingOrigin::Cli });
+        }
+        match self.settings.get(name){
+            Some(s) if !self.is_overridden(name, s) => Some(s.clone()),
+            _ => self.matches.value_of(name).map(|v| Setting{ value: String::from(v), origin: SettingOrigin::Default }),
+        }
+    }
+
+    /// Get value of an arg
+    fn value_of(&self, name : &str) -> Option<String>{
+        self.get(name).map(|s| s.value)
+    }
+
+    /// Get if a flag is set
+    fn is_present(&self, name : &str) -> bool{
+        self.get(name).is_some_and(|s| s.is_true())
+    }
 
+    /// Get if a config file value is overridden by a conflicting arg, panics if both are set with the same precedence
+    fn is_overridden(&self, name : &str, setting : &Setting) -> bool{
+        let precedence = setting.origin.get_precedence();
+        CONFLICTING_OPTIONS.iter()
+            .filter_map(|(a, b)| match (*a == name, *b == name){
+                (true, _) => Some(*b),
+                (_, true) => Some(*a),
+                _ => None,
+            })
+            .any(|c| {
+                let other = match self.matches.occurrences_of(c){
+                    0 => self.settings.get(c).filter(|s| s.value != "false").map_or(0, |s| s.origin.get_precedence()),
+                    _ => SettingOrigin::Cli.get_precedence(),
+                };
+                if other == precedence{
+                    let key = |n : &str| config_options().find(|(_, a, _)| *a == n).map_or(String::from(n), |(k, _, _)| String::from(*k));
+                    panic!("Options {} and {} conflict ({})", key(name), key(c), setting.origin_output());
+                }
+                other > precedence
+            })
+    }
 }
 
 impl Config{
     /// Create new Config based on CLI args
     pub fn initialize() -> Config{
         let app  = Self::initialize_clap_app();
-        let matches = app.get_matches();
-        let cfg = Self::parse_app_arguments(&matches);
-
+        let args : Vec<String> = std::env::args().collect();
+        let matches = app.get_matches_from(args.clone());
+        let mut cfg = Self::parse_app_arguments(&matches);
+        cfg.cli_args = args;
         cfg
     }
 
     /// Run application with provided Config
     pub fn run_app(&self){
+        match &self.command{
+            Command::Run => {
+                interrupt::install();
+                for (side, army) in ["Attacker", "Defender"].iter().zip(self.armies.iter()){
+                    if let Some(a) = army{
+                        let player = a.produce_player(&self.roster, &self.treasure, &self.profile);
+                        println!("{}: {}", side, notation::format_player(&player, &self.roster, &self.treasure));
+                    }
+                }
+                let target = RunTarget{ label: None, battle_file: self.battle_file.clone(), battle_json: self.get_army_battle() };
+                self.run_battles(&target, self.get_seed());
+            },
+            Command::Compare(files) => {
+                interrupt::install();
+                let targets = files.iter().map(|f| RunTarget{ label: Some(f.clone()), battle_file: Some(f.clone()), battle_json: None });
+                self.run_comparison("Battle", targets.collect());
+            },
+            Command::Sweep(sweep) => {
+                interrupt::install();
+                let base = sweep.read_base();
+                let targets = sweep.values.iter().map(|v| RunTarget{
+                    label: Some(format!("{}={}", sweep.field, v)),
+                    battle_file: Some(sweep.file.clone()),
+                    battle_json: Some(sweep.produce(&base, v)),
+                });
+                self.run_comparison(&sweep.field, targets.collect());
+            },
+            Command::Scenarios => println!("Scenarios: {}", resources::scenario_names().join(", ")),
+            Command::Analyze(a) => a.run(),
+            Command::Roster(c) => {
+                if !c.run(self.roster.clone()){
+                    std::process::exit(1);
+                }
+            },
+            Command::Treasure(c) => {
+                if !c.run(self.treasure.clone()){
+                    std::process::exit(1);
+                }
+            },
+            Command::Validate(files) => {
+                if !validate::validate_files(files, &self.roster, &self.treasure){
+                    std::process::exit(1);
+                }
+            },
+            Command::Schema => println!("{}", serde_json::to_string_pretty(&schema::battle_schema()).unwrap()),
+            Command::Resources => {
+                resources::print_resources(&self.resources);
+                let packs : Vec<String> = self.packs.iter().map(|p| format!("{} ({})", p.namespace, p.dir)).collect();
+                println!("Content packs: {}", if packs.is_empty(){ String::from("none") } else { packs.join(", ") });
+            },
+            Command::ShowConfig => settings::print_settings(&self.config_files, self.config_profile.as_deref(), &self.settings),
+            Command::Query(q, path) => println!("{}", BattleDatabase::open(path).run_query(*q).table_output()),
+        }
+    }
 
-        // aggregate data for runs
-        let mut battle_outcomes : [i32;7] = [0;7];
-
-        // Use Normal battle if none specified
-        let mut b_type= self.battle_type.unwrap_or_else(|| BattleType::Normal);
-
-        // create Battle
-        let b = match &self.battle_file {
-            // using a JSON battle
-            Some(s) => {
-                let b = BattleJSONObject::from_json(s).produce_battle(&self.roster, &self.treasure);
-                b_type = b.get_battle_type();
-                b
+    /// Run every target with the same seed, then print a table comparing their results
+    fn run_comparison(&self, label : &str, targets : Vec<RunTarget>){
+        let seed = self.get_seed();
+        let mut results = vec![];
+        for t in targets.iter(){
+            if interrupt::is_interrupted(){
+                break;
+            }
+            results.push((t.label.clone().unwrap_or_default(), self.run_battles(t, seed)));
+            println!();
+        }
+        println!("Seed: {}\n{}", seed, compare::outcome_table(label, &results).table_output());
+    }
+
+    /// Get seed for the run, a random seed if none was given
+    fn get_seed(&self) -> u64{
+        self.seed.unwrap_or_else(rng::random_seed)
+    }
+
+    /// Run battles of a target, saving, logging and exporting them as configured, return their outcomes
+    fn run_battles(&self, target : &RunTarget, seed : u64) -> OutcomeCounts{
+
+        // seed random number generation so the run can be reproduced
+        rng::seed(seed);
+
+        // create generator of Battles for runs
+        let generator = BattleGenerator::new(self.create_battle_source(target), self.profile.clone(), self.balance);
+
+        // Use Normal battle if none specified or random
+        let b_type = generator.get_battle_type().unwrap_or(BattleType::Normal);
+
+        // create exporter for writing generated battles to JSON
+        let exporter = self.export_dir.as_ref().map(|d| BattleExporter::new(d, self.export_filter.clone()));
+
+        // open output file and record how the run is produced
+        let mut output = match self.save_data{
+            true => {
+                let output_file = self.get_output_file(b_type);
+                println!("Saving results to file {}", output_file);
+                let mut manifest = RunManifest::new(self.cli_args.clone(), seed, self.get_run_count(),
+                                                    self.roster.get_file_path(), self.treasure.get_file_path(),
+                                                    target.battle_file.as_deref(), &self.profile);
+                manifest.packs = self.packs.iter().map(PackRecord::new).collect();
+                if target.battle_json.is_some(){
+                    manifest.battle_json = target.battle_json.clone();
+                }
+                let writer = RunWriter::open(self.format, &output_file, self.write_mode, &self.roster, &manifest);
+                Some((writer, manifest))
             },
-            // not using JSON
-            None => Battle::new(Player::default(), Player::default(), b_type)
+            false => None,
         };
 
-        // run battles with either one or multiple threads
-        let data : (Vec<BattleData>, Vec<BattleResults>) = match self.multithread{
-            true => self.run_multiple_threads(&b, self.run_count),
-            false => Config::run_single_thread(&b, self.run_count, &self.roster, &self.treasure, self.use_rand, self.battle_type)
+        // aggregate data for runs
+        let type_name = generator.get_battle_type().map(|b| b.get_name()).unwrap_or_else(|| String::from("Random"));
+        let mut summary = BattleSummary::new(seed, &type_name);
+
+        // run battles with one or more threads, handling each battle in order as it is resolved
+        // runs until a precision is reached can stop before the maximum count
+        let mut progress = match self.precision{
+            Some(p) => Progress::capped(p.max_count as u64, self.progress),
+            None => Progress::new(self.run_count as u64, self.progress),
         };
+        let executor = Executor::new(&generator, &self.roster, &self.treasure, seed, self.get_thread_count(), exporter.is_some());
+        let outcomes = match self.outcomes_only(){
+            // only outcomes are sampled so skip building BattleData
+            true => {
+                let mut outcomes = OutcomeCounts::default();
+                executor.run_outcomes(self.get_run_count(), |c| {
+                    outcomes.merge(c);
+                    progress.add(c.get_total());
+                    self.check_stop(&outcomes)
+                });
+                outcomes
+            },
+            false => {
+                executor.run(self.get_run_count(), |(d, r, json)| {
+                    if let (Some(e), Some(j)) = (exporter.as_ref(), json){
+                        e.export(&j, r.get_outcome());
+                    }
+                    // output data for each battle
+                    if self.log{
+                        println!("{}", r.battle_output());
+                    }
+                    summary.add(&d);
+                    if let Some((w, _)) = output.as_mut(){
+                        w.write(&d, &r);
+                    }
+                    progress.add(1);
+                    self.check_stop(summary.get_outcomes())
+                });
+                summary.get_outcomes().clone()
+            },
+        };
+        progress.finish();
 
-        // output data for each battle
-        if self.log{
-            data.1.iter().for_each(|r| println!("{}",r.battle_output()));
-        }
-
-        // save outcome data
-        data.0.iter().for_each(|d|
-            match d.get_outcome(){
-                BattleOutcome::DecisiveVictory => battle_outcomes[0] += 1,
-                BattleOutcome::HeroicVictory => battle_outcomes[1] += 1,
-                BattleOutcome::CloseVictory => battle_outcomes[2] += 1,
-                BattleOutcome::Draw => battle_outcomes[3] += 1,
-                BattleOutcome::CloseDefeat => battle_outcomes[4] += 1,
-                BattleOutcome::ValiantDefeat => battle_outcomes[5] += 1,
-                BattleOutcome::CrushingDefeat => battle_outcomes[6] += 1,
+        // mark results of an interrupted run as partial
+        let partial = interrupt::is_interrupted();
+        if partial{
+            println!("Run interrupted, results are partial: {} of {} battles completed", outcomes.get_total(), self.get_run_count());
+            summary.set_partial();
+            if let Some((_, manifest)) = output.as_mut(){
+                manifest.partial = true;
             }
-        );
+        }
 
         // print general result statistics
+        if let Some(l) = &target.label{
+            println!("Battle: {}", l);
+        }
         println!("Battle Type: {}\nResults(For attacker):\n\
         Decisive Victory:{}\n\
         Heroic Victory:{}\n\
@@ -92,166 +397,611 @@ impl Config{
         Close Defeat:{}\n\
         Valiant Defeat:{}\n\
         Crushing Defeat:{}",
-                 if (self.use_rand) && (self.battle_type == None) {String::from("Random")} else {b_type.get_name()},
-                 battle_outcomes[0], battle_outcomes[1], battle_outcomes[2],
-                 battle_outcomes[3],
-                 battle_outcomes[4], battle_outcomes[5], battle_outcomes[6]);
+                 type_name,
+                 outcomes.get(&BattleOutcome::DecisiveVictory), outcomes.get(&BattleOutcome::HeroicVictory),
+                 outcomes.get(&BattleOutcome::CloseVictory), outcomes.get(&BattleOutcome::Draw),
+                 outcomes.get(&BattleOutcome::CloseDefeat), outcomes.get(&BattleOutcome::ValiantDefeat),
+                 outcomes.get(&BattleOutcome::CrushingDefeat));
 
-        // save data to file
-        if self.save_data {
-            self.save_run_results(&data.0, b_type)
+        // print how many runs were needed for the precision
+        if let Some(p) = self.precision{
+            println!("{}", p.precision_output(&outcomes));
         }
-    }
-
-    /// Run all calculations using a single thread
-    fn run_single_thread(battle: &Battle, count : u32, roster : &Roster, treasure : &Treasure,  use_rand : bool, battle_type : Option<BattleType>) -> (Vec<BattleData>, Vec<BattleResults>) {
-        let mut data : Vec<BattleData> = vec![];
-        let mut res : Vec<BattleResults> = vec![];
-
-        for i in 1..=count {
-            // run battles
-
-            // create temp battle
-            let mut temp = match use_rand{
-                true => {
-                    Battle::generate_random_battle(roster,treasure,3,10,5, battle_type)
-                }
-                false => {
-                    battle.clone()
-                }
-            };
 
-            let r = Config::autoresolve_battle(&mut temp, roster, treasure);
-            data.push(r.0);
-            res.push(r.1);
+        // print rejection statistics for balanced generation
+        if let Some(settings) = generator.get_balance(){
+            let stats = generator.get_balance_stats();
+            match stats.get_candidates(){
+                0 => println!("Balanced generation has no effect on a battle without random fields"),
+                _ => println!("{}", stats.stats_output(settings)),
+            }
         }
-        (data, res)
-    }
-
-    /// Run calculations utilizing multiple threads
-    fn run_multiple_threads(&self, battle : &Battle, count : u32) -> (Vec<BattleData>, Vec<BattleResults>){
-
-        let mut data : Vec<BattleData> = vec![];
-        let mut res : Vec<BattleResults> = vec![];
-
-        // create multiple producer, single consumer channel for receiving results tuples
-        let (tx,rx) : (Sender<(Vec<BattleData>, Vec<BattleResults>)>, Receiver<(Vec<BattleData>, Vec<BattleResults>)>)= channel();
 
-        // determine how many threads to create
-        let num_threads = num_cpus::get();
+        if let Some(e) = exporter{
+            println!("Exported {} battles to {}", e.get_count(), e.get_directory());
+        }
 
-        // create ThreadPool
-        let pool = ThreadPool::new(num_threads);
+        // finish saving data and write manifest recording how the rows were produced
+        if let Some((writer, mut manifest)) = output{
+            summary.set_run_id(&manifest.run_id);
+            // fewer battles than the maximum count are run once a precision is reached
+            manifest.run_count = outcomes.get_total() as u32;
+            let output_file = writer.get_path().to_string();
+            let (first_row, row_count) = writer.finish(&summary, &manifest);
+            println!("Saved {} battles to {}", row_count, output_file);
+            manifest.set_output(&output_file, first_row, row_count);
+            println!("Saved run manifest {} to {}", manifest.run_id, manifest.save());
+        }
 
-        // determine how to break up self.run_count to run all calculations
-        let ct_per_thread: u32 = count / (num_threads as u32 - 1);
-        let remainder = count % (num_threads as u32 - 1);
+        outcomes
+    }
 
-        // run and receive data from threads
-        for i in 1..=num_threads {
-            let ros = self.roster.clone();
-            let tr = self.treasure.clone();
-            let bat = battle.clone();
-            let rand = self.use_rand;
-            let b_type = self.battle_type.clone();
-            let tx_c = tx.clone();
+    /// Get battle JSON of the armies given in army notation, with random players for sides not given.
+    /// None if no army is given, unless random battles use a battle type given in notation
+    fn get_army_battle(&self) -> Option<serde_json::Value>{
+        if self.armies.iter().all(|a| a.is_none()) && !(self.use_rand && self.fixed_battle_parameters){
+            return None;
+        }
+        let side = |a : &Option<PlayerJSONObject>| a.clone().map_or(Randomizable::Random(RandomMarker::Random), Randomizable::Fixed);
+        let battle = BattleJSONObject::new(BattleTypeJSONObject::Fixed(self.battle_type.unwrap_or(BattleType::Normal)),
+                                           side(&self.armies[0]), side(&self.armies[1]));
+        Some(serde_json::to_value(battle).unwrap())
+    }
 
-            if i < num_threads { // first n-1 threads
-                pool.execute(move || {
-                    let r = ros;
-                    let t = tr;
-                    let b = bat;
-                    let thread_results = Config::run_single_thread(&b, ct_per_thread, &r, &t, rand, b_type);
-                    tx_c.send(thread_results).expect("Unable to send results through tx channel");
-                });
-            } else { // thread
-                pool.execute(move || {
-                    let r = ros;
-                    let t = tr;
-                    let b = bat;
-                    let thread_results = Config::run_single_thread(&b, remainder, &r, &t, rand, b_type);
-                    tx_c.send(thread_results).expect("Unable to send results through tx channel");
-                });
+    /// Create the source of Battles from the target's battle JSON or random settings
+    fn create_battle_source(&self, target : &RunTarget) -> BattleSource{
+        let json = match (&target.battle_json, &target.battle_file){
+            (Some(j), _) => Some(BattleJSONObject::from_value(j.clone()).unwrap_or_else(|e| panic!("Invalid battle JSON: {}", e))),
+            (None, Some(f)) => Some(BattleJSONObject::from_json(f)),
+            (None, None) => None,
+        };
+        match json {
+            // using a JSON battle, only produce a new Battle every run if some fields are randomized
+            Some(j) => match j.has_random(){
+                true => BattleSource::Template(Box::new(j)),
+                false => BattleSource::Fixed(Box::new(j.produce_battle(&self.roster, &self.treasure, &self.profile))),
+            },
+            // not using JSON
+            None => match self.use_rand{
+                true => BattleSource::Random(self.battle_type),
+                false => BattleSource::Fixed(Box::new(Battle::new(Player::default(), Player::default(), self.battle_type.unwrap_or(BattleType::Normal))))
             }
         }
+    }
 
-        // ensure all threads have completed before continuing
-        pool.join();
-        assert_eq!(0,pool.panic_count());
-        // ensure initial tx channel is closed
-        drop(tx);
+    /// Get if only battle outcomes are sampled, when asked for with --fast and no battle data is saved, logged or exported.
+    /// Sampled outcomes draw different random numbers than fully resolved battles with the same seed
+    fn outcomes_only(&self) -> bool{
+        self.fast_outcomes && !self.save_data && !self.log && self.export_dir.is_none()
+    }
 
-        // save results to vectors
-        for (i,r) in rx.iter().enumerate(){
-            r.0.iter().map(|d| data.push(d.clone())).for_each(drop);
-            r.1.iter().map(|r| res.push(r.clone())).for_each(drop);
+    /// Get number of battles to run, the maximum count when running until a precision is reached
+    fn get_run_count(&self) -> u32{
+        match self.precision{
+            Some(p) => p.max_count,
+            None => self.run_count,
         }
-
-        (data, res)
     }
 
-    /// Autoresolve a single battle and return the BattleData and BattleResults structs
-    fn autoresolve_battle(battle: &mut Battle, roster : &Roster, treasure : &Treasure) -> (BattleData, BattleResults){
-        let mut data = BattleData::new(roster);
-        let res = battle.autoresolve(treasure, &mut data);
-        (data,res)
+    /// Stop running battles once the run is interrupted or the precision is reached
+    fn check_stop(&self, outcomes : &OutcomeCounts) -> ControlFlow<()>{
+        match self.precision{
+            _ if interrupt::is_interrupted() => ControlFlow::Break(()),
+            Some(p) if p.is_reached(outcomes) => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        }
     }
 
-    /// Save set of run results to file
-    fn save_run_results(&self, data: &Vec<BattleData>, b_type: BattleType) {
-        // create BufWriter and write to file
+    /// Get number of threads to run battles with, all cores are used when multithreading without a thread count
+    fn get_thread_count(&self) -> usize{
+        match (self.threads, self.multithread){
+            (Some(n), _) => n,
+            (None, true) => num_cpus::get(),
+            (None, false) => 1,
+        }
+    }
 
-        // Determine what the output file should be
-        let output_file: String = match &self.output_file_override {
-            // use default
-            None => String::from(format!("./DataCapture/{}", b_type.get_data_path())),
+    /// Get the file to save run results to
+    fn get_output_file(&self, b_type : BattleType) -> String{
+        match &self.output_file_override {
+            // all battle types share one database
+            None if self.format == OutputFormat::Sqlite => String::from(DEFAULT_DATABASE),
+            // use default, with the extension of the output format
+            None => Path::new("./DataCapture").join(b_type.get_data_path())
+                .with_extension(self.format.get_extension()).to_string_lossy().to_string(),
             // override default
             Some(s) => s.clone()
-        };
-        print!("Saving results to file {}...", output_file);
-
-        let file_path = Path::new(&output_file);
-        // If output file doesn't exist, create by copying template
-        if !Path::exists(file_path) {
-            println!("\nCreated output file at {} for battle data", output_file);
-            fs::copy("./ResourceFiles/data_capture_template.txt", &output_file).unwrap();
         }
+    }
 
-        // Open output file
-        let f = OpenOptions::new().write(true).append(true).open(file_path).unwrap();
-
-        // Write lines to file
-        let mut writer = BufWriter::new(f);
-        data.iter().for_each(|d| writeln!(writer, "{}", d.format_output()).unwrap());
-        println!("Done");
+    /// Parse arguments from provided CLI command and config files and return a new Config
+    fn parse_app_arguments(top : &ArgMatches) -> Config{
+        let global = Self::innermost(top);
+        Self::parse_with_config(top, &ConfigFile::load(global.value_of("config_file")))
     }
 
-    /// Parse arguments from provided CLI command and return a new Config
-    fn parse_app_arguments(matches : &ArgMatches) -> Config{
+    /// Parse arguments from provided CLI command, using values from the config file for options not given,
+    /// and return a new Config
+    fn parse_with_config(top : &ArgMatches, config : &ConfigFile) -> Config{
+        // run options are read from run-like subcommands, otherwise from the top level flags that are aliases for run
+        let (command, matches) = Self::parse_command(top);
+        // global options can be given before or after the subcommand
+        let global = Self::innermost(top);
+        let config_profile = global.value_of("config_profile").map(String::from);
+        let values = config.select(config_profile.as_deref());
+        let opts = Options::new(matches, &values);
+        let global_opts = Options::new(global, &values);
+        let resources = Self::locate_resources(&global_opts);
+        // roster commands report invalid units instead of failing to read them
+        let mut roster = match command{
+            Command::Roster(_) => Roster::load(Some(&resources[0].path)),
+            _ => Roster::new(Some(&resources[0].path)),
+        };
+        // treasure commands report invalid items instead of failing to read them
+        let mut treasure = match command{
+            Command::Treasure(_) => Treasure::load(Some(&resources[1].path)),
+            _ => Treasure::new(Some(&resources[1].path)),
+        };
+        let packs : Vec<ContentPack> = global_opts.value_of("packs").map(|p| p.split(',').map(|d| ContentPack::read(d.trim())).collect())
+            .unwrap_or_default();
+        for w in roster.add_packs(&packs).iter().chain(treasure.add_packs(&packs).iter()){
+            eprintln!("Warning: {}", w);
+        }
         Config{
-            roster: Roster::new(matches.value_of("roster_file")),
-            treasure: Treasure::new(matches.value_of("treasure_file")),
-            use_rand: matches.is_present("random"),
-            save_data: matches.is_present("save"),
-            log : matches.is_present("log"),
-            output_file_override: matches.value_of("output_file").map(|s| s.to_string()),
-            run_count: matches.value_of("run_count").unwrap().parse().unwrap(),
+            roster,
+            treasure,
+            use_rand: opts.is_present("random"),
+            save_data: opts.is_present("save"),
+            log : opts.is_present("log"),
+            output_file_override: opts.value_of("output_file"),
+            format: OutputFormat::from_name(&opts.value_of("format").unwrap()),
+            write_mode: WriteMode::from_name(&opts.value_of("write_mode").unwrap()),
+            command,
+            run_count: opts.value_of("run_count").unwrap().parse().expect("Invalid count"),
+            precision: opts.value_of("precision").map(|w| Precision{
+                width: match w.parse(){
+                    Ok(w) if w > 0.0 && w <= 1.0 => w,
+                    _ => panic!("Invalid precision {}", w),
+                },
+                target: PrecisionTarget::from_name(&opts.value_of("precision_target").unwrap()),
+                max_count: opts.value_of("max_count").unwrap().parse().expect("Invalid max count"),
+            }),
             // use default values for initializing battle type, they can be altered later
-            battle_type: matches.value_of("battle_type").map( |s| match s {
+            battle_type: opts.value_of("battle_type").map( |s| match s.as_str() {
+                "1" => BattleType::Normal,
                 "2" => BattleType::Siege { rams: 0, catapults: 0, siege_towers: 0, defenses: TownStats::default(), },
                 "3" => BattleType::Raid { defenses: TownStats::default() },
                 "4" => BattleType::Naval {attacker_ships:0,defender_ships:0},
                 "5" => BattleType::Monster { monster: MonsterType::Minotaur },
-                "1" | _ => BattleType::Normal,
+                _ => notation::parse_battle_type(&s).unwrap_or_else(|e| panic!("Invalid battle type \"{}\": {}", s, e)),
+            }),
+            fixed_battle_parameters: opts.value_of("battle_type").map(|s| !["1", "2", "3", "4", "5"].contains(&s.as_str())).unwrap_or(false),
+            armies: ["attacker", "defender"].map(|a| opts.value_of(a).map(|t| notation::parse_army(&t)
+                .unwrap_or_else(|e| panic!("Invalid {} army \"{}\": {}", a, t, e)))),
+            battle_file: match top.subcommand(){
+                ("scenario", Some(m)) => m.value_of("name").map(resources::scenario_path),
+                _ => opts.value_of("battle_file"),
+            },
+            multithread: opts.is_present("multithread"),
+            threads: opts.value_of("threads").map(|s| match s.parse(){
+                Ok(n) if n > 0 => n,
+                _ => panic!("Invalid thread count {}", s),
+            }),
+            progress: opts.is_present("progress"),
+            fast_outcomes: opts.is_present("fast_outcomes"),
+            profile: Self::parse_profile(&opts, &resources[2].path),
+            balance: opts.value_of("balance_margin").map(|m| BalanceSettings{
+                margin: m.parse().expect("Invalid balance margin"),
+                max_attempts: opts.value_of("max_attempts").unwrap().parse().expect("Invalid max attempts"),
+            }),
+            export_dir: opts.value_of("export_dir"),
+            export_filter: opts.value_of("export_filter").as_deref().map(BattleExporter::parse_outcomes),
+            seed: opts.value_of("seed").map(|s| s.parse().expect("Invalid seed")),
+            cli_args: vec![],
+            resources,
+            packs,
+            config_files: config.get_files().to_vec(),
+            config_profile,
+            // resource options are global, the others are read from the run options
+            settings: RESOURCE_OPTIONS.iter().map(|o| (o, &global_opts)).chain(RUN_OPTIONS.iter().map(|o| (o, &opts)))
+                .filter_map(|((key, name, _), o)| o.get(name).map(|s| (String::from(*key), s)))
+                .collect(),
+        }
+    }
+
+    /// Parse the command to perform and get the matches run options are read from
+    fn parse_command<'a>(top : &'a ArgMatches<'a>) -> (Command, &'a ArgMatches<'a>){
+        match top.subcommand(){
+            ("run", Some(m)) => (Command::Run, m),
+            ("scenario", Some(m)) => match m.is_present("name"){
+                true => (Command::Run, m),
+                false => (Command::Scenarios, m),
+            },
+            ("compare", Some(m)) => (Command::Compare(m.values_of("files").unwrap().map(String::from).collect()), m),
+            ("sweep", Some(m)) => (Command::Sweep(Sweep{
+                file: m.value_of("file").unwrap().to_string(),
+                field: m.value_of("field").unwrap().to_string(),
+                values: Sweep::parse_values(m.value_of("values").unwrap()),
+            }), m),
+            ("analyze", Some(m)) => (Command::Analyze(Self::parse_analysis(m)), top),
+            ("roster", Some(m)) => (Command::Roster(Self::parse_roster_command(m)), top),
+            ("treasure", Some(m)) => (Command::Treasure(Self::parse_treasure_command(m)), top),
+            ("validate", Some(m)) => (Command::Validate(m.values_of("files").unwrap().map(String::from).collect()), top),
+            ("schema", Some(_)) => (Command::Schema, top),
+            ("resources", Some(_)) => (Command::Resources, top),
+            ("config", Some(m)) => (Command::ShowConfig, m),
+            _ => match top.value_of("query"){
+                Some(q) => (Command::Query(BuiltinQuery::from_name(q), top.value_of("database").unwrap_or(DEFAULT_DATABASE).to_string()), top),
+                None => (Command::Run, top),
+            },
+        }
+    }
+
+    /// Get matches of the innermost subcommand, which global args given at any level are propagated to
+    fn innermost<'a>(matches : &'a ArgMatches<'a>) -> &'a ArgMatches<'a>{
+        match matches.subcommand(){
+            (_, Some(m)) => Self::innermost(m),
+            _ => matches,
+        }
+    }
+
+    /// Parse roster subcommand args, listing every unit without a subcommand
+    fn parse_roster_command(matches : &ArgMatches) -> RosterCommand{
+        let id = |m : &ArgMatches| m.value_of("id").map(|s| s.parse().expect("Invalid unit id"));
+        let fields = |m : &ArgMatches| UnitFields{
+            faction: m.value_of("faction").and_then(Faction::from_name),
+            name: m.value_of("name").map(String::from),
+            unit_type: m.value_of("unit_type").and_then(UnitType::from_name),
+            bonus: m.value_of("bonus").map(|s| s.parse().expect("Invalid autoresolve bonus")),
+            size: m.value_of("size").map(|s| s.parse().expect("Invalid unit size")),
+            id: m.value_of("new_id").map(|s| s.parse().expect("Invalid unit id")),
+        };
+        match matches.subcommand(){
+            ("show", Some(m)) => RosterCommand::Show(id(m).unwrap()),
+            ("add", Some(m)) => RosterCommand::Add(UnitFields{ id: id(m), ..fields(m) }),
+            ("edit", Some(m)) => RosterCommand::Edit(id(m).unwrap(), fields(m)),
+            ("validate", Some(_)) => RosterCommand::Validate,
+            ("list", Some(m)) => RosterCommand::List(m.value_of("faction").and_then(Faction::from_name),
+                                                     m.value_of("unit_type").and_then(UnitType::from_name)),
+            _ => RosterCommand::List(None, None),
+        }
+    }
+
+    /// Parse treasure subcommand args, listing every item without a subcommand
+    fn parse_treasure_command(matches : &ArgMatches) -> TreasureCommand{
+        let filter = |m : &ArgMatches| ItemFilter{
+            text: m.value_of("text").map(String::from),
+            equipment_type: m.value_of("equipment_type").and_then(EquipmentType::from_name),
+            dragon: m.value_of("dragon").map(|s| s.parse().expect("Invalid dragon flag")),
+            bonus: m.value_of("bonus").map(parse_signed_range),
+            coin_value: m.value_of("coin_value").map(parse_signed_range),
+        };
+        let number = |m : &ArgMatches, name : &str| m.value_of(name).unwrap().parse().unwrap_or_else(|_| panic!("Invalid {}", name));
+        match matches.subcommand(){
+            ("list", Some(m)) | ("search", Some(m)) => TreasureCommand::List(filter(m)),
+            ("add", Some(m)) => TreasureCommand::Add(ItemFields{
+                equipment_type: m.value_of("equipment_type").and_then(EquipmentType::from_name).unwrap(),
+                name: m.value_of("name").unwrap().to_string(),
+                effect: m.value_of("effect").unwrap().to_string(),
+                coin_value: number(m, "coin_value"),
+                id: m.value_of("id").map(|s| s.parse().expect("Invalid item id")),
+                bonus: number(m, "bonus"),
+                range: number(m, "range"),
+                dragon: m.is_present("dragon"),
             }),
-            battle_file: matches.value_of("battle_file").map(|s| s.to_string()),
-            multithread: matches.is_present("multithread"),
+            ("validate", Some(_)) => TreasureCommand::Validate,
+            _ => TreasureCommand::List(ItemFilter::default()),
         }
     }
 
+    /// Find the units, equipment and generator profile files to use, in the order of Resource::ALL
+    fn locate_resources(opts : &Options) -> Vec<ResourceLocation>{
+        Resource::ALL.iter().map(|r| {
+            let arg = match r{
+                Resource::Units => "roster_file",
+                Resource::Equipment => "treasure_file",
+                Resource::GeneratorProfile => "profile_file",
+            };
+            // resource options have no default value, so are either CLI args or from config files
+            let setting = opts.get(arg);
+            let value = |cli : bool| setting.as_ref().filter(|s| (s.origin == SettingOrigin::Cli) == cli).map(|s| s.value.as_str());
+            r.locate(value(true), value(false))
+        }).collect()
+    }
+
+    /// Parse files, filters and grouping from analyze subcommand args
+    fn parse_analysis(matches : &ArgMatches) -> Analysis{
+        let mut filters = vec![];
+        if let Some(f) = matches.value_of("faction"){
+            filters.push(Filter::faction(f));
+        }
+        if let Some(b) = matches.value_of("battle_type"){
+            filters.push(Filter::battle_type(b));
+        }
+        if let Some(r) = matches.value_of("rank"){
+            filters.push(Filter::rank(r));
+        }
+        if let Some(w) = matches.values_of("where"){
+            filters.extend(w.map(Filter::parse));
+        }
+        Analysis{
+            files: matches.values_of("files").unwrap().map(String::from).collect(),
+            filters,
+            group_by: matches.value_of("group_by").map(String::from),
+        }
+    }
+
+    /// Parse generator profile from profile file and override individual values from options
+    fn parse_profile(opts : &Options, profile_file : &str) -> GeneratorProfile{
+        let mut profile = GeneratorProfile::from_json(profile_file);
+
+        if let Some(s) = opts.value_of("equipment_ratio"){
+            profile.equipment_ratio = s.parse().expect("Invalid equipment ratio");
+        }
+        if let Some(s) = opts.value_of("rank_range"){
+            profile.rank_range = parse_range(&s);
+        }
+        if let Some(s) = opts.value_of("reinforcement_cap"){
+            profile.reinforcement_cap = s.parse().expect("Invalid reinforcement cap");
+        }
+        if let Some(s) = opts.value_of("army_size"){
+            profile.army_size = parse_range(&s);
+        }
+        if let Some(s) = opts.value_of("adv_combat_chance"){
+            profile.adv_combat_chance = s.parse().expect("Invalid advanced combat deck chance");
+        }
+        if let Some(s) = opts.value_of("faction_weights"){
+            profile.faction_weights = FactionWeights::from_list(&s);
+        }
+        if let Some(s) = opts.value_of("unit_weights"){
+            profile.unit_type_weights = Some(UnitTypeWeights::from_list(&s));
+        }
+        if let Some(s) = opts.value_of("battle_weights"){
+            profile.battle_type_weights = BattleTypeWeights::from_list(&s);
+        }
+
+        profile.validate();
+        profile
+    }
+
     /// Initialize clap App with arguments
     fn initialize_clap_app() -> App<'static, 'static>{
 
+        // Args for choosing config files and profile, usable with every subcommand
+        let config_file = Arg::with_name("config_file")
+            .long("config")
+            .help("Config file to read option values from, instead of ./autoresolve.toml and config.toml in the user config directory")
+            .value_name("FILE")
+            .global(true);
+        let config_profile = Arg::with_name("config_profile")
+            .long("config-profile")
+            .help("Named profile in the config files whose option values override the others")
+            .value_name("NAME")
+            .global(true);
+        // Arg for querying saved battle runs in a SQLite database
+        let query = Arg::with_name("query")
+            .long("query")
+            .help("Print results of a built-in query on the database from --format sqlite instead of running battles")
+            .possible_values(&BuiltinQuery::NAMES)
+            .value_name("QUERY");
+        let database = Arg::with_name("database")
+            .long("database")
+            .help("Database queried by --query, ./DataCapture/BattleData.db by default")
+            .value_name("FILE")
+            .requires("query");
+
+        // Subcommand for running battles, its args can also be used without a subcommand
+        let run = SubCommand::with_name("run")
+            .about("Run battles from a battle JSON file or random settings, the default without a subcommand")
+            .args(&Self::run_args(true));
+
+        // Subcommand for running built-in battle templates
+        let scenario = SubCommand::with_name("scenario")
+            .about("Run a built-in battle template by name, or list them if no name is given")
+            .arg(Arg::with_name("name")
+                .help("Scenario to run")
+                .value_name("NAME")
+                .possible_values(&resources::scenario_names()))
+            .args(&Self::run_args(false));
+
+        // Subcommand for comparing battle files
+        let compare = SubCommand::with_name("compare")
+            .about("Run each battle JSON file with the same seed and settings and compare their results")
+            .arg(Arg::with_name("files")
+                .help("Battle JSON files to compare")
+                .value_name("FILE").required(true).min_values(2))
+            .args(&Self::run_args(false));
+
+        // Subcommand for sweeping a field of a battle file
+        let sweep = SubCommand::with_name("sweep")
+            .about("Run a battle JSON file with the same seed and settings for each value of one of its fields and compare the results")
+            .arg(Arg::with_name("file")
+                .help("Battle JSON file to sweep")
+                .value_name("FILE").required(true))
+            .arg(Arg::with_name("field")
+                .long("field")
+                .help("Field to set, e.g. attacker.general.rank or /defender/units/0")
+                .value_name("FIELD").required(true))
+            .arg(Arg::with_name("values")
+                .long("values")
+                .help("Values to set the field to, a range e.g. 1-10, or a list e.g. true,false")
+                .value_name("VALUES").required(true))
+            .args(&Self::run_args(false));
+
+        // Subcommand for analyzing previously captured data
+        let analyze = SubCommand::with_name("analyze")
+            .about("Print outcome distributions and casualty statistics of captured CSV data")
+            .arg(Arg::with_name("files")
+                .help("Captured data files to analyze, e.g. ./DataCapture/NormalData.csv")
+                .value_name("FILE").required(true).multiple(true))
+            .arg(Arg::with_name("faction")
+                .long("faction")
+                .help("Only analyze battles with the faction on either side")
+                .value_name("FACTION"))
+            .arg(Arg::with_name("battle_type")
+                .long("battle-type")
+                .help("Only analyze battles of the type, e.g. Siege")
+                .value_name("TYPE"))
+            .arg(Arg::with_name("rank")
+                .long("rank")
+                .help("Only analyze battles where either general's rank is in the range, e.g. 3-5")
+                .value_name("RANGE"))
+            .arg(Arg::with_name("where")
+                .long("where")
+                .help("Only analyze battles meeting a condition on any column, e.g. DGenRank>=5, can be used multiple times")
+                .value_name("CONDITION").multiple(true).number_of_values(1))
+            .arg(Arg::with_name("group_by")
+                .long("group-by")
+                .help("Print statistics for each value of a column, e.g. AFaction")
+                .value_name("COLUMN"));
+
+        // Subcommand for managing the units used
+        let unit_args = |required : bool| vec![
+            Arg::with_name("faction").long("faction").help("Faction of the unit").value_name("FACTION")
+                .possible_values(&Faction::NAMES).case_insensitive(true).required(required),
+            Arg::with_name("name").long("name").help("Name of the unit").value_name("NAME").required(required),
+            Arg::with_name("unit_type").long("type").help("Type of the unit").value_name("TYPE")
+                .possible_values(&UnitType::NAMES).case_insensitive(true).required(required),
+            Arg::with_name("bonus").long("bonus").help("Autoresolve bonus of the unit").value_name("N")
+                .allow_hyphen_values(true).required(required),
+            Arg::with_name("size").long("size").help("Size of the unit").value_name("N").required(required),
+        ];
+        let roster = SubCommand::with_name("roster")
+            .about("List, show, add, edit or validate the units in the roster, listing them without a subcommand")
+            .subcommand(SubCommand::with_name("list")
+                .about("List the units, optionally only of a faction and type")
+                .arg(Arg::with_name("faction").long("faction").help("Only list units of the faction").value_name("FACTION")
+                    .possible_values(&Faction::NAMES).case_insensitive(true))
+                .arg(Arg::with_name("unit_type").long("type").help("Only list units of the type").value_name("TYPE")
+                    .possible_values(&UnitType::NAMES).case_insensitive(true)))
+            .subcommand(SubCommand::with_name("show")
+                .about("Show the units with an id and the shipped battle templates that use it")
+                .arg(Arg::with_name("id").help("Id of the unit").value_name("ID").required(true)))
+            .subcommand(SubCommand::with_name("add")
+                .about("Add a unit to the units file")
+                .args(&unit_args(true))
+                .arg(Arg::with_name("id").long("id").help("Id of the unit, the next unused id if not given").value_name("ID")))
+            .subcommand(SubCommand::with_name("edit")
+                .about("Change fields of the unit with an id in the units file")
+                .arg(Arg::with_name("id").help("Id of the unit").value_name("ID").required(true))
+                .args(&unit_args(false))
+                .arg(Arg::with_name("new_id").long("id").help("New id of the unit").value_name("ID")))
+            .subcommand(SubCommand::with_name("validate")
+                .about("Check units for duplicate ids, invalid factions and types and non-positive sizes, \
+                and report units no shipped battle template can reference"));
+
+        // Subcommand for managing the equipment used
+        let item_filters = vec![
+            Arg::with_name("equipment_type").long("type").help("Only items of the type").value_name("TYPE")
+                .possible_values(&EquipmentType::NAMES).case_insensitive(true),
+            Arg::with_name("dragon").long("dragon").help("Only dragon equipment if true, only other equipment if false")
+                .value_name("BOOL").possible_values(&["true", "false"]),
+            Arg::with_name("bonus").long("bonus").help("Only items with an autoresolve bonus in the range, e.g. 1-3 or -2--1").value_name("RANGE")
+                .allow_hyphen_values(true),
+            Arg::with_name("coin_value").long("coin-value").help("Only items with a coin value in the range, e.g. 100-200").value_name("RANGE"),
+        ];
+        let treasure = SubCommand::with_name("treasure")
+            .about("List, search, add or validate the equipment in the treasure, listing it without a subcommand")
+            .subcommand(SubCommand::with_name("list")
+                .about("List the items, optionally only those matching filters")
+                .args(&item_filters))
+            .subcommand(SubCommand::with_name("search")
+                .about("List the items whose name or effect contains text, optionally only those matching filters")
+                .arg(Arg::with_name("text").help("Text to search for, ignoring case").value_name("TEXT").required(true))
+                .args(&item_filters))
+            .subcommand(SubCommand::with_name("add")
+                .about("Add an item to the equipment file")
+                .arg(Arg::with_name("equipment_type").long("type").help("Type of the item").value_name("TYPE")
+                    .possible_values(&EquipmentType::NAMES).case_insensitive(true).required(true))
+                .arg(Arg::with_name("name").long("name").help("Name of the item").value_name("NAME").required(true))
+                .arg(Arg::with_name("effect").long("effect").help("Description of the item's effect").value_name("EFFECT")
+                    .allow_hyphen_values(true).required(true))
+                .arg(Arg::with_name("coin_value").long("coin-value").help("Coin value of the item").value_name("N").required(true))
+                .arg(Arg::with_name("bonus").long("bonus").help("Autoresolve bonus of the item").value_name("N")
+                    .allow_hyphen_values(true).default_value("0"))
+                .arg(Arg::with_name("range").long("range").help("Range of the item").value_name("N").default_value("0"))
+                .arg(Arg::with_name("dragon").long("dragon").help("Item is dragon equipment"))
+                .arg(Arg::with_name("id").long("id").help("Id of the item, the next unused id if not given").value_name("ID")))
+            .subcommand(SubCommand::with_name("validate")
+                .about("Check items for duplicate ids, invalid equipment types and invalid dragon flags"));
+
+        // Subcommand for checking battle files
+        let validate = SubCommand::with_name("validate")
+            .about("Check battle JSON files against the schema and that their units and equipment are in the roster and treasure")
+            .arg(Arg::with_name("files")
+                .help("Battle JSON files to check")
+                .value_name("FILE").required(true).multiple(true));
+
+        // Subcommand for printing the battle JSON schema
+        let schema = SubCommand::with_name("schema")
+            .about("Print the JSON Schema of battle JSON files, for editor completion and validation");
+
+        // Subcommand for printing which resource files are used
+        let resources = SubCommand::with_name("resources")
+            .about("Print which units, equipment and generator profile files are used and where they were found");
+
+        // Subcommand for printing the merged options, taking the same args as run
+        let config = SubCommand::with_name("config")
+            .about("Print the value of every option and where it was set, after merging config files, the config profile and CLI args")
+            .args(&Self::run_args(true));
+
+        // Create and return new App
+        App::new("Autoresolve")
+            .version(env!("CARGO_PKG_VERSION"))
+            .author("Lafe Wessel")
+            .about("Calculator for \"The Game\"")
+            .args(&Self::run_args(true))
+            .args(&Self::resource_args())
+            .arg(config_file)
+            .arg(config_profile)
+            .arg(query)
+            .arg(database)
+            .subcommand(run)
+            .subcommand(scenario)
+            .subcommand(compare)
+            .subcommand(sweep)
+            .subcommand(analyze)
+            .subcommand(roster)
+            .subcommand(treasure)
+            .subcommand(validate)
+            .subcommand(schema)
+            .subcommand(resources)
+            .subcommand(config)
+    }
+
+    /// Get args for overriding resource files, usable with every subcommand
+    fn resource_args() -> Vec<Arg<'static, 'static>>{
+        // Arg for specifying a different unit/roster file to use
+        let roster_file = Arg::with_name("roster_file")
+            .long("unit")
+            .help("Override input file for reading unit/roster data")
+            .value_name("FILE")
+            .global(true);
+        // Arg for specifying a different treasure file to use
+        let treasure_file = Arg::with_name("treasure_file")
+            .long("treasure")
+            .help("Override input file for reading treasure/equipment data")
+            .value_name("FILE")
+            .global(true);
+        // Arg for specifying a different generator profile to use
+        let profile_file = Arg::with_name("profile_file")
+            .long("profile")
+            .help("Generator profile JSON file for random battles")
+            .value_name("FILE")
+            .global(true);
+        // Arg for loading content packs on top of the unit and treasure files
+        let packs = Arg::with_name("packs")
+            .long("pack")
+            .help("Content pack directory of units and equipment to merge, later packs override earlier ones")
+            .value_name("DIR")
+            .multiple(true)
+            .number_of_values(1)
+            .global(true);
+        vec![roster_file, treasure_file, profile_file, packs]
+    }
+
+    /// Get args for running battles, with the args choosing the battle to run if battle_source
+    fn run_args(battle_source : bool) -> Vec<Arg<'static, 'static>>{
         // Arg for running randomly generated battles
         let rand = Arg::with_name("random")
             .short("r").long("random")
@@ -270,27 +1020,62 @@ impl Config{
             .help("Override output file for saving output data")
             .requires("save")
             .value_name("FILE");
+        // Arg for specifying format of saved runs
+        let format = Arg::with_name("format")
+            .long("format")
+            .help("Format to save battle runs in: csv rows, jsonl records, a json summary, or a sqlite database")
+            .possible_values(&["csv", "jsonl", "json", "sqlite"])
+            .default_value("csv")
+            .value_name("FORMAT");
+        // Arg for how an existing output file is treated
+        let write_mode = Arg::with_name("write_mode")
+            .long("write-mode")
+            .help("How to save to an existing output file: append after its battles, overwrite them, \
+            or rotate it to a numbered file and start a new one")
+            .possible_values(&WriteMode::NAMES)
+            .default_value("append")
+            .value_name("MODE");
         // Arg for specifying how many runs to perform
         let count = Arg::with_name("run_count")
             .short("c").long("count")
             .help("Number of battle runs to perform")
             .value_name("COUNT").default_value("1");
+        // Args for running until outcome probabilities are precise enough
+        let precision = Arg::with_name("precision")
+            .long("precision")
+            .help("Run battles until the 95% confidence interval of the target probabilities is at most WIDTH wide, \
+            replaces --count")
+            .value_name("WIDTH");
+        let precision_target = Arg::with_name("precision_target")
+            .long("precision-target")
+            .help("Probabilities --precision is measured on: the attacker's win probability or every battle outcome")
+            .value_name("TARGET")
+            .possible_values(&PrecisionTarget::NAMES)
+            .default_value("win");
+        let max_count = Arg::with_name("max_count")
+            .long("max-count")
+            .help("Maximum number of battle runs when using --precision")
+            .value_name("N")
+            .default_value("1000000");
         // Arg for specifying which type of battle to run
         let battle_type = Arg::with_name("battle_type")
             .short("b").long("battle")
-            .help("Battle type to run. 1:Normal,2:Siege,3:Raid,4:Naval,5:Monster")
+            .help("Battle type to run. 1:Normal,2:Siege,3:Raid,4:Naval,5:Monster, or in battle type notation, \
+            e.g. \"Siege rams=3 towers=4 defenses=StoneWall\"")
             .value_name("TYPE")
             .conflicts_with("battle_file");
-        // Arg for specifying a different unit/roster file to use
-        let roster_file = Arg::with_name("roster_file")
-            .long("unit")
-            .help("Override input file for reading unit/roster data")
-            .value_name("FILE");
-        // Arg for specifying a different treasure file to use
-        let treasure_file = Arg::with_name("treasure_file")
-            .long("treasure")
-            .help("Override input file for reading treasure/equipment data")
-            .value_name("FILE");
+        // Args for giving the armies to run in army notation
+        let attacker = Arg::with_name("attacker")
+            .long("attacker")
+            .help("Attacker in army notation, e.g. \"Beladimir: 3x Beladimir Spearmen; general r3 weapon=10\", \
+            a random defender is used if --defender isn't given")
+            .value_name("ARMY")
+            .conflicts_with_all(&["random", "battle_file"]);
+        let defender = Arg::with_name("defender")
+            .long("defender")
+            .help("Defender in army notation, a random attacker is used if --attacker isn't given")
+            .value_name("ARMY")
+            .conflicts_with_all(&["random", "battle_file"]);
         // Arg for specifying situation file to run
         let battle_file = Arg::with_name("battle_file")
             .short("j").long("json")
@@ -300,22 +1085,88 @@ impl Config{
         let multithread = Arg::with_name("multithread")
             .short("m").long("multithread")
             .help("Utilize multiple threads for running calculations");
+        let threads = Arg::with_name("threads")
+            .long("threads")
+            .help("Number of threads for running calculations, results are the same for any number of threads")
+            .value_name("N");
+        // Arg for printing progress of runs
+        let progress = Arg::with_name("progress")
+            .long("progress")
+            .help("Print how many battle runs have been resolved while running");
+        // Arg for only sampling battle outcomes
+        let fast = Arg::with_name("fast_outcomes")
+            .long("fast")
+            .help("Only sample battle outcomes, much faster but results differ from a run without --fast using the same seed")
+            .conflicts_with_all(&["save", "log", "export_dir"]);
+        // Args for overriding random battle generation
+        let equipment_ratio = Arg::with_name("equipment_ratio")
+            .long("equipment-ratio")
+            .help("1 in N chance for each piece of random general equipment")
+            .value_name("N");
+        let rank_range = Arg::with_name("rank_range")
+            .long("rank-range")
+            .help("Range of random general ranks")
+            .value_name("MIN-MAX");
+        let reinforcement_cap = Arg::with_name("reinforcement_cap")
+            .long("reinforcement-cap")
+            .help("Maximum random reinforcements")
+            .value_name("N");
+        let army_size = Arg::with_name("army_size")
+            .long("army-size")
+            .help("Range of random army sizes")
+            .value_name("MIN-MAX");
+        let adv_combat_chance = Arg::with_name("adv_combat_chance")
+            .long("adv-combat-chance")
+            .help("Probability of a random player having the advanced combat deck")
+            .value_name("P");
+        let faction_weights = Arg::with_name("faction_weights")
+            .long("faction-weights")
+            .help("Random faction weights. Rebel,Beladimir,Lerastir,Menoriad")
+            .value_name("WEIGHTS");
+        let unit_weights = Arg::with_name("unit_weights")
+            .long("unit-weights")
+            .help("Random unit type weights. Melee,Cavalry,Ranged")
+            .value_name("WEIGHTS");
+        let battle_weights = Arg::with_name("battle_weights")
+            .long("battle-weights")
+            .help("Random battle type weights. Normal,Siege,Raid,Naval,Monster")
+            .value_name("WEIGHTS");
 
-        // Create and return new App
-        App::new("Autoresolve")
-            .version("1.0.0")
-            .author("Lafe Wessel")
-            .about("Calculator for \"The Game\"")
-            .arg(rand)
-            .arg(save)
-            .arg(output_file)
-            .arg(count)
-            .arg(battle_type)
-            .arg(roster_file)
-            .arg(treasure_file)
-            .arg(battle_file)
-            .arg(log)
-            .arg(multithread)
+        // Args for only generating balanced random battles
+        let balance_margin = Arg::with_name("balance_margin")
+            .long("balanced")
+            .help("Only use random battles whose non-random total is within MARGIN of a draw")
+            .value_name("MARGIN");
+        let max_attempts = Arg::with_name("max_attempts")
+            .long("max-attempts")
+            .help("Maximum random battles drawn per run when using --balanced")
+            .value_name("N").default_value("1000");
+
+        // Args for exporting generated battles to replayable JSON
+        let export_dir = Arg::with_name("export_dir")
+            .long("export")
+            .help("Write each generated battle to a JSON file in DIR that can be replayed with --json")
+            .value_name("DIR");
+        let export_filter = Arg::with_name("export_filter")
+            .long("export-filter")
+            .help("Only export battles with these outcomes, e.g. DecisiveVictory,CrushingDefeat")
+            .value_name("OUTCOMES")
+            .requires("export_dir");
+
+        // Arg for seeding random number generation
+        let seed = Arg::with_name("seed")
+            .long("seed")
+            .help("Seed for random number generation, a random seed is used and recorded if not provided")
+            .value_name("SEED");
+
+        let mut args = vec![save, output_file, format, write_mode, count, log, multithread, threads, precision,
+                            precision_target, max_count, progress, fast, equipment_ratio, rank_range, reinforcement_cap,
+                            army_size, adv_combat_chance, faction_weights, unit_weights, battle_weights, balance_margin,
+                            max_attempts, export_dir, export_filter, seed];
+        if battle_source{
+            args.extend(vec![rand, battle_type, battle_file, attacker, defender]);
+        }
+        args
     }
 
 }
@@ -324,14 +1175,22 @@ impl Config{
 mod cli_tests{
     use super::*;
     use crate::monster::MonsterType;
-    use crate::battle::BattleType;
+    use crate::battle::{BattleType, TownDefenses};
+    use crate::resources::ResourceOrigin;
+    use crate::pack::EntryRef;
+    use crate::testing::TestDir;
+
+    /// Parse args without reading config files, so results don't depend on the machine's config
+    fn parse_args(matches : &ArgMatches) -> Config{
+        Config::parse_with_config(matches, &ConfigFile::default())
+    }
 
     #[test]
     fn test_default_cli_options(){
         let app = Config::initialize_clap_app();
         let args = vec![""];
         let matches = app.get_matches_from(args);
-        let cfg = Config::parse_app_arguments(&matches);
+        let cfg = parse_args(&matches);
         assert!(!cfg.save_data);
         assert!(!cfg.use_rand);
         assert!(!cfg.log);
@@ -340,6 +1199,11 @@ mod cli_tests{
         assert_eq!(None,cfg.output_file_override);
         assert_eq!(None,cfg.battle_file);
         assert!(!cfg.multithread);
+        assert_eq!(GeneratorProfile::default(), cfg.profile);
+        assert_eq!(None, cfg.balance);
+        assert_eq!(None, cfg.export_dir);
+        assert_eq!(None, cfg.export_filter);
+        assert_eq!(None, cfg.seed);
     }
 
     #[test]
@@ -347,7 +1211,7 @@ mod cli_tests{
         let app = Config::initialize_clap_app();
         let args = vec!["","-r","-s","-f","test1","-c","2","-m","-b","5","--unit","./ResourceFiles/units.csv","--treasure","./ResourceFiles/equipment.csv","-l"];
         let matches = app.get_matches_from(args);
-        let cfg = Config::parse_app_arguments(&matches);
+        let cfg = parse_args(&matches);
         assert!(cfg.save_data);
         assert!(cfg.use_rand);
         assert!(cfg.log);
@@ -358,14 +1222,381 @@ mod cli_tests{
         assert!(cfg.multithread);
     }
 
+    #[test]
+    fn test_seed_option(){
+        let app = Config::initialize_clap_app();
+        let args = vec!["","-r","--seed","12345"];
+        let matches = app.get_matches_from(args);
+        let cfg = parse_args(&matches);
+        assert_eq!(Some(12345), cfg.seed);
+    }
+
     #[test]
     fn test_json_template(){
         let app = Config::initialize_clap_app();
         let args = vec!["","--json","./ResourceFiles/normal_battle_template.json"];
         let matches = app.get_matches_from(args);
-        let cfg = Config::parse_app_arguments(&matches);
+        let cfg = parse_args(&matches);
         assert_eq!(Some("./ResourceFiles/normal_battle_template.json".to_string()),cfg.battle_file);
     }
 
+    #[test]
+    fn test_army_options(){
+        let parse = |args : Vec<&str>| parse_args(&Config::initialize_clap_app().get_matches_from(args));
+        let cfg = parse(vec!["","--attacker","Beladimir: 3x Beladimir Spearmen; general r3 weapon=10","-b","siege rams=2 defenses=StoneWall"]);
+        assert_eq!(Some(BattleType::Siege{ rams: 2, catapults: 0, siege_towers: 0, defenses: TownStats::new(0, TownDefenses::StoneWall) }),
+                   cfg.battle_type);
+        let battle : BattleJSONObject = serde_json::from_value(cfg.get_army_battle().unwrap()).unwrap();
+        let [attacker, defender] = battle.get_players();
+        assert_eq!(Some(3), attacker.and_then(|a| a.get_unit_refs()).map(|r| r.len()));
+        assert!(defender.is_none());
+        assert!(battle.has_random());
+
+        let cfg = parse(vec!["","run","--attacker","Rebel: 1","--defender","Menoriad: 2x 30; adv"]);
+        let battle : BattleJSONObject = serde_json::from_value(cfg.get_army_battle().unwrap()).unwrap();
+        assert!(!battle.has_random());
+        assert_eq!(Some(BattleType::Normal), battle.get_battle_type());
+        assert!(parse(vec![""]).get_army_battle().is_none());
+
+        // random battles keep the parameters of a battle type given in notation
+        let cfg = parse(vec!["","run","-r","-b","Siege rams=3 catapults=0 towers=4 defenses=StoneWall supplies=2"]);
+        let siege = BattleType::Siege{ rams: 3, catapults: 0, siege_towers: 4, defenses: TownStats::new(2, TownDefenses::StoneWall) };
+        let battle : BattleJSONObject = serde_json::from_value(cfg.get_army_battle().unwrap()).unwrap();
+        assert!(battle.get_players().iter().all(|p| p.is_none()));
+        let source = cfg.create_battle_source(&RunTarget{ label: None, battle_file: None, battle_json: cfg.get_army_battle() });
+        assert_eq!(siege, source.next_battle(&cfg.roster, &cfg.treasure, &cfg.profile).get_battle_type());
+        assert!(parse(vec!["","run","-r","-b","2"]).get_army_battle().is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid defender army \"Menoriad 2x 30\": expected FACTION: UNITS")]
+    fn test_invalid_army_option(){
+        parse_args(&Config::initialize_clap_app().get_matches_from(vec!["","--defender","Menoriad 2x 30"]));
+    }
+
+    #[test]
+    fn test_generator_profile_options(){
+        let app = Config::initialize_clap_app();
+        let args = vec!["","-r","--profile","./ResourceFiles/generator_profile_template.json",
+                        "--equipment-ratio","2","--rank-range","2-5","--reinforcement-cap","1","--army-size","4-8",
+                        "--adv-combat-chance","0.25","--faction-weights","1,2,3,4","--unit-weights","3,1,1",
+                        "--battle-weights","1,0,0,0,0"];
+        let matches = app.get_matches_from(args);
+        let cfg = parse_args(&matches);
+        assert_eq!(2, cfg.profile.equipment_ratio);
+        assert_eq!((2,5), cfg.profile.rank_range);
+        assert_eq!(1, cfg.profile.reinforcement_cap);
+        assert_eq!((4,8), cfg.profile.army_size);
+        assert_eq!(0.25, cfg.profile.adv_combat_chance);
+        assert_eq!(FactionWeights::from_list("1,2,3,4"), cfg.profile.faction_weights);
+        assert_eq!(Some(UnitTypeWeights::from_list("3,1,1")), cfg.profile.unit_type_weights);
+        assert_eq!(BattleTypeWeights::from_list("1,0,0,0,0"), cfg.profile.battle_type_weights);
+    }
+
+    #[test]
+    fn test_balance_options(){
+        let app = Config::initialize_clap_app();
+        let args = vec!["","-r","--balanced","5","--max-attempts","20"];
+        let matches = app.get_matches_from(args);
+        let cfg = parse_args(&matches);
+        assert_eq!(Some(BalanceSettings{margin: 5.0, max_attempts: 20}), cfg.balance);
+    }
+
+    #[test]
+    fn test_export_options(){
+        let app = Config::initialize_clap_app();
+        let args = vec!["","-r","--export","./DataCapture/exports","--export-filter","Draw,CloseDefeat"];
+        let matches = app.get_matches_from(args);
+        let cfg = parse_args(&matches);
+        assert_eq!(Some("./DataCapture/exports".to_string()), cfg.export_dir);
+        assert_eq!(Some(vec![BattleOutcome::Draw, BattleOutcome::CloseDefeat]), cfg.export_filter);
+    }
+
+    #[test]
+    fn test_format_option(){
+        let app = Config::initialize_clap_app();
+        let matches = app.get_matches_from(vec![""]);
+        assert_eq!(OutputFormat::Csv, parse_args(&matches).format);
+
+        let app = Config::initialize_clap_app();
+        let matches = app.get_matches_from(vec!["","-s","--format","jsonl"]);
+        assert_eq!(OutputFormat::JsonLines, parse_args(&matches).format);
+
+        let app = Config::initialize_clap_app();
+        assert_eq!(WriteMode::Append, parse_args(&app.get_matches_from(vec!["","-s"])).write_mode);
+        let app = Config::initialize_clap_app();
+        let matches = app.get_matches_from(vec!["","-s","--write-mode","rotate"]);
+        assert_eq!(WriteMode::Rotate, parse_args(&matches).write_mode);
+    }
+
+    #[test]
+    fn test_analyze_subcommand(){
+        let app = Config::initialize_clap_app();
+        let args = vec!["","analyze","a.csv","b.csv","--faction","Rebel","--rank","2-4","--where","DGenRank>=5","--where","Outcome!=Draw","--group-by","BattleType"];
+        let matches = app.get_matches_from(args);
+        let a = match parse_args(&matches).command{
+            Command::Analyze(a) => a,
+            c => panic!("Expected analyze command, got {:?}", c),
+        };
+        assert_eq!(vec!["a.csv", "b.csv"], a.files);
+        assert_eq!(4, a.filters.len());
+        assert_eq!(Filter::faction("Rebel"), a.filters[0]);
+        assert_eq!(Filter::rank("2-4"), a.filters[1]);
+        assert_eq!(Filter::parse("Outcome!=Draw"), a.filters[3]);
+        assert_eq!(Some(String::from("BattleType")), a.group_by);
+    }
+
+    #[test]
+    fn test_thread_options(){
+        let app = Config::initialize_clap_app();
+        let cfg = parse_args(&app.get_matches_from(vec![""]));
+        assert_eq!(1, cfg.get_thread_count());
+        assert!(!cfg.progress);
+
+        let app = Config::initialize_clap_app();
+        let cfg = parse_args(&app.get_matches_from(vec!["","-m"]));
+        assert_eq!(num_cpus::get(), cfg.get_thread_count());
+
+        let app = Config::initialize_clap_app();
+        let cfg = parse_args(&app.get_matches_from(vec!["","--threads","3","--progress"]));
+        assert_eq!(Some(3), cfg.threads);
+        assert_eq!(3, cfg.get_thread_count());
+        assert!(cfg.progress);
+    }
+
+    #[test]
+    fn test_fast_outcomes(){
+        let app = Config::initialize_clap_app();
+        assert!(!parse_args(&app.get_matches_from(vec![""])).outcomes_only());
+
+        let app = Config::initialize_clap_app();
+        assert!(parse_args(&app.get_matches_from(vec!["","--fast"])).outcomes_only());
+
+        let app = Config::initialize_clap_app();
+        assert!(app.get_matches_from_safe(vec!["","--fast","-s"]).is_err());
+    }
+
+    #[test]
+    fn test_precision_options(){
+        let app = Config::initialize_clap_app();
+        let cfg = parse_args(&app.get_matches_from(vec!["","-c","5"]));
+        assert_eq!(None, cfg.precision);
+        assert_eq!(5, cfg.get_run_count());
+
+        let app = Config::initialize_clap_app();
+        let cfg = parse_args(&app.get_matches_from(vec!["","--precision","0.02"]));
+        assert_eq!(Some(Precision{ width: 0.02, target: PrecisionTarget::WinProbability, max_count: 1000000 }), cfg.precision);
+        assert_eq!(1000000, cfg.get_run_count());
+
+        let app = Config::initialize_clap_app();
+        let matches = app.get_matches_from(vec!["","--precision","0.05","--precision-target","outcomes","--max-count","2000"]);
+        let cfg = parse_args(&matches);
+        assert_eq!(Some(PrecisionTarget::Outcomes), cfg.precision.map(|p| p.target));
+        assert_eq!(2000, cfg.get_run_count());
+    }
+
+    #[test]
+    fn test_resources_subcommand(){
+        let app = Config::initialize_clap_app();
+        let cfg = parse_args(&app.get_matches_from(vec!["","--unit","./ResourceFiles/units.csv","resources"]));
+        assert_eq!(Command::Resources, cfg.command);
+        assert_eq!(3, cfg.resources.len());
+        assert_eq!(ResourceOrigin::Flag, cfg.resources[0].origin);
+        assert_eq!("./ResourceFiles/units.csv", cfg.roster.get_file_path());
+        assert_eq!(cfg.resources[1].path, cfg.treasure.get_file_path());
+    }
+
+    #[test]
+    fn test_run_aliases(){
+        // top level flags are the same as the run subcommand
+        let parse = |args : Vec<&str>| parse_args(&Config::initialize_clap_app().get_matches_from(args));
+        let top = parse(vec!["","-r","-b","2","-c","7","--seed","3","-m"]);
+        let run = parse(vec!["","run","-r","-b","2","-c","7","--seed","3","-m"]);
+        assert_eq!(Command::Run, top.command);
+        assert_eq!(Command::Run, run.command);
+        assert_eq!((top.use_rand, top.run_count, top.seed, top.multithread), (run.use_rand, run.run_count, run.seed, run.multithread));
+        assert_eq!(top.battle_type, run.battle_type);
+
+        // resource flags can be given before or after the subcommand
+        let run = parse(vec!["","run","--unit","./ResourceFiles/units.csv"]);
+        assert_eq!("./ResourceFiles/units.csv", run.roster.get_file_path());
+        let run = parse(vec!["","--unit","./ResourceFiles/units.csv","run"]);
+        assert_eq!("./ResourceFiles/units.csv", run.roster.get_file_path());
+    }
+
+    #[test]
+    fn test_subcommands(){
+        let parse = |args : Vec<&str>| parse_args(&Config::initialize_clap_app().get_matches_from(args));
+        let cfg = parse(vec!["","scenario","siege","-c","4"]);
+        assert_eq!(Command::Run, cfg.command);
+        assert_eq!(Some(String::from("embedded:siege_battle_template.json")), cfg.battle_file);
+        assert_eq!(4, cfg.run_count);
+        assert_eq!(Command::Scenarios, parse(vec!["","scenario"]).command);
+
+        let cfg = parse(vec!["","compare","a.json","b.json","-c","10"]);
+        assert_eq!(Command::Compare(vec![String::from("a.json"), String::from("b.json")]), cfg.command);
+        assert_eq!(10, cfg.run_count);
+
+        let cfg = parse(vec!["","sweep","a.json","--field","attacker.general.rank","--values","1-3"]);
+        assert_eq!(Command::Sweep(Sweep{ file: String::from("a.json"), field: String::from("attacker.general.rank"),
+            values: Sweep::parse_values("1-3") }), cfg.command);
+
+        assert_eq!(Command::Roster(RosterCommand::List(None, None)), parse(vec!["","roster"]).command);
+        assert_eq!(Command::Treasure(TreasureCommand::List(ItemFilter::default())), parse(vec!["","treasure"]).command);
+        assert_eq!(Command::Validate(vec![String::from("a.json")]), parse(vec!["","validate","a.json"]).command);
+        assert_eq!(Command::Schema, parse(vec!["","schema"]).command);
+    }
+
+    #[test]
+    fn test_roster_subcommands(){
+        let parse = |args : Vec<&str>| parse_args(&Config::initialize_clap_app().get_matches_from(args));
+        assert_eq!(Command::Roster(RosterCommand::List(Some(Faction::Rebel), Some(UnitType::Cavalry))),
+                   parse(vec!["","roster","list","--faction","rebel","--type","Cavalry"]).command);
+        assert_eq!(Command::Roster(RosterCommand::Show(4)), parse(vec!["","roster","show","4"]).command);
+        assert_eq!(Command::Roster(RosterCommand::Validate), parse(vec!["","roster","validate"]).command);
+
+        let cfg = parse(vec!["","roster","add","--faction","Menoriad","--name","Guards","--type","Melee","--bonus","-1","--size","4",
+                             "--unit","./ResourceFiles/units.csv"]);
+        assert_eq!(Command::Roster(RosterCommand::Add(UnitFields{ faction: Some(Faction::Menoriad), name: Some(String::from("Guards")),
+            unit_type: Some(UnitType::Melee), bonus: Some(-1), size: Some(4), id: None })), cfg.command);
+        // global args given after nested subcommands are used
+        assert_eq!("./ResourceFiles/units.csv", cfg.roster.get_file_path());
+
+        assert_eq!(Command::Roster(RosterCommand::Edit(3, UnitFields{ size: Some(2), id: Some(40), ..UnitFields::default() })),
+                   parse(vec!["","roster","edit","3","--size","2","--id","40"]).command);
+    }
+
+    #[test]
+    fn test_treasure_subcommands(){
+        let parse = |args : Vec<&str>| parse_args(&Config::initialize_clap_app().get_matches_from(args));
+        let filter = ItemFilter{ text: Some(String::from("armor")), equipment_type: Some(EquipmentType::Armor), dragon: Some(false),
+            bonus: Some((0, 2)), coin_value: Some((100, 100)) };
+        assert_eq!(Command::Treasure(TreasureCommand::List(filter)),
+                   parse(vec!["","treasure","search","armor","--type","armor","--dragon","false","--bonus","0-2","--coin-value","100"]).command);
+        assert_eq!(Command::Treasure(TreasureCommand::List(ItemFilter{ bonus: Some((-2, -1)), ..ItemFilter::default() })),
+                   parse(vec!["","treasure","list","--bonus","-2--1"]).command);
+        assert_eq!(Command::Treasure(TreasureCommand::List(ItemFilter{ dragon: Some(true), ..ItemFilter::default() })),
+                   parse(vec!["","treasure","list","--dragon","true"]).command);
+        assert_eq!(Command::Treasure(TreasureCommand::Validate), parse(vec!["","treasure","validate"]).command);
+        assert_eq!(Command::Treasure(TreasureCommand::Add(ItemFields{ equipment_type: EquipmentType::Banner, name: String::from("Flag"),
+            effect: String::from("-1 D Att"), coin_value: 75, id: None, bonus: 0, range: 0, dragon: true })),
+                   parse(vec!["","treasure","add","--type","Banner","--name","Flag","--effect","-1 D Att","--coin-value","75","--dragon"]).command);
+        assert_eq!(Command::Treasure(TreasureCommand::Add(ItemFields{ equipment_type: EquipmentType::Weapon, name: String::from("Rusty Blade"),
+            effect: String::from("-1"), coin_value: 5, id: None, bonus: -1, range: 0, dragon: false })),
+                   parse(vec!["","treasure","add","--type","Weapon","--name","Rusty Blade","--effect","-1","--coin-value","5","--bonus","-1"]).command);
+    }
+
+    #[test]
+    fn test_content_packs(){
+        let dir = TestDir::new("content_packs");
+        dir.write("frost/units.csv", "Faction,Name,Type,Autoresolve Bonus,Unit Size,id\n\
+        Rebel,Frost Giants,Melee,9,2,1\nRebel,Frost Rivermen,Melee,5,4,base:1\n");
+        dir.write("frost/equipment.csv", "Type,Name,Effect,Coin Value,Index,Autoresolve Bonus,Range,Dragon\n\
+        Armor,Frost Armor,+2 R Def,200,1,0,0,false\n");
+        dir.write("ice/pack.toml", "namespace = \"glacier\"\ndisable_units = [\"frost:1\"]\n");
+        dir.write("ice/units.csv", "Faction,Name,Type,Autoresolve Bonus,Unit Size,id\n\
+        Rebel,Ice Rivermen,Melee,6,4,base:1\n");
+        let (frost, ice) = (&dir.file("frost"), &dir.file("ice"));
+        let cfg = parse_args(&Config::initialize_clap_app().get_matches_from(vec!["","run","--pack",frost,"--pack",ice]));
+
+        assert_eq!(vec!["frost", "glacier"], cfg.packs.iter().map(|p| p.namespace.as_str()).collect::<Vec<&str>>());
+        assert_eq!(vec!["base", "frost", "glacier"], cfg.roster.get_namespaces());
+        assert_eq!("Ice Rivermen", cfg.roster.get_unit_by_ref(&EntryRef::Key(String::from("base:1"))).get_name());
+        assert!(cfg.roster.get_units_by_id(39).is_empty());
+        assert_eq!("Frost Armor", cfg.treasure.get_item_by_ref(&EntryRef::Key(String::from("frost:1")), EquipmentType::Armor).unwrap().unwrap().get_name());
+        assert_eq!(Ok(None), cfg.treasure.get_item_by_ref(&EntryRef::Id(0), EquipmentType::Armor));
+        let pack = cfg.settings.iter().find(|(k, _)| k == "pack").unwrap();
+        assert_eq!(format!("{},{}", frost, ice), pack.1.value);
+    }
+
+    #[test]
+    fn test_config_file(){
+        let config = ConfigFile::parse("count = 10000\nmultithread = true\nunit = \"./ResourceFiles/units.csv\"\n\
+        json = \"embedded:siege_battle_template.json\"\n[profiles.quick]\ncount = 100\nmultithread = false\n", "test.toml");
+        let parse = |args : Vec<&str>| Config::parse_with_config(&Config::initialize_clap_app().get_matches_from(args), &config);
+        let cfg = parse(vec![""]);
+        assert_eq!((10000, true), (cfg.run_count, cfg.multithread));
+        assert_eq!(Some(String::from("embedded:siege_battle_template.json")), cfg.battle_file);
+        assert_eq!(ResourceOrigin::ConfigFile, cfg.resources[0].origin);
+        assert_eq!(vec!["test.toml"], cfg.config_files);
+
+        // profile values override the file's, CLI args override both
+        let cfg = parse(vec!["","--config-profile","quick"]);
+        assert_eq!((100, false), (cfg.run_count, cfg.multithread));
+        let cfg = parse(vec!["","run","-c","5","--config-profile","quick"]);
+        assert_eq!((5, Some(String::from("quick"))), (cfg.run_count, cfg.config_profile.clone()));
+        let count = cfg.settings.iter().find(|(k, _)| k == "count").map(|(_, s)| s.origin.clone());
+        assert_eq!(Some(SettingOrigin::Cli), count);
+        let format = cfg.settings.iter().find(|(k, _)| k == "format").map(|(_, s)| s.clone());
+        assert_eq!(Some(Setting{ value: String::from("csv"), origin: SettingOrigin::Default }), format);
+
+        // CLI args conflicting with config values replace them
+        let cfg = parse(vec!["","-r"]);
+        assert!(cfg.use_rand);
+        assert_eq!(None, cfg.battle_file);
+
+        assert_eq!(Command::ShowConfig, parse(vec!["","config","-c","3"]).command);
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown option counts (from test.toml)")]
+    fn test_unknown_config_option(){
+        let config = ConfigFile::parse("counts = 100", "test.toml");
+        Config::parse_with_config(&Config::initialize_clap_app().get_matches_from(vec![""]), &config);
+    }
+
+    #[test]
+    fn test_config_option_table(){
+        // CLI args setting an option, with a value accepted by the arg and args it requires
+        let cli_args = |key : &str, flag : bool| {
+            let mut args = vec![format!("--{}", key)];
+            if !flag{
+                args.push(String::from(match key{ "format" => "csv", "write-mode" => "append", "precision-target" => "win", _ => "1" }));
+            }
+            match key{
+                "file" => args.push(String::from("-s")),
+                "export-filter" => args.extend(vec![String::from("--export"), String::from("1")]),
+                _ => (),
+            }
+            args
+        };
+        // each key is the long flag of its arg
+        for (key, name, flag) in config_options(){
+            let args = std::iter::once(String::new()).chain(cli_args(key, *flag));
+            let m = Config::initialize_clap_app().get_matches_from_safe(args).unwrap_or_else(|e| panic!("--{}: {}", key, e));
+            assert!(m.is_present(name), "--{} doesn't set {}", key, name);
+            assert_eq!(*flag, m.value_of(name).is_none(), "--{} is{} a flag", key, if *flag{ " not" } else { "" });
+        }
+        // each pair of conflicting options conflict
+        let key = |n : &str| config_options().find(|(_, a, _)| *a == n).map(|(k, _, f)| (*k, *f)).unwrap();
+        for (a, b) in CONFLICTING_OPTIONS{
+            let args = std::iter::once(String::new()).chain(cli_args(key(a).0, key(a).1)).chain(cli_args(key(b).0, key(b).1));
+            let e = Config::initialize_clap_app().get_matches_from_safe(args).unwrap_err();
+            assert_eq!(clap::ErrorKind::ArgumentConflict, e.kind, "{} and {}", a, b);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "conflict (from test.toml)")]
+    fn test_conflicting_config_options(){
+        let config = ConfigFile::parse("random = true\njson = \"embedded:siege_battle_template.json\"", "test.toml");
+        Config::parse_with_config(&Config::initialize_clap_app().get_matches_from(vec![""]), &config);
+    }
+
+    #[test]
+    fn test_query_option(){
+        let app = Config::initialize_clap_app();
+        let matches = app.get_matches_from(vec!["","--query","faction-win-rate","--database","./DataCapture/test.db"]);
+        let cfg = parse_args(&matches);
+        assert_eq!(Command::Query(BuiltinQuery::FactionWinRate, String::from("./DataCapture/test.db")), cfg.command);
+
+        let app = Config::initialize_clap_app();
+        let cfg = parse_args(&app.get_matches_from(vec!["","--query","runs"]));
+        assert_eq!(Command::Query(BuiltinQuery::Runs, String::from(DEFAULT_DATABASE)), cfg.command);
+
+        // the output file is only used when saving
+        assert!(Config::initialize_clap_app().get_matches_from_safe(vec!["","-f","out.csv"]).is_err());
+    }
+
 }
 
@@ -0,0 +1,388 @@
+use crate::battle::{BattleData, BattleResults, BattleOutcome};
+use crate::equipment::EquipmentType;
+use crate::manifest::RunManifest;
+use rusqlite::{params, Connection};
+use rusqlite::types::ValueRef;
+
+/// Default location of the battle database
+pub const DEFAULT_DATABASE : &str = "./DataCapture/BattleData.db";
+
+/// Tables for normalized battle data, every battle is keyed by the run id of its manifest
+const SCHEMA : &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    run_id TEXT PRIMARY KEY,
+    timestamp INTEGER NOT NULL,
+    seed TEXT NOT NULL,
+    tool_version TEXT NOT NULL,
+    ruleset TEXT NOT NULL,
+    run_count INTEGER NOT NULL,
+    manifest TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS battles (
+    battle_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    run_id TEXT NOT NULL REFERENCES runs(run_id),
+    run_index INTEGER NOT NULL,
+    battle_type TEXT NOT NULL,
+    battle_parameters TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    winner TEXT NOT NULL,
+    attacker_random REAL NOT NULL,
+    defender_random REAL NOT NULL,
+    total REAL NOT NULL
+);
+CREATE TABLE IF NOT EXISTS players (
+    battle_id INTEGER NOT NULL REFERENCES battles(battle_id),
+    side TEXT NOT NULL,
+    faction TEXT NOT NULL,
+    general_rank INTEGER NOT NULL,
+    general_bonus INTEGER NOT NULL,
+    armor_bonus INTEGER NOT NULL,
+    weapon_bonus INTEGER NOT NULL,
+    follower_bonus INTEGER NOT NULL,
+    banner_bonus INTEGER NOT NULL,
+    trinket_bonus INTEGER NOT NULL,
+    adv_combat INTEGER NOT NULL,
+    begin_unit_bonus INTEGER NOT NULL,
+    begin_soldiers INTEGER NOT NULL,
+    end_unit_bonus INTEGER NOT NULL,
+    end_soldiers INTEGER NOT NULL,
+    total_units INTEGER NOT NULL,
+    reinforcements INTEGER NOT NULL,
+    PRIMARY KEY (battle_id, side)
+);
+CREATE TABLE IF NOT EXISTS player_units (
+    battle_id INTEGER NOT NULL REFERENCES battles(battle_id),
+    side TEXT NOT NULL,
+    unit_name TEXT NOT NULL,
+    count INTEGER NOT NULL,
+    PRIMARY KEY (battle_id, side, unit_name)
+);
+CREATE TABLE IF NOT EXISTS casualties (
+    battle_id INTEGER NOT NULL REFERENCES battles(battle_id),
+    side TEXT NOT NULL,
+    general_state TEXT NOT NULL,
+    upgrades INTEGER NOT NULL,
+    soldier_casualties INTEGER NOT NULL,
+    unit_casualties INTEGER NOT NULL,
+    PRIMARY KEY (battle_id, side)
+);
+CREATE TABLE IF NOT EXISTS treasure (
+    battle_id INTEGER NOT NULL REFERENCES battles(battle_id),
+    side TEXT NOT NULL,
+    equipment_id INTEGER NOT NULL,
+    equipment_type TEXT NOT NULL,
+    name TEXT NOT NULL,
+    autoresolve_bonus INTEGER NOT NULL,
+    coin_value INTEGER NOT NULL,
+    PRIMARY KEY (battle_id, side)
+);
+";
+
+/// Queries that can be run against the battle database
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BuiltinQuery{
+    /// Battles fought and won by each faction on either side
+    FactionWinRate,
+    /// Battles won by the attacker for each battle type
+    BattleTypeWinRate,
+    /// Mean casualties of each side for each battle type
+    CasualtiesByBattleType,
+    /// Count of each outcome for each battle type
+    Outcomes,
+    /// Battles saved by each run
+    Runs,
+}
+
+impl BuiltinQuery{
+    /// Names of all queries
+    pub const NAMES : [&'static str; 5] = ["faction-win-rate", "battle-type-win-rate", "casualties-by-battle-type", "outcomes", "runs"];
+
+    /// Get enum from name
+    pub fn from_name(name : &str) -> Self{
+        match name{
+            "faction-win-rate" => BuiltinQuery::FactionWinRate,
+            "battle-type-win-rate" => BuiltinQuery::BattleTypeWinRate,
+            "casualties-by-battle-type" => BuiltinQuery::CasualtiesByBattleType,
+            "outcomes" => BuiltinQuery::Outcomes,
+            "runs" => BuiltinQuery::Runs,
+            _ => panic!("Invalid query {}, expected one of {}", name, Self::NAMES.join(", "))
+        }
+    }
+
+    /// Get SQL for the query
+    fn get_sql(&self) -> &str{
+        match self{
+            BuiltinQuery::FactionWinRate => "
+                SELECT p.faction, COUNT(*) AS battles, SUM(b.winner = p.side) AS wins,
+                       ROUND(AVG(b.winner = p.side), 4) AS win_rate
+                FROM players p JOIN battles b ON b.battle_id = p.battle_id
+                GROUP BY p.faction ORDER BY win_rate DESC",
+            BuiltinQuery::BattleTypeWinRate => "
+                SELECT battle_type, COUNT(*) AS battles, SUM(winner = 'Attacker') AS attacker_wins,
+                       ROUND(AVG(winner = 'Attacker'), 4) AS attacker_win_rate
+                FROM battles GROUP BY battle_type ORDER BY battle_type",
+            BuiltinQuery::CasualtiesByBattleType => "
+                SELECT b.battle_type, c.side, COUNT(*) AS battles,
+                       ROUND(AVG(c.soldier_casualties), 2) AS mean_soldier_casualties,
+                       ROUND(AVG(c.unit_casualties), 2) AS mean_unit_casualties
+                FROM casualties c JOIN battles b ON b.battle_id = c.battle_id
+                GROUP BY b.battle_type, c.side ORDER BY b.battle_type, c.side",
+            BuiltinQuery::Outcomes => "
+                SELECT battle_type, outcome, COUNT(*) AS battles
+                FROM battles GROUP BY battle_type, outcome ORDER BY battle_type, battles DESC",
+            BuiltinQuery::Runs => "
+                SELECT r.run_id, r.seed, r.run_count, COUNT(b.battle_id) AS battles
+                FROM runs r LEFT JOIN battles b ON b.run_id = r.run_id
+                GROUP BY r.run_id ORDER BY r.timestamp",
+        }
+    }
+}
+
+/// Rows returned by a query
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryTable{
+    pub columns : Vec<String>,
+    pub rows : Vec<Vec<String>>,
+}
+
+impl QueryTable{
+    /// Convert to printable table with aligned columns
+    pub fn table_output(&self) -> String{
+        let widths = (0..self.columns.len())
+            .map(|i| self.rows.iter().map(|r| r[i].len()).chain(std::iter::once(self.columns[i].len())).max().unwrap())
+            .collect::<Vec<usize>>();
+        let line = |cells : &[String]| cells.iter().zip(widths.iter())
+            .map(|(c, w)| format!("{:<w$}", c, w = *w))
+            .collect::<Vec<String>>().join("  ").trim_end().to_string();
+
+        let mut out = vec![line(&self.columns)];
+        out.extend(self.rows.iter().map(|r| line(r)));
+        out.join("\n")
+    }
+}
+
+/// SQLite store of battle runs
+pub struct BattleDatabase{
+    connection : Connection,
+}
+
+impl BattleDatabase{
+    /// Open database at path, creating it and its tables if they don't exist
+    pub fn open(path : &str) -> Self{
+        let connection = Connection::open(path).unwrap_or_else(|e| panic!("Unable to open database {}: {}", path, e));
+        connection.execute_batch(SCHEMA).unwrap_or_else(|e| panic!("Unable to create tables in {}: {}", path, e));
+        BattleDatabase{ connection }
+    }
+
+    /// Save a run and all of its battles, return the id of the first battle saved
+    pub fn insert_run(&mut self, manifest : &RunManifest, data : &[BattleData], results : &[BattleResults]) -> u64{
+        self.begin_run(manifest);
+        let mut first_id = None;
+        for (i, (d, r)) in data.iter().zip(results.iter()).enumerate(){
+            let id = self.insert_battle(&manifest.run_id, i as u64 + 1, d, r);
+            first_id.get_or_insert(id);
+        }
+        self.commit();
+        first_id.unwrap_or(0)
+    }
+
+    /// Start a transaction for the battles of a run and save the run, battles are saved once commit is called
+    pub fn begin_run(&mut self, manifest : &RunManifest){
+        self.connection.execute_batch("BEGIN").expect("Unable to start database transaction");
+        self.connection.execute("INSERT INTO runs (run_id, timestamp, seed, tool_version, ruleset, run_count, manifest) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                   params![manifest.run_id, manifest.timestamp as i64, manifest.seed.to_string(), manifest.tool_version,
+                           manifest.ruleset, manifest.run_count, serde_json::to_string(manifest).unwrap()])
+            .unwrap_or_else(|e| panic!("Unable to save run {}: {}", manifest.run_id, e));
+    }
+
+    /// Update the run count and manifest of a run saved with begin_run, e.g. once the run finished
+    pub fn update_run(&self, manifest : &RunManifest){
+        self.connection.execute("UPDATE runs SET run_count = ?1, manifest = ?2 WHERE run_id = ?3",
+                   params![manifest.run_count, serde_json::to_string(manifest).unwrap(), manifest.run_id])
+            .unwrap_or_else(|e| panic!("Unable to update run {}: {}", manifest.run_id, e));
+    }
+
+    /// Commit the battles of a run started with begin_run
+    pub fn commit(&self){
+        self.connection.execute_batch("COMMIT").expect("Unable to commit battles to database");
+    }
+
+    /// Save a single battle and its players, units, casualties and treasure, return the battle id
+    pub fn insert_battle(&self, run_id : &str, run_index : u64, data : &BattleData, results : &BattleResults) -> u64{
+        let tx = &self.connection;
+        let outcome = data.get_outcome();
+        let winner = match outcome{
+            BattleOutcome::Draw => "Draw",
+            o if o.is_victory() => "Attacker",
+            _ => "Defender",
+        };
+        let (attacker_random, defender_random) = data.get_randoms();
+        tx.execute("INSERT INTO battles (run_id, run_index, battle_type, battle_parameters, outcome, winner, attacker_random, defender_random, total)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                   params![run_id, run_index as i64, data.get_battle_type().get_name(), serde_json::to_string(&data.get_battle_type()).unwrap(),
+                           format!("{:?}", outcome), winner, attacker_random as f64, defender_random as f64, data.get_total() as f64])
+            .expect("Unable to save battle");
+        let battle_id = tx.last_insert_rowid();
+
+        let sides = [("Attacker", data.get_attacker(), results.get_attacker_treasure()),
+                     ("Defender", data.get_defender(), results.get_defender_treasure())];
+        for (side, p, treasure) in sides.iter(){
+            tx.execute("INSERT INTO players (battle_id, side, faction, general_rank, general_bonus, armor_bonus, weapon_bonus, follower_bonus,
+                        banner_bonus, trinket_bonus, adv_combat, begin_unit_bonus, begin_soldiers, end_unit_bonus, end_soldiers, total_units, reinforcements)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17)",
+                       params![battle_id, side, format!("{:?}", p.get_faction()), p.get_general_rank(), p.get_general_bonus(),
+                               p.get_equipment_bonus(EquipmentType::Armor), p.get_equipment_bonus(EquipmentType::Weapon),
+                               p.get_equipment_bonus(EquipmentType::Follower), p.get_equipment_bonus(EquipmentType::Banner),
+                               p.get_equipment_bonus(EquipmentType::Trinket), p.get_adv_combat(), p.get_begin().get_unit_bonus(),
+                               p.get_begin().get_soldiers(), p.get_end().get_unit_bonus(), p.get_end().get_soldiers(),
+                               p.get_total_units(), p.get_reinforcements()])
+                .expect("Unable to save player");
+
+            for (name, count) in data.get_unit_counts(p){
+                tx.execute("INSERT INTO player_units (battle_id, side, unit_name, count) VALUES (?1, ?2, ?3, ?4)",
+                           params![battle_id, side, name, count])
+                    .expect("Unable to save player units");
+            }
+
+            tx.execute("INSERT INTO casualties (battle_id, side, general_state, upgrades, soldier_casualties, unit_casualties)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                       params![battle_id, side, format!("{:?}", p.get_general_state()), p.get_upgrades(),
+                               p.get_soldier_casualties(), p.get_unit_casualties()])
+                .expect("Unable to save casualties");
+
+            if let Some(t) = treasure{
+                tx.execute("INSERT INTO treasure (battle_id, side, equipment_id, equipment_type, name, autoresolve_bonus, coin_value)
+                            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                           params![battle_id, side, t.get_id(), format!("{:?}", t.equip_type()), t.get_name(),
+                                   t.get_bonus(), t.get_coin_value()])
+                    .expect("Unable to save treasure");
+            }
+        }
+        battle_id as u64
+    }
+
+    /// Run a built-in query
+    pub fn run_query(&self, query : BuiltinQuery) -> QueryTable{
+        self.query(query.get_sql())
+    }
+
+    /// Run SQL and return all rows with values converted to strings
+    pub fn query(&self, sql : &str) -> QueryTable{
+        let mut stmt = self.connection.prepare(sql).unwrap_or_else(|e| panic!("Invalid query: {}", e));
+        let columns = stmt.column_names().iter().map(|c| c.to_string()).collect::<Vec<String>>();
+        let count = columns.len();
+        let rows = stmt.query_map([], |row| {
+            (0..count).map(|i| row.get_ref(i).map(|v| match v{
+                ValueRef::Null => String::new(),
+                ValueRef::Integer(n) => n.to_string(),
+                ValueRef::Real(f) => f.to_string(),
+                ValueRef::Text(t) | ValueRef::Blob(t) => String::from_utf8_lossy(t).to_string(),
+            })).collect::<Result<Vec<String>, _>>()
+        }).and_then(|r| r.collect::<Result<Vec<Vec<String>>, _>>())
+            .unwrap_or_else(|e| panic!("Unable to run query: {}", e));
+        QueryTable{ columns, rows }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::battle::{Battle, BattleType};
+    use crate::generator::GeneratorProfile;
+    use crate::player::Player;
+    use crate::general::General;
+    use crate::roster::Roster;
+    use crate::treasure::Treasure;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_insert_and_query(){
+        let dir = TestDir::new("database");
+        let path = &dir.file("test.db");
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+
+        let mut data = vec![];
+        let mut results = vec![];
+        for _ in 0..10{
+            let mut b = Battle::generate_random_battle(&r, &t, &GeneratorProfile::default(), None);
+            let mut d = BattleData::new(&r);
+            results.push(b.autoresolve(&t, &mut d));
+            data.push(d);
+        }
+        let manifest = RunManifest::new(vec![], 3, 10, r.get_file_path(), t.get_file_path(), None, &GeneratorProfile::default());
+
+        let mut db = BattleDatabase::open(path);
+        assert_eq!(1, db.insert_run(&manifest, &data, &results));
+
+        let runs = db.run_query(BuiltinQuery::Runs);
+        assert_eq!(vec![manifest.run_id.clone(), String::from("3"), String::from("10"), String::from("10")], runs.rows[0]);
+
+        let players = db.query("SELECT COUNT(*) FROM players");
+        assert_eq!("20", players.rows[0][0]);
+
+        // every battle is counted once for each side's faction
+        let factions = db.run_query(BuiltinQuery::FactionWinRate);
+        assert_eq!(vec!["faction", "battles", "wins", "win_rate"], factions.columns);
+        assert_eq!(20, factions.rows.iter().map(|r| r[1].parse::<i32>().unwrap()).sum::<i32>());
+
+        let wins = data.iter().filter(|d| d.get_outcome().is_victory()).count();
+        let types = db.run_query(BuiltinQuery::BattleTypeWinRate);
+        assert_eq!(wins as i32, types.rows.iter().map(|r| r[2].parse::<i32>().unwrap()).sum::<i32>());
+
+        // a run stopped early records the battles it ran
+        let mut stopped = manifest.clone();
+        stopped.run_count = 4;
+        db.update_run(&stopped);
+        assert_eq!("4", db.run_query(BuiltinQuery::Runs).rows[0][2]);
+
+        // runs with the same seed in the same second are saved separately
+        let again = RunManifest::new(vec![], 3, 10, r.get_file_path(), t.get_file_path(), None, &GeneratorProfile::default());
+        assert_eq!(11, db.insert_run(&again, &data, &results));
+        assert_eq!(2, db.run_query(BuiltinQuery::Runs).rows.len());
+    }
+
+    #[test]
+    fn test_units_and_treasure(){
+        let dir = TestDir::new("database_units");
+        let path = &dir.file("test.db");
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let names = r.get_all_unit_names();
+        let attacker = Player::new(vec![r.get_unit_by_id(1).clone(), r.get_unit_by_id(1).clone()], General::default());
+        let defender = Player::new(vec![r.get_unit_by_id(2).clone()], General::default());
+        let mut b = Battle::new(attacker, defender, BattleType::Normal);
+        let mut d = BattleData::new(&r);
+        let res = b.autoresolve(&t, &mut d);
+        let manifest = RunManifest::new(vec![], 4, 1, r.get_file_path(), t.get_file_path(), None, &GeneratorProfile::default());
+
+        let received = [d.get_attacker(), d.get_defender()].iter().filter(|p| p.get_treasure_received()).count();
+
+        let mut db = BattleDatabase::open(path);
+        db.insert_run(&manifest, std::slice::from_ref(&d), &[res]);
+
+        let units = db.query("SELECT side, unit_name, count FROM player_units ORDER BY side");
+        assert_eq!(vec![String::from("Attacker"), names[0].clone(), String::from("2")], units.rows[0]);
+        assert_eq!(vec![String::from("Defender"), names[1].clone(), String::from("1")], units.rows[1]);
+
+        let players = db.query("SELECT p.faction, p.begin_soldiers, p.end_soldiers, c.general_state, c.soldier_casualties
+                                FROM players p JOIN casualties c ON c.battle_id = p.battle_id AND c.side = p.side WHERE p.side = 'Attacker'");
+        let a = d.get_attacker();
+        assert_eq!(vec![format!("{:?}", a.get_faction()), a.get_begin().get_soldiers().to_string(), a.get_end().get_soldiers().to_string(),
+                        format!("{:?}", a.get_general_state()), a.get_soldier_casualties().to_string()], players.rows[0]);
+        let battles = db.query("SELECT total, battle_parameters FROM battles");
+        assert_eq!(vec![(d.get_total() as f64).to_string(), String::from("\"Normal\"")], battles.rows[0]);
+
+        let treasure = db.query("SELECT COUNT(*) FROM treasure");
+        assert_eq!(received.to_string(), treasure.rows[0][0]);
+    }
+
+    #[test]
+    fn test_table_output(){
+        let t = QueryTable{
+            columns: vec![String::from("faction"), String::from("wins")],
+            rows: vec![vec![String::from("Rebel"), String::from("10")]],
+        };
+        assert_eq!("faction  wins\nRebel    10", t.table_output());
+    }
+}
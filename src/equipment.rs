@@ -1,5 +1,6 @@
+use serde::Serialize;
 
-#[derive(Debug, Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Serialize)]
 pub struct Equipment{
     equipment_type : EquipmentType,
     name : String,
@@ -20,14 +21,8 @@ impl Default for Equipment{
 impl Equipment{
     pub fn new(equipment_type_str: &str, name : String, effect : String, coin_value : i32, id: i32, bonus : i32, range: i32, dragon : bool) -> Self{
         Equipment{
-            equipment_type: match equipment_type_str{
-                "Armor" => EquipmentType::Armor,
-                "Weapon" => EquipmentType::Weapon,
-                "Trinket" => EquipmentType::Trinket,
-                "Banner" => EquipmentType::Banner,
-                "Follower" => EquipmentType::Follower,
-                _ => panic!("Unable to convert {} to EquipmentType", equipment_type_str)
-            },
+            equipment_type: EquipmentType::from_name(equipment_type_str)
+                .unwrap_or_else(|| panic!("Unable to convert {} to EquipmentType", equipment_type_str)),
             name,
             effect,
             coin_value,
@@ -63,13 +58,56 @@ impl Equipment{
         self.id
     }
 
+    /// Set equipment id, used when merging content packs
+    pub fn set_id(&mut self, id : i32){
+        self.id = id;
+    }
+
+    /// Get description of the equipment's effect
+    pub fn get_effect(&self) -> &str{
+        self.effect.as_str()
+    }
+
+    /// Get coin value
+    pub fn get_coin_value(&self) -> i32{
+        self.coin_value
+    }
+
+    /// Get range
+    pub fn get_range(&self) -> i32{
+        self.range
+    }
+
+    /// Convert to line of the equipment CSV file
+    pub fn to_csv_line(&self) -> String{
+        format!("{:?},{},{},{},{},{},{},{}", self.equipment_type, self.name, self.effect, self.coin_value, self.id,
+                self.autoresolve_bonus, self.range, self.dragon_equipment)
+    }
+
 }
 
-#[derive(Debug,PartialEq,Eq,Copy,Clone)]
+#[derive(Debug,PartialEq,Eq,Copy,Clone,Serialize)]
 pub enum EquipmentType {
     Armor = 1,
     Weapon,
     Trinket,
     Banner,
     Follower
+}
+
+impl EquipmentType{
+    /// Names of all equipment types
+    pub const NAMES : [&'static str; 5] = ["Armor", "Weapon", "Trinket", "Banner", "Follower"];
+
+    /// Get EquipmentType from its name, ignoring case
+    pub fn from_name(name : &str) -> Option<Self>{
+        match name.to_lowercase().as_ref(){
+            "armor" => Some(EquipmentType::Armor),
+            "weapon" => Some(EquipmentType::Weapon),
+            "trinket" => Some(EquipmentType::Trinket),
+            "banner" => Some(EquipmentType::Banner),
+            "follower" => Some(EquipmentType::Follower),
+            _ => None,
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,260 @@
+use crate::battle::{BattleData, BattleResults, BattleJSONObject};
+use crate::generator::BattleGenerator;
+use crate::roster::Roster;
+use crate::treasure::Treasure;
+use crate::rng;
+use crate::output::OutcomeCounts;
+use crate::resolver::BatchResolver;
+use std::ops::ControlFlow;
+use std::sync::mpsc::{sync_channel, SyncSender, Receiver};
+use threadpool::ThreadPool;
+
+/// BattleData and BattleResults of a resolved battle, with its starting state if it is to be exported
+pub type ResolvedBattle = (BattleData, BattleResults, Option<BattleJSONObject>);
+
+/// Number of battles in a chunk, each chunk is run with its own seed
+pub const CHUNK_SIZE : u32 = 512;
+
+/// Number of resolved chunks each thread can hold before waiting for them to be handled
+const CHANNEL_CAPACITY : usize = 2;
+
+/// Runs battles in chunks of CHUNK_SIZE, each seeded from the run's seed and its chunk number,
+/// so results are the same and in the same order for any number of threads
+pub struct Executor<'a>{
+    generator : &'a BattleGenerator,
+    roster : &'a Roster,
+    treasure : &'a Treasure,
+    seed : u64,
+    threads : usize,
+    export : bool,
+}
+
+impl<'a> Executor<'a>{
+    /// Create new Executor, export is if battles' starting states are kept for exporting
+    pub fn new(generator : &'a BattleGenerator, roster : &'a Roster, treasure : &'a Treasure, seed : u64, threads : usize, export : bool) -> Self{
+        Executor{
+            generator,
+            roster,
+            treasure,
+            seed,
+            threads: threads.max(1),
+            export,
+        }
+    }
+
+    /// Run up to count battles, passing each to handle on the calling thread in order until handle breaks
+    pub fn run<F : FnMut(ResolvedBattle) -> ControlFlow<()>>(&self, count : u32, mut handle : F){
+        let export = self.export;
+        self.run_chunks(count, move |g, r, t, size| {
+            let mut results = Vec::with_capacity(size as usize);
+            for _i in 0..size{
+                // create temp battle
+                let mut temp = g.next_battle(r, t);
+                // keep starting state of battle for exporting
+                let json = match export{
+                    true => Some(BattleJSONObject::from_battle(&temp)),
+                    false => None,
+                };
+
+                let mut data = BattleData::new(r);
+                let res = temp.autoresolve(t, &mut data);
+                results.push((data, res, json));
+            }
+            results
+        }, |results| results.into_iter().try_for_each(&mut handle));
+    }
+
+    /// Run up to count battles resolving only their outcomes with a BatchResolver, passing the outcome counts of each chunk
+    /// to handle until it breaks. The deterministic total of a fixed battle is only calculated once
+    pub fn run_outcomes<F : FnMut(&OutcomeCounts) -> ControlFlow<()>>(&self, count : u32, mut handle : F){
+        let resolver = BatchResolver::default();
+        let fixed_total = self.generator.get_fixed_battle().map(|b| b.get_deterministic_total());
+        self.run_chunks(count, move |g, r, t, size| {
+            let mut counts = OutcomeCounts::default();
+            let mut rng = rng::thread_rng();
+            match fixed_total{
+                Some(total) => resolver.resolve(total, size, &mut rng, &mut counts),
+                None => for _i in 0..size{
+                    let total = g.next_battle(r, t).get_deterministic_total();
+                    counts.add(&resolver.sample_outcome(total, &mut rng));
+                },
+            }
+            counts
+        }, |counts| handle(&counts));
+    }
+
+    /// Get number of chunks for a number of battles
+    fn chunk_count(count : u32) -> u32{
+        count.div_ceil(CHUNK_SIZE)
+    }
+
+    /// Get number of battles in a chunk
+    fn chunk_size(count : u32, chunk : u32) -> u32{
+        CHUNK_SIZE.min(count - chunk * CHUNK_SIZE)
+    }
+
+    /// Run work for each chunk with the current thread seeded for the chunk, passing each chunk's result to handle in order
+    /// until handle breaks
+    fn run_chunks<T, W, H>(&self, count : u32, work : W, handle : H)
+        where T : Send + 'static,
+              W : Fn(&BattleGenerator, &Roster, &Treasure, u32) -> T + Send + Clone + 'static,
+              H : FnMut(T) -> ControlFlow<()>{
+        // no need for more threads than chunks
+        match self.threads.min(Self::chunk_count(count) as usize){
+            0 | 1 => self.run_single_thread(count, work, handle),
+            n => self.run_multiple_threads(count, n, work, handle),
+        }
+    }
+
+    /// Run all chunks on the calling thread
+    fn run_single_thread<T, W, H>(&self, count : u32, work : W, mut handle : H)
+        where W : Fn(&BattleGenerator, &Roster, &Treasure, u32) -> T,
+              H : FnMut(T) -> ControlFlow<()>{
+        for chunk in 0..Self::chunk_count(count){
+            rng::seed(rng::derive_seed(self.seed, chunk as u64));
+            if handle(work(self.generator, self.roster, self.treasure, Self::chunk_size(count, chunk))).is_break(){
+                return;
+            }
+        }
+    }
+
+    /// Run chunks across threads, thread i runs every n-th chunk starting at chunk i and sends
+    /// them through its own channel so they can be handled in order
+    fn run_multiple_threads<T, W, H>(&self, count : u32, num_threads : usize, work : W, mut handle : H)
+        where T : Send + 'static,
+              W : Fn(&BattleGenerator, &Roster, &Treasure, u32) -> T + Send + Clone + 'static,
+              H : FnMut(T) -> ControlFlow<()>{
+        let pool = ThreadPool::new(num_threads);
+        let mut receivers : Vec<Receiver<T>> = vec![];
+
+        for i in 0..num_threads {
+            let (tx, rx) : (SyncSender<T>, Receiver<T>) = sync_channel(CHANNEL_CAPACITY);
+            receivers.push(rx);
+            let ros = self.roster.clone();
+            let tr = self.treasure.clone();
+            let gen = self.generator.clone();
+            let w = work.clone();
+            let seed = self.seed;
+
+            pool.execute(move || {
+                for chunk in (i as u32..Self::chunk_count(count)).step_by(num_threads){
+                    rng::seed(rng::derive_seed(seed, chunk as u64));
+                    // receiver is only dropped if the main thread stopped handling results
+                    if tx.send(w(&gen, &ros, &tr, Self::chunk_size(count, chunk))).is_err(){
+                        return;
+                    }
+                }
+            });
+        }
+
+        // handle chunks in order as they are received
+        for chunk in 0..Self::chunk_count(count){
+            let results = receivers[chunk as usize % num_threads].recv()
+                .expect("Battle thread stopped before sending all results");
+            if handle(results).is_break(){
+                break;
+            }
+        }
+        // stop threads from resolving more chunks
+        drop(receivers);
+
+        // ensure all threads have completed before continuing
+        pool.join();
+        assert_eq!(0, pool.panic_count());
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::generator::{BattleSource, GeneratorProfile};
+
+    fn run_rows(threads : usize, count : u32) -> Vec<String>{
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let g = BattleGenerator::new(BattleSource::Random(None), GeneratorProfile::default(), None);
+        let mut rows = vec![];
+        Executor::new(&g, &r, &t, 99, threads, false).run(count, |(d, _, _)| {
+            rows.push(d.format_output());
+            ControlFlow::Continue(())
+        });
+        rows
+    }
+
+    #[test]
+    fn test_chunks(){
+        assert_eq!(0, Executor::chunk_count(0));
+        assert_eq!(1, Executor::chunk_count(CHUNK_SIZE));
+        assert_eq!(2, Executor::chunk_count(CHUNK_SIZE + 1));
+        assert_eq!(1, Executor::chunk_size(CHUNK_SIZE + 1, 1));
+    }
+
+    #[test]
+    fn test_threads_match_single_thread(){
+        let count = CHUNK_SIZE * 3 + 10;
+        let single = run_rows(1, count);
+        assert_eq!(count as usize, single.len());
+        assert_eq!(single, run_rows(2, count));
+        assert_eq!(single, run_rows(3, count));
+    }
+
+    #[test]
+    fn test_outcomes(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let g = BattleGenerator::new(BattleSource::Random(None), GeneratorProfile::default(), None);
+        let count = CHUNK_SIZE * 2 + 1;
+        let run = |threads| {
+            let mut counts = OutcomeCounts::default();
+            Executor::new(&g, &r, &t, 5, threads, false).run_outcomes(count, |c| {
+                counts.merge(c);
+                ControlFlow::Continue(())
+            });
+            counts
+        };
+        let single = run(1);
+        assert_eq!(count as u64, single.get_total());
+        assert_eq!(single, run(3));
+    }
+
+    #[test]
+    fn test_stop_early(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let g = BattleGenerator::new(BattleSource::Random(None), GeneratorProfile::default(), None);
+        for threads in [1, 3]{
+            let mut chunks = 0;
+            Executor::new(&g, &r, &t, 5, threads, false).run_outcomes(CHUNK_SIZE * 10, |_| {
+                chunks += 1;
+                match chunks{
+                    2 => ControlFlow::Break(()),
+                    _ => ControlFlow::Continue(()),
+                }
+            });
+            assert_eq!(2, chunks);
+
+            let mut battles = 0;
+            Executor::new(&g, &r, &t, 5, threads, false).run(CHUNK_SIZE * 10, |_| {
+                battles += 1;
+                match battles{
+                    3 => ControlFlow::Break(()),
+                    _ => ControlFlow::Continue(()),
+                }
+            });
+            assert_eq!(3, battles);
+        }
+    }
+
+    #[test]
+    fn test_export_state(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let g = BattleGenerator::new(BattleSource::Random(None), GeneratorProfile::default(), None);
+        let mut exported = 0;
+        Executor::new(&g, &r, &t, 1, 2, true).run(5, |(_, _, j)| {
+            exported += j.is_some() as i32;
+            ControlFlow::Continue(())
+        });
+        assert_eq!(5, exported);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::battle::{BattleJSONObject, BattleOutcome};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Writes battles to replayable JSON files, optionally only those with given outcomes.
+/// Clones share a file counter so they can be used across threads.
+#[derive(Debug, Clone)]
+pub struct BattleExporter{
+    directory : String,
+    outcomes : Option<Vec<BattleOutcome>>,
+    count : Arc<AtomicU64>,
+}
+
+impl BattleExporter{
+    /// Create new BattleExporter, creates directory if it doesn't exist
+    pub fn new(directory : &str, outcomes : Option<Vec<BattleOutcome>>) -> Self{
+        fs::create_dir_all(directory)
+            .unwrap_or_else(|e| panic!("Unable to create export directory {}: {}", directory, e));
+        BattleExporter{
+            directory: String::from(directory),
+            outcomes,
+            count: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Parse comma separated list of BattleOutcome names for filtering exported battles
+    pub fn parse_outcomes(list : &str) -> Vec<BattleOutcome>{
+        list.split(',').map(|s| BattleOutcome::from_name(s.trim())).collect()
+    }
+
+    /// Get if battles with the outcome are exported
+    pub fn matches(&self, outcome : &BattleOutcome) -> bool{
+        self.outcomes.as_ref().map(|o| o.contains(outcome)).unwrap_or(true)
+    }
+
+    /// Write battle to a new file if its outcome matches the filter, return the file written
+    pub fn export(&self, battle : &BattleJSONObject, outcome : &BattleOutcome) -> Option<String>{
+        if !self.matches(outcome){
+            return None;
+        }
+        let n = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        let file = Path::new(&self.directory).join(format!("battle_{:06}_{:?}.json", n, outcome));
+        let file = file.to_string_lossy().to_string();
+        battle.to_json(&file);
+        Some(file)
+    }
+
+    /// Get number of battles exported
+    pub fn get_count(&self) -> u64{
+        self.count.load(Ordering::SeqCst)
+    }
+
+    /// Get export directory
+    pub fn get_directory(&self) -> &str{
+        &self.directory
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::battle::{Battle, BattleData};
+    use crate::roster::Roster;
+    use crate::treasure::Treasure;
+    use crate::generator::GeneratorProfile;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_export_replay(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let dir = TestDir::new("export");
+        let e = BattleExporter::new(&dir.file("exports"), None);
+
+        let mut b = Battle::generate_random_battle(&r, &t, &GeneratorProfile::default(), None);
+        let j = BattleJSONObject::from_battle(&b);
+        let expected_total = b.get_deterministic_total();
+        let res = b.autoresolve(&t, &mut BattleData::new(&r));
+
+        let file = e.export(&j, res.get_outcome()).unwrap();
+        assert_eq!(1, e.get_count());
+
+        // replayed battle has the same state as the generated battle before it was autoresolved
+        let replay = BattleJSONObject::from_json(&file);
+        assert!(!replay.has_random());
+        let replay = replay.produce_battle(&r, &t, &GeneratorProfile::default());
+        assert_eq!(expected_total, replay.get_deterministic_total());
+        assert_eq!(b.get_battle_type(), replay.get_battle_type());
+    }
+
+    #[test]
+    fn test_outcome_filter(){
+        let dir = TestDir::new("export_filter");
+        let e = BattleExporter::new(&dir.file("exports"), Some(BattleExporter::parse_outcomes("DecisiveVictory, CrushingDefeat")));
+        assert!(e.matches(&BattleOutcome::DecisiveVictory));
+        assert!(e.matches(&BattleOutcome::CrushingDefeat));
+        assert!(!e.matches(&BattleOutcome::Draw));
+
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let b = Battle::generate_random_battle(&r, &t, &GeneratorProfile::default(), None);
+        assert_eq!(None, e.export(&BattleJSONObject::from_battle(&b), &BattleOutcome::Draw));
+        assert_eq!(0, e.get_count());
+    }
+}
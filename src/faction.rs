@@ -10,9 +10,23 @@ pub enum Faction {
 }
 
 impl Faction{
+    /// Names of all factions
+    pub const NAMES : [&'static str; 4] = ["Rebel", "Beladimir", "Lerastir", "Menoriad"];
+
+    /// Get Faction from its name, ignoring case
+    pub fn from_name(name : &str) -> Option<Self>{
+        match name.to_lowercase().as_ref(){
+            "rebel" => Some(Faction::Rebel),
+            "beladimir" => Some(Faction::Beladimir),
+            "lerastir" => Some(Faction::Lerastir),
+            "menoriad" => Some(Faction::Menoriad),
+            _ => None,
+        }
+    }
+
     /// Generate random faction
     pub fn generate_random_faction() -> Self{
-        let mut rng = rand::thread_rng();
+        let mut rng = crate::rng::thread_rng();
 
         match rng.gen_range(1..=4) {
             1 => Faction::Rebel,
@@ -1,7 +1,8 @@
 use crate::equipment::{Equipment, EquipmentType};
 use serde::{Deserialize, Serialize};
-use rand::Rng;
 use crate::treasure::Treasure;
+use crate::generator::{GeneratorProfile, Randomizable};
+use crate::pack::EntryRef;
 
 #[derive(Debug, Clone)]
 pub struct General{
@@ -92,61 +93,85 @@ impl General{
     }
 
     /// Generate a General with random equipment and rank
-    pub fn generate_random_general(equipment_ratio : u32, rank_cap : u32, treasure: &Treasure) -> Self{
-        let mut rng = rand::thread_rng();
+    pub fn generate_random_general(profile : &GeneratorProfile, treasure: &Treasure) -> Self{
+        let random_equipment = |equip_type : EquipmentType| match profile.random_has_equipment() {
+            true => Some(treasure.get_item_by_type(equip_type).clone()),
+            false => None
+        };
 
         General::new(
-            match rng.gen_range(1..equipment_ratio+1) {
-            1 => Some(treasure.get_item_by_type(EquipmentType::Armor).clone()),
-            _ => None
-        }, match rng.gen_range(1..equipment_ratio+1) {
-            1 => Some(treasure.get_item_by_type(EquipmentType::Weapon).clone()),
-            _ => None
-        }, match rng.gen_range(1..equipment_ratio+1) {
-            1 => Some(treasure.get_item_by_type(EquipmentType::Banner).clone()),
-            _ => None
-        }, match rng.gen_range(1..equipment_ratio+1) {
-            1 => Some(treasure.get_item_by_type(EquipmentType::Follower).clone()),
-            _ => None
-        }, match rng.gen_range(1..equipment_ratio+1) {
-            1 => Some(treasure.get_item_by_type(EquipmentType::Trinket).clone()),
-            _ => None
-        }, rng.gen_range(1..rank_cap+1) as i32
+            random_equipment(EquipmentType::Armor),
+            random_equipment(EquipmentType::Weapon),
+            random_equipment(EquipmentType::Banner),
+            random_equipment(EquipmentType::Follower),
+            random_equipment(EquipmentType::Trinket),
+            profile.random_rank()
         )
     }
 
 }
 
 /// Holds General struct in a format for serializing/deserializing
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct GeneralJSONObject{
-    armor : i32,
-    weapon : i32,
-    banner : i32,
-    follower : i32,
-    trinket : i32,
-    rank : i32,
+    armor : Randomizable<EntryRef>,
+    weapon : Randomizable<EntryRef>,
+    banner : Randomizable<EntryRef>,
+    follower : Randomizable<EntryRef>,
+    trinket : Randomizable<EntryRef>,
+    rank : Randomizable<i32>,
 }
 
 impl GeneralJSONObject{
-    /// Produce General object from self
-    pub fn produce_general(self, treasure: &Treasure) -> General{
+    /// Create fixed GeneralJSONObject from references to its equipment, in the order armor, weapon, banner,
+    /// follower, trinket, and its rank
+    pub fn new(equipment : [EntryRef; 5], rank : i32) -> Self{
+        let [armor, weapon, banner, follower, trinket] = equipment.map(Randomizable::Fixed);
+        GeneralJSONObject{ armor, weapon, banner, follower, trinket, rank: Randomizable::Fixed(rank) }
+    }
+
+    /// Produce General object from self, randomized fields are generated using profile
+    pub fn produce_general(&self, treasure: &Treasure, profile : &GeneratorProfile) -> General{
         General::new(
-            Self::get_equipment(self.armor, treasure),
-            Self::get_equipment(self.weapon, treasure),
-            Self::get_equipment(self.banner, treasure),
-            Self::get_equipment(self.follower, treasure),
-            Self::get_equipment(self.trinket, treasure),
-            self.rank,
+            Self::get_equipment(&self.armor, EquipmentType::Armor, treasure, profile),
+            Self::get_equipment(&self.weapon, EquipmentType::Weapon, treasure, profile),
+            Self::get_equipment(&self.banner, EquipmentType::Banner, treasure, profile),
+            Self::get_equipment(&self.follower, EquipmentType::Follower, treasure, profile),
+            Self::get_equipment(&self.trinket, EquipmentType::Trinket, treasure, profile),
+            self.rank.resolve(|| profile.random_rank()),
         )
     }
-    fn get_equipment( id : i32, treasure : &Treasure) -> Option<Equipment>{
-        if id <= 0 {
-            None
-        }else{
-            treasure.get_item_by_id(id).map(|e| e.clone())
+
+    /// Get equipment by id, namespaced id or name or generate random equipment of equip_type
+    fn get_equipment(id : &Randomizable<EntryRef>, equip_type : EquipmentType, treasure : &Treasure, profile : &GeneratorProfile) -> Option<Equipment>{
+        match id{
+            Randomizable::Random(_) => match profile.random_has_equipment(){
+                true => Some(treasure.get_item_by_type(equip_type).clone()),
+                false => None,
+            },
+            Randomizable::Fixed(id) => treasure.get_item_by_ref(id, equip_type)
+                .unwrap_or_else(|e| panic!("Invalid {:?} in battle JSON: {}", equip_type, e)).cloned(),
+        }
+    }
+
+    /// Create fixed GeneralJSONObject from a General, empty equipment slots use id 0
+    pub fn from_general(general : &General) -> Self{
+        let id = |t : EquipmentType| Randomizable::Fixed(EntryRef::Id(general.get_equipment(t).map(|e| i64::from(e.get_id())).unwrap_or(0)));
+        GeneralJSONObject{
+            armor: id(EquipmentType::Armor),
+            weapon: id(EquipmentType::Weapon),
+            banner: id(EquipmentType::Banner),
+            follower: id(EquipmentType::Follower),
+            trinket: id(EquipmentType::Trinket),
+            rank: Randomizable::Fixed(general.get_rank()),
         }
     }
+
+    /// Get if any field is randomized
+    pub fn has_random(&self) -> bool{
+        [&self.armor, &self.weapon, &self.banner, &self.follower, &self.trinket].iter()
+            .any(|f| f.is_random()) || self.rank.is_random()
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -163,7 +188,7 @@ mod tests{
 
     #[test]
     fn test_set_equipment(){
-        let r = Treasure::new(Option::None);
+        let r = Treasure::embedded();
         let mut g = General::default();
 
         g.set_equipment(r.get_item_by_type(EquipmentType::Armor).clone());
@@ -0,0 +1,608 @@
+use crate::faction::Faction;
+use crate::unit::UnitType;
+use crate::battle::{Battle, BattleType, BattleJSONObject};
+use crate::roster::Roster;
+use crate::treasure::Treasure;
+use crate::monster::MonsterType;
+use crate::battle::TownStats;
+use serde::{Deserialize, Serialize};
+use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use crate::resources;
+use std::sync::{Arc, Mutex};
+
+/// Settings that control how random battles, players, and generals are generated
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct GeneratorProfile{
+    /// 1 in equipment_ratio chance for each piece of general equipment to be filled
+    pub equipment_ratio : u32,
+    /// Inclusive range of general ranks
+    pub rank_range : (u32, u32),
+    /// Maximum number of reinforcements for a player
+    pub reinforcement_cap : u32,
+    /// Inclusive range of the number of units in an army
+    pub army_size : (u32, u32),
+    /// Probability that a player has the advanced combat deck
+    pub adv_combat_chance : f64,
+    pub faction_weights : FactionWeights,
+    /// Weights for choosing each unit's type before the unit, None picks uniformly from the faction's roster
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit_type_weights : Option<UnitTypeWeights>,
+    pub battle_type_weights : BattleTypeWeights,
+}
+
+impl Default for GeneratorProfile{
+    fn default() -> Self {
+        GeneratorProfile{
+            equipment_ratio: 3,
+            rank_range: (1, 10),
+            reinforcement_cap: 5,
+            army_size: (1, 19),
+            adv_combat_chance: 0.5,
+            faction_weights: FactionWeights::default(),
+            unit_type_weights: None,
+            battle_type_weights: BattleTypeWeights::default(),
+        }
+    }
+}
+
+impl GeneratorProfile{
+    /// Read JSON profile file and convert to self, unspecified fields use default values
+    pub fn from_json(file_path : &str) -> Self{
+        let p : GeneratorProfile = serde_json::from_str(&resources::read_to_string(file_path))
+            .unwrap_or_else(|e| panic!("Unable to parse generator profile {}: {}", file_path, e));
+        p.validate();
+        p
+    }
+
+    /// Panic if the profile contains values that cannot be used for generation
+    pub fn validate(&self){
+        if self.equipment_ratio == 0{
+            panic!("Generator profile equipment_ratio must be at least 1");
+        }
+        if self.rank_range.0 > self.rank_range.1{
+            panic!("Generator profile rank_range {:?} has minimum greater than maximum", self.rank_range);
+        }
+        if self.army_size.0 > self.army_size.1{
+            panic!("Generator profile army_size {:?} has minimum greater than maximum", self.army_size);
+        }
+        if !(0.0..=1.0).contains(&self.adv_combat_chance){
+            panic!("Generator profile adv_combat_chance {} not in [0,1]", self.adv_combat_chance);
+        }
+        if self.faction_weights.as_array().iter().all(|w| *w == 0){
+            panic!("Generator profile faction_weights must have at least one non-zero weight");
+        }
+        if self.unit_type_weights.map(|w| w.as_array().iter().all(|w| *w == 0)).unwrap_or(false){
+            panic!("Generator profile unit_type_weights must have at least one non-zero weight");
+        }
+        if self.battle_type_weights.as_array().iter().all(|w| *w == 0){
+            panic!("Generator profile battle_type_weights must have at least one non-zero weight");
+        }
+    }
+
+    /// Generate a random general rank
+    pub fn random_rank(&self) -> i32{
+        crate::rng::thread_rng().gen_range(self.rank_range.0..=self.rank_range.1) as i32
+    }
+
+    /// Generate a random army size
+    pub fn random_army_size(&self) -> u32{
+        crate::rng::thread_rng().gen_range(self.army_size.0..=self.army_size.1)
+    }
+
+    /// Generate whether a player has the advanced combat deck
+    pub fn random_adv_combat(&self) -> bool{
+        crate::rng::thread_rng().gen_bool(self.adv_combat_chance)
+    }
+
+    /// Generate whether a piece of equipment is filled
+    pub fn random_has_equipment(&self) -> bool{
+        crate::rng::thread_rng().gen_range(1..=self.equipment_ratio) == 1
+    }
+
+    /// Generate a random faction using the faction weights
+    pub fn random_faction(&self) -> Faction{
+        match weighted_choice(&self.faction_weights.as_array()){
+            0 => Faction::Rebel,
+            1 => Faction::Beladimir,
+            2 => Faction::Lerastir,
+            3 => Faction::Menoriad,
+            i => panic!("Invalid faction index {} generated", i)
+        }
+    }
+
+    /// Generate a random unit type using the unit type weights, only types with a non-zero availability are chosen.
+    /// Without unit type weights all types are equally likely
+    pub fn random_unit_type(&self, available : &[UnitType]) -> Option<UnitType>{
+        let types = [UnitType::Melee, UnitType::Cavalry, UnitType::Ranged];
+        let weights = self.unit_type_weights.unwrap_or_default().as_array().iter().zip(types.iter())
+            .map(|(w,t)| if available.contains(t) {*w} else {0})
+            .collect::<Vec<u32>>();
+        if weights.iter().all(|w| *w == 0){
+            return None;
+        }
+        Some(types[weighted_choice(&weights)])
+    }
+
+    /// Generate a random BattleType with default values using the battle type weights
+    pub fn random_battle_type(&self) -> BattleType{
+        match weighted_choice(&self.battle_type_weights.as_array()){
+            0 => BattleType::Normal,
+            1 => BattleType::Siege {rams:0,catapults:0,siege_towers:0,defenses:TownStats::default()},
+            2 => BattleType::Raid{defenses: TownStats::default()},
+            3 => BattleType::Naval{attacker_ships:0,defender_ships:0},
+            4 => BattleType::Monster{monster:MonsterType::Minotaur},
+            i => panic!("Invalid battle type index {} generated", i)
+        }
+    }
+}
+
+/// Source of the Battle used for each run
+#[derive(Debug, Clone)]
+pub enum BattleSource{
+    /// Same Battle every run
+    Fixed(Box<Battle>),
+    /// Battle JSON with randomized fields, produced again every run
+    Template(Box<BattleJSONObject>),
+    /// Fully random Battle, optionally of a given BattleType
+    Random(Option<BattleType>),
+}
+
+impl BattleSource{
+    /// Produce the Battle for the next run
+    pub fn next_battle(&self, roster : &Roster, treasure : &Treasure, profile : &GeneratorProfile) -> Battle{
+        match self{
+            BattleSource::Fixed(b) => (**b).clone(),
+            BattleSource::Template(j) => j.produce_battle(roster, treasure, profile),
+            BattleSource::Random(b_type) => Battle::generate_random_battle(roster, treasure, profile, *b_type),
+        }
+    }
+
+    /// Get BattleType of every run, None if it is randomized
+    pub fn get_battle_type(&self) -> Option<BattleType>{
+        match self{
+            BattleSource::Fixed(b) => Some(b.get_battle_type()),
+            BattleSource::Template(j) => j.get_battle_type(),
+            BattleSource::Random(b_type) => *b_type,
+        }
+    }
+}
+
+/// Settings for only accepting generated battles whose deterministic total is within a margin of zero
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BalanceSettings{
+    pub margin : f32,
+    pub max_attempts : u32,
+}
+
+/// Statistics on candidate battles drawn and rejected while generating balanced battles
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BalanceStats{
+    candidates : u64,
+    accepted : u64,
+    exhausted : u64,
+    candidate_total_sum : f64,
+    candidate_abs_total_sum : f64,
+    accepted_total_sum : f64,
+    accepted_abs_total_sum : f64,
+}
+
+impl BalanceStats{
+    /// Record a candidate battle's deterministic total
+    fn record_candidate(&mut self, total : f32){
+        self.candidates += 1;
+        self.candidate_total_sum += total as f64;
+        self.candidate_abs_total_sum += total.abs() as f64;
+    }
+
+    /// Record the deterministic total of the battle used for a run
+    fn record_accepted(&mut self, total : f32, exhausted : bool){
+        self.accepted += 1;
+        self.accepted_total_sum += total as f64;
+        self.accepted_abs_total_sum += total.abs() as f64;
+        if exhausted{
+            self.exhausted += 1;
+        }
+    }
+
+    /// Get number of candidate battles drawn
+    pub fn get_candidates(&self) -> u64{
+        self.candidates
+    }
+
+    /// Get number of candidate battles rejected
+    pub fn get_rejected(&self) -> u64{
+        self.candidates - self.accepted
+    }
+
+    /// Get number of runs that used the closest candidate after hitting the maximum attempts
+    pub fn get_exhausted(&self) -> u64{
+        self.exhausted
+    }
+
+    /// Convert BalanceStats to a printable string
+    pub fn stats_output(&self, settings : &BalanceSettings) -> String{
+        let mean = |sum : f64, count : u64| if count == 0 {0.0} else {sum / count as f64};
+        format!("Balanced Generation (margin: {}, max attempts: {}):\n\
+        Candidates Drawn:{}\n\
+        Accepted:{}\n\
+        Rejected:{} ({:.1}%)\n\
+        Runs Exceeding Max Attempts:{}\n\
+        Mean Deterministic Total (all candidates):{:.2}\n\
+        Mean Absolute Deterministic Total (all candidates):{:.2}\n\
+        Mean Deterministic Total (accepted):{:.2}\n\
+        Mean Absolute Deterministic Total (accepted):{:.2}",
+                settings.margin, settings.max_attempts,
+                self.candidates, self.accepted,
+                self.get_rejected(), 100.0 * mean(self.get_rejected() as f64, self.candidates),
+                self.exhausted,
+                mean(self.candidate_total_sum, self.candidates),
+                mean(self.candidate_abs_total_sum, self.candidates),
+                mean(self.accepted_total_sum, self.accepted),
+                mean(self.accepted_abs_total_sum, self.accepted))
+    }
+}
+
+/// Produces the Battle for each run from a BattleSource, optionally only accepting balanced battles.
+/// Clones share balance statistics so they can be collected across threads.
+#[derive(Debug, Clone)]
+pub struct BattleGenerator{
+    source : BattleSource,
+    profile : GeneratorProfile,
+    balance : Option<BalanceSettings>,
+    stats : Arc<Mutex<BalanceStats>>,
+}
+
+impl BattleGenerator{
+    pub fn new(source : BattleSource, profile : GeneratorProfile, balance : Option<BalanceSettings>) -> Self{
+        BattleGenerator{
+            source,
+            profile,
+            balance,
+            stats: Arc::new(Mutex::new(BalanceStats::default())),
+        }
+    }
+
+    /// Produce the Battle for the next run
+    pub fn next_battle(&self, roster : &Roster, treasure : &Treasure) -> Battle{
+        match (&self.source, self.balance){
+            // fixed battles cannot be rebalanced
+            (BattleSource::Fixed(_), _) | (_, None) => self.source.next_battle(roster, treasure, &self.profile),
+            (_, Some(settings)) => self.next_balanced_battle(roster, treasure, &settings),
+        }
+    }
+
+    /// Draw battles until one's deterministic total is within the margin, if none are found
+    /// within the maximum attempts the closest candidate is used
+    fn next_balanced_battle(&self, roster : &Roster, treasure : &Treasure, settings : &BalanceSettings) -> Battle{
+        let mut stats = BalanceStats::default();
+        let mut closest : Option<(f32, Battle)> = None;
+
+        for _ in 0..settings.max_attempts.max(1){
+            let b = self.source.next_battle(roster, treasure, &self.profile);
+            let total = b.get_deterministic_total();
+            stats.record_candidate(total);
+            if total.abs() <= settings.margin{
+                stats.record_accepted(total, false);
+                self.merge_stats(&stats);
+                return b;
+            }
+            if closest.as_ref().map(|(t,_)| total.abs() < t.abs()).unwrap_or(true){
+                closest = Some((total, b));
+            }
+        }
+
+        let (total, b) = closest.unwrap();
+        stats.record_accepted(total, true);
+        self.merge_stats(&stats);
+        b
+    }
+
+    /// Add stats from a single run to the shared stats
+    fn merge_stats(&self, run : &BalanceStats){
+        let mut s = self.stats.lock().unwrap();
+        s.candidates += run.candidates;
+        s.accepted += run.accepted;
+        s.exhausted += run.exhausted;
+        s.candidate_total_sum += run.candidate_total_sum;
+        s.candidate_abs_total_sum += run.candidate_abs_total_sum;
+        s.accepted_total_sum += run.accepted_total_sum;
+        s.accepted_abs_total_sum += run.accepted_abs_total_sum;
+    }
+
+    /// Get BattleType of every run, None if it is randomized
+    pub fn get_battle_type(&self) -> Option<BattleType>{
+        self.source.get_battle_type()
+    }
+
+    /// Get the Battle used for every run, None if battles are generated
+    pub fn get_fixed_battle(&self) -> Option<&Battle>{
+        match &self.source{
+            BattleSource::Fixed(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Get balance settings
+    pub fn get_balance(&self) -> Option<&BalanceSettings>{
+        self.balance.as_ref()
+    }
+
+    /// Get balance statistics collected so far
+    pub fn get_balance_stats(&self) -> BalanceStats{
+        self.stats.lock().unwrap().clone()
+    }
+}
+
+/// Value in a battle JSON that is either fixed or marked as "Random" and filled in by the generators at each run
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum Randomizable<T>{
+    Random(RandomMarker),
+    Fixed(T),
+}
+
+/// Marker for a randomized value, serialized as the string "Random"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RandomMarker{
+    Random,
+}
+
+impl<T: Clone> Randomizable<T>{
+    /// Get the fixed value or generate a new one
+    pub fn resolve(&self, generate : impl FnOnce() -> T) -> T{
+        match self{
+            Randomizable::Random(_) => generate(),
+            Randomizable::Fixed(v) => v.clone(),
+        }
+    }
+
+    /// Get if the value is randomized
+    pub fn is_random(&self) -> bool{
+        matches!(self, Randomizable::Random(_))
+    }
+
+    /// Get the fixed value, None if randomized
+    pub fn fixed(&self) -> Option<&T>{
+        match self{
+            Randomizable::Random(_) => None,
+            Randomizable::Fixed(v) => Some(v),
+        }
+    }
+}
+
+/// Choose an index using the given weights
+fn weighted_choice(weights : &[u32]) -> usize{
+    WeightedIndex::new(weights).expect("Invalid weights for random choice").sample(&mut crate::rng::thread_rng())
+}
+
+/// Relative weights for choosing a Faction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct FactionWeights{
+    pub rebel : u32,
+    pub beladimir : u32,
+    pub lerastir : u32,
+    pub menoriad : u32,
+}
+
+impl Default for FactionWeights{
+    fn default() -> Self {
+        FactionWeights{ rebel: 1, beladimir: 1, lerastir: 1, menoriad: 1 }
+    }
+}
+
+impl FactionWeights{
+    /// Get weights in Faction order
+    fn as_array(&self) -> [u32;4]{
+        [self.rebel, self.beladimir, self.lerastir, self.menoriad]
+    }
+
+    /// Parse comma separated weights in Faction order
+    pub fn from_list(list : &str) -> Self{
+        let w = parse_weights(list, 4);
+        FactionWeights{ rebel: w[0], beladimir: w[1], lerastir: w[2], menoriad: w[3] }
+    }
+}
+
+/// Relative weights for choosing a UnitType
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct UnitTypeWeights{
+    pub melee : u32,
+    pub cavalry : u32,
+    pub ranged : u32,
+}
+
+impl Default for UnitTypeWeights{
+    fn default() -> Self {
+        UnitTypeWeights{ melee: 1, cavalry: 1, ranged: 1 }
+    }
+}
+
+impl UnitTypeWeights{
+    /// Get weights in UnitType order
+    fn as_array(&self) -> [u32;3]{
+        [self.melee, self.cavalry, self.ranged]
+    }
+
+    /// Parse comma separated weights in UnitType order
+    pub fn from_list(list : &str) -> Self{
+        let w = parse_weights(list, 3);
+        UnitTypeWeights{ melee: w[0], cavalry: w[1], ranged: w[2] }
+    }
+}
+
+/// Relative weights for choosing a BattleType
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct BattleTypeWeights{
+    pub normal : u32,
+    pub siege : u32,
+    pub raid : u32,
+    pub naval : u32,
+    pub monster : u32,
+}
+
+impl Default for BattleTypeWeights{
+    fn default() -> Self {
+        BattleTypeWeights{ normal: 1, siege: 1, raid: 1, naval: 1, monster: 1 }
+    }
+}
+
+impl BattleTypeWeights{
+    /// Get weights in BattleType order
+    fn as_array(&self) -> [u32;5]{
+        [self.normal, self.siege, self.raid, self.naval, self.monster]
+    }
+
+    /// Parse comma separated weights in BattleType order
+    pub fn from_list(list : &str) -> Self{
+        let w = parse_weights(list, 5);
+        BattleTypeWeights{ normal: w[0], siege: w[1], raid: w[2], naval: w[3], monster: w[4] }
+    }
+}
+
+/// Parse a comma separated list of weights, panics if the count does not match
+fn parse_weights(list : &str, count : usize) -> Vec<u32>{
+    let w = list.split(',')
+        .map(|s| s.trim().parse::<u32>().unwrap_or_else(|_| panic!("Invalid weight {} in {}", s, list)))
+        .collect::<Vec<u32>>();
+    if w.len() != count{
+        panic!("Expected {} weights, found {} in {}", count, w.len(), list);
+    }
+    w
+}
+
+/// Parse an inclusive range in the form MIN-MAX or a single value
+pub fn parse_range(range : &str) -> (u32, u32){
+    let parse = |s : &str| s.trim().parse::<u32>().unwrap_or_else(|_| panic!("Invalid range {}", range));
+    match range.split_once('-'){
+        Some((min, max)) => (parse(min), parse(max)),
+        None => (parse(range), parse(range)),
+    }
+}
+
+/// Parse an inclusive range of possibly negative values in the form MIN-MAX or a single value, e.g. -2--1
+pub fn parse_signed_range(range : &str) -> (i32, i32){
+    let parse = |s : &str| s.trim().parse::<i32>().unwrap_or_else(|_| panic!("Invalid range {}", range));
+    // the separator is the first '-' after the minimum's digits
+    match range.char_indices().find(|(i, c)| *c == '-' && *i > 0 && !range[..*i].trim().ends_with('-')){
+        Some((i, _)) => (parse(&range[..i]), parse(&range[i + 1..])),
+        None => (parse(range), parse(range)),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_default_profile_valid(){
+        GeneratorProfile::default().validate();
+    }
+
+    #[test]
+    fn test_embedded_profile(){
+        assert_eq!(GeneratorProfile::default(), GeneratorProfile::from_json("embedded:generator_profile.json"));
+    }
+
+    #[test]
+    fn test_partial_json_profile(){
+        let p : GeneratorProfile = serde_json::from_str(r#"{"rank_range":[2,4],"faction_weights":{"rebel":0}}"#).unwrap();
+        assert_eq!((2,4), p.rank_range);
+        assert_eq!(0, p.faction_weights.rebel);
+        assert_eq!(1, p.faction_weights.menoriad);
+        assert_eq!(GeneratorProfile::default().army_size, p.army_size);
+    }
+
+    #[test]
+    fn test_profile_template(){
+        let p = GeneratorProfile::from_json("./ResourceFiles/generator_profile_template.json");
+        assert_eq!(GeneratorProfile::default(), p);
+    }
+
+    #[test]
+    fn test_weighted_choices(){
+        let p = GeneratorProfile{
+            faction_weights: FactionWeights::from_list("0,0,1,0"),
+            unit_type_weights: Some(UnitTypeWeights::from_list("1,0,0")),
+            battle_type_weights: BattleTypeWeights::from_list("0,0,0,1,0"),
+            ..Default::default()
+        };
+        for _ in 0..20{
+            assert_eq!(Faction::Lerastir, p.random_faction());
+            assert_eq!("Naval", p.random_battle_type().get_name());
+            assert_eq!(Some(UnitType::Melee), p.random_unit_type(&[UnitType::Melee, UnitType::Ranged]));
+        }
+        assert_eq!(None, p.random_unit_type(&[UnitType::Ranged]));
+    }
+
+    #[test]
+    fn test_ranges(){
+        assert_eq!((2,20), parse_range("2-20"));
+        assert_eq!((3,3), parse_range("3"));
+        assert_eq!((-2,-1), parse_signed_range("-2--1"));
+        assert_eq!((-1,3), parse_signed_range("-1-3"));
+        assert_eq!((1,3), parse_signed_range("1-3"));
+        assert_eq!((-4,-4), parse_signed_range("-4"));
+
+        let p = GeneratorProfile{ rank_range: (4,4), army_size: (7,7), ..Default::default() };
+        assert_eq!(4, p.random_rank());
+        assert_eq!(7, p.random_army_size());
+    }
+
+    #[test]
+    fn test_randomizable(){
+        let r : Randomizable<i32> = serde_json::from_str("\"Random\"").unwrap();
+        assert!(r.is_random());
+        assert_eq!(5, r.resolve(|| 5));
+        let f : Randomizable<i32> = serde_json::from_str("3").unwrap();
+        assert!(!f.is_random());
+        assert_eq!(3, f.resolve(|| 5));
+        assert_eq!("\"Random\"", serde_json::to_string(&r).unwrap());
+        assert_eq!("3", serde_json::to_string(&f).unwrap());
+    }
+
+    #[test]
+    fn test_balanced_generation(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let settings = BalanceSettings{ margin: 10.0, max_attempts: 10000 };
+        let g = BattleGenerator::new(BattleSource::Random(Some(BattleType::Normal)), GeneratorProfile::default(), Some(settings));
+
+        for _ in 0..20{
+            let b = g.next_battle(&r, &t);
+            assert!(b.get_deterministic_total().abs() <= settings.margin);
+        }
+        let stats = g.get_balance_stats();
+        assert_eq!(0, stats.get_exhausted());
+        assert_eq!(20, stats.get_candidates() - stats.get_rejected());
+
+        // clones share statistics
+        let c = g.clone();
+        c.next_battle(&r, &t);
+        assert_eq!(21, g.get_balance_stats().get_candidates() - g.get_balance_stats().get_rejected());
+    }
+
+    #[test]
+    fn test_balanced_generation_exhausted(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let settings = BalanceSettings{ margin: -1.0, max_attempts: 5 };
+        let g = BattleGenerator::new(BattleSource::Random(None), GeneratorProfile::default(), Some(settings));
+        g.next_battle(&r, &t);
+        let stats = g.get_balance_stats();
+        assert_eq!(5, stats.get_candidates());
+        assert_eq!(4, stats.get_rejected());
+        assert_eq!(1, stats.get_exhausted());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_range(){
+        let p = GeneratorProfile{ army_size: (5,2), ..Default::default() };
+        p.validate();
+    }
+}
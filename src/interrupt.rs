@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Once;
+
+/// Exit code of a process stopped by SIGINT
+const INTERRUPTED_EXIT_CODE : i32 = 130;
+
+/// Set once a run has been interrupted
+static INTERRUPTED : AtomicBool = AtomicBool::new(false);
+
+/// Guards the handler so installing it more than once is harmless
+static INSTALL : Once = Once::new();
+
+/// Install Ctrl-C handler that stops the run so completed battles can be saved,
+/// a second Ctrl-C exits immediately. Only the first call installs the handler
+pub fn install(){
+    INSTALL.call_once(|| {
+        ctrlc::set_handler(|| {
+            if INTERRUPTED.swap(true, Ordering::SeqCst){
+                std::process::exit(INTERRUPTED_EXIT_CODE);
+            }
+            eprintln!("\nInterrupted, saving completed battles. Press Ctrl-C again to exit immediately");
+        }).expect("Unable to set Ctrl-C handler");
+    });
+}
+
+/// Get if the run has been interrupted
+pub fn is_interrupted() -> bool{
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_install_twice(){
+        install();
+        install();
+        assert!(!is_interrupted());
+    }
+}
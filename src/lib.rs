@@ -0,0 +1,33 @@
+
+pub mod treasure;
+pub mod roster;
+pub mod unit;
+pub mod equipment;
+pub mod faction;
+pub mod general;
+pub mod player;
+pub mod battle;
+pub mod monster;
+pub mod config;
+pub mod generator;
+pub mod export;
+pub mod rng;
+pub mod manifest;
+pub mod output;
+pub mod database;
+pub mod analyze;
+pub mod executor;
+pub mod progress;
+pub mod resolver;
+pub mod precision;
+pub mod interrupt;
+pub mod resources;
+pub mod compare;
+pub mod validate;
+pub mod settings;
+pub mod pack;
+pub mod notation;
+pub mod army;
+pub mod schema;
+#[cfg(test)]
+pub mod testing;
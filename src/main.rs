@@ -1,15 +1,4 @@
-mod treasure;
-mod roster;
-mod unit;
-mod equipment;
-mod faction;
-mod general;
-mod player;
-mod battle;
-mod monster;
-mod config;
-
-use crate::config::Config;
+use autoresolve::config::Config;
 
 fn main() {
 
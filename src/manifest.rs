@@ -0,0 +1,146 @@
+use crate::generator::GeneratorProfile;
+use crate::resources;
+use crate::pack::PackRecord;
+use crate::battle::BattleJSONObject;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Version of the autoresolve rules used to calculate battles
+pub const RULESET : &str = "AutoResolve2.0";
+
+/// Record of how a set of saved battle runs was produced
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RunManifest{
+    /// Unique id of the run, its timestamp and seed with a random suffix so runs with the same seed in the same second differ
+    pub run_id : String,
+    pub tool_version : String,
+    pub timestamp : u64,
+    pub cli_args : Vec<String>,
+    pub seed : u64,
+    /// Number of battles run, fewer than the maximum count when running until a precision is reached
+    pub run_count : u32,
+    pub ruleset : String,
+    pub roster_file : String,
+    pub roster_sha256 : String,
+    pub treasure_file : String,
+    pub treasure_sha256 : String,
+    /// Content packs merged into the roster and treasure, in load order
+    #[serde(default)]
+    pub packs : Vec<PackRecord>,
+    pub battle_file : Option<String>,
+    pub battle_json : Option<serde_json::Value>,
+    pub generator_profile : GeneratorProfile,
+    pub output_file : String,
+    /// First data row (1-based, excluding the header) written to the output file by this run
+    pub first_row : u64,
+    pub row_count : u64,
+    /// If the run was interrupted before all its battles were resolved
+    #[serde(default)]
+    pub partial : bool,
+}
+
+impl RunManifest{
+    /// Create new RunManifest for a run, reading input files for hashing
+    pub fn new(cli_args : Vec<String>, seed : u64, run_count : u32, roster_file : &str, treasure_file : &str,
+               battle_file : Option<&str>, generator_profile : &GeneratorProfile) -> Self{
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        RunManifest{
+            run_id: format!("{}-{:016x}-{:08x}", timestamp, seed, rand::random::<u32>()),
+            tool_version: String::from(env!("CARGO_PKG_VERSION")),
+            timestamp,
+            cli_args,
+            seed,
+            run_count,
+            ruleset: String::from(RULESET),
+            roster_file: String::from(roster_file),
+            roster_sha256: hash_file(roster_file),
+            treasure_file: String::from(treasure_file),
+            treasure_sha256: hash_file(treasure_file),
+            packs: vec![],
+            battle_file: battle_file.map(String::from),
+            battle_json: battle_file.map(BattleJSONObject::read_value),
+            generator_profile: generator_profile.clone(),
+            output_file: String::new(),
+            first_row: 0,
+            row_count: 0,
+            partial: false,
+        }
+    }
+
+    /// Set the output file and rows that the run's data was written to
+    pub fn set_output(&mut self, output_file : &str, first_row : u64, row_count : u64){
+        self.output_file = String::from(output_file);
+        self.first_row = first_row;
+        self.row_count = row_count;
+    }
+
+    /// Get manifest file path for an output file
+    pub fn manifest_path(output_file : &str) -> String{
+        format!("{}.manifest.jsonl", output_file)
+    }
+
+    /// Append self as a line to the manifest file next to the output file, return the manifest file path
+    pub fn save(&self) -> String{
+        let path = Self::manifest_path(&self.output_file);
+        let mut f = OpenOptions::new().create(true).append(true).open(&path)
+            .unwrap_or_else(|e| panic!("Unable to open {}: {}", path, e));
+        writeln!(f, "{}", serde_json::to_string(self).unwrap()).unwrap();
+        path
+    }
+
+    /// Read all manifests saved next to an output file
+    pub fn read_all(output_file : &str) -> Vec<RunManifest>{
+        fs::read_to_string(Self::manifest_path(output_file)).unwrap_or_default()
+            .lines()
+            .map(|l| serde_json::from_str(l).expect("Unable to parse manifest"))
+            .collect()
+    }
+}
+
+/// Get hex encoded SHA-256 of a resource file's contents
+pub fn hash_file(file_path : &str) -> String{
+    hash_bytes(resources::read_to_string(file_path).as_bytes())
+}
+
+/// Get hex encoded SHA-256 of bytes
+pub fn hash_bytes(bytes : &[u8]) -> String{
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_hash(){
+        assert_eq!("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", hash_bytes(b""));
+        assert_eq!(hash_bytes(&fs::read("./ResourceFiles/units.csv").unwrap()), hash_file("./ResourceFiles/units.csv"));
+    }
+
+    #[test]
+    fn test_save_manifest(){
+        let dir = TestDir::new("manifest");
+        let output = &dir.file("test_manifest.csv");
+        let mut m = RunManifest::new(vec![String::from("-s")], 7, 10,
+                                     "./ResourceFiles/units.csv", "./ResourceFiles/equipment.csv",
+                                     Some("./ResourceFiles/normal_battle_template.json"), &GeneratorProfile::default());
+        assert_eq!(RULESET, m.ruleset);
+        assert!(m.run_id.contains("-0000000000000007-"));
+        assert_eq!("Normal", m.battle_json.as_ref().unwrap()["battle_type"]);
+
+        m.set_output(output, 1, 10);
+        m.save();
+        m.set_output(output, 11, 10);
+        m.save();
+
+        let saved = RunManifest::read_all(output);
+        assert_eq!(2, saved.len());
+        assert_eq!(1, saved[0].first_row);
+        assert_eq!(m, saved[1]);
+    }
+}
@@ -13,7 +13,13 @@ pub enum MonsterType {
 }
 
 impl MonsterType {
+    /// All monster types, from weakest to strongest
+    pub const ALL : [MonsterType; 6] = [MonsterType::Minotaur, MonsterType::Hobgoblin, MonsterType::Troll, MonsterType::Giant,
+        MonsterType::Demon, MonsterType::Dragon];
+
     /// Get coin reward for given monster type
+    // monster rewards are not part of autoresolve results yet
+    #[allow(dead_code)]
     fn coin_reward(&self) -> i32{
         match *self{
             MonsterType::Minotaur => 200,
@@ -38,6 +44,7 @@ impl MonsterType {
     }
 
     /// Get list of equipment types as reward for given monster type, return empty for Dragon
+    #[allow(dead_code)]
     fn rewards(&self) -> Vec<EquipmentType>{
         match *self{
             MonsterType::Minotaur => vec![EquipmentType::Weapon],
@@ -51,7 +58,7 @@ impl MonsterType {
 
     /// Get random Monster
     pub fn get_random_monster() -> Self{
-        let mut rng = rand::thread_rng();
+        let mut rng = crate::rng::thread_rng();
         match rng.gen_range(1..=6){
             1 => MonsterType::Minotaur,
             2 => MonsterType::Hobgoblin,
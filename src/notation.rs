@@ -0,0 +1,236 @@
+use crate::battle::{BattleType, TownDefenses, TownStats};
+use crate::equipment::EquipmentType;
+use crate::faction::Faction;
+use crate::general::GeneralJSONObject;
+use crate::generator::GeneratorProfile;
+use crate::monster::MonsterType;
+use crate::pack::EntryRef;
+use crate::player::{Player, PlayerJSONObject, UnitCounts, UnitsEntry};
+use crate::roster::Roster;
+use crate::treasure::Treasure;
+
+/// Equipment slots of a general, in the order they are written
+const SLOTS : [EquipmentType; 5] = [EquipmentType::Armor, EquipmentType::Weapon, EquipmentType::Banner,
+    EquipmentType::Follower, EquipmentType::Trinket];
+
+/// Parse army notation, e.g. "Beladimir: 3x Beladimir Spearmen, 2x Archers of Beladimir; general r3 weapon=10 banner=28;
+/// reinforcements 1; adv". Units and equipment are ids, namespaced ids or names, looked up when the player is produced
+pub fn parse_army(text : &str) -> Result<PlayerJSONObject, String>{
+    let (faction, rest) = text.split_once(':').ok_or_else(|| String::from("expected FACTION: UNITS, e.g. Beladimir: 3x Beladimir Spearmen"))?;
+    let faction = Faction::from_name(faction.trim())
+        .ok_or_else(|| format!("invalid faction {}, expected one of {}", faction.trim(), Faction::NAMES.join(", ")))?;
+    let mut sections = rest.split(';');
+    let units = sections.next().unwrap_or("").split(',').map(|u| u.trim()).filter(|u| !u.is_empty())
+        .map(parse_units_entry).collect();
+    let mut general = GeneralJSONObject::new(SLOTS.map(|_| EntryRef::Id(0)), 0);
+    let mut reinforcements = 0;
+    let mut adv_combat = false;
+    for section in sections.map(|s| s.trim()).filter(|s| !s.is_empty()){
+        let (name, values) = section.split_once(char::is_whitespace).unwrap_or((section, ""));
+        match name.to_lowercase().as_str(){
+            "general" => general = parse_general(values)?,
+            "reinforcements" => reinforcements = values.trim().parse().ok().filter(|r| *r >= 0)
+                .ok_or_else(|| format!("invalid reinforcements {}", values.trim()))?,
+            "adv" if values.trim().is_empty() => adv_combat = true,
+            _ => return Err(format!("invalid section \"{}\", expected general, reinforcements or adv", section)),
+        }
+    }
+    Ok(PlayerJSONObject::new(general, units, reinforcements, adv_combat, faction))
+}
+
+/// Parse army notation and produce the Player, panics if the notation is invalid or refers to unknown units or items
+pub fn parse_player(text : &str, roster : &Roster, treasure : &Treasure) -> Player{
+    parse_army(text).unwrap_or_else(|e| panic!("Invalid army \"{}\": {}", text, e))
+        .produce_player(roster, treasure, &GeneratorProfile::default())
+}
+
+/// Parse a unit with an optional count, e.g. "3x Beladimir Spearmen"
+fn parse_units_entry(text : &str) -> UnitsEntry{
+    let count = text.split_once(char::is_whitespace)
+        .and_then(|(n, unit)| n.strip_suffix(['x', 'X']).and_then(|n| n.parse::<u32>().ok()).map(|n| (n, unit.trim())));
+    match count{
+        Some((n, unit)) => UnitsEntry::Counts(UnitCounts(vec![(parse_ref(unit), n)])),
+        None => UnitsEntry::Unit(parse_ref(text)),
+    }
+}
+
+/// Parse reference to a unit or item by id, namespaced id or name
+fn parse_ref(text : &str) -> EntryRef{
+    text.parse().map(EntryRef::Id).unwrap_or_else(|_| EntryRef::Key(String::from(text)))
+}
+
+/// Parse general section after "general", e.g. "r3 weapon=10 banner=Great Standard"
+fn parse_general(text : &str) -> Result<GeneralJSONObject, String>{
+    let mut rank = 0;
+    let mut equipment : Vec<(EquipmentType, Vec<&str>)> = vec![];
+    for word in text.split_whitespace(){
+        let rank_value = word.strip_prefix(['r', 'R']).and_then(|r| r.parse().ok());
+        match (rank_value, word.split_once('=')){
+            (Some(r), _) => rank = r,
+            (None, Some((slot, value))) => {
+                let t = EquipmentType::from_name(slot)
+                    .ok_or_else(|| format!("invalid equipment slot {}, expected one of {}", slot, EquipmentType::NAMES.join(", ")))?;
+                equipment.push((t, vec![value]));
+            },
+            // words of an item name after its slot
+            (None, None) => match equipment.last_mut(){
+                Some((_, value)) => value.push(word),
+                None => return Err(format!("invalid general \"{}\", expected rN and SLOT=ITEM, e.g. r3 weapon=10", text)),
+            },
+        }
+    }
+    let item = |t : EquipmentType| equipment.iter().rev().find(|(s, _)| *s == t)
+        .map_or(EntryRef::Id(0), |(_, v)| parse_ref(&v.join(" ")));
+    Ok(GeneralJSONObject::new(SLOTS.map(item), rank))
+}
+
+/// Format a Player as army notation, the inverse of parse_player. Units and items are written by name when the
+/// name refers to them, otherwise by id
+pub fn format_player(player : &Player, roster : &Roster, treasure : &Treasure) -> String{
+    // count runs of the same unit
+    let mut runs : Vec<(u32, &str, u32)> = vec![];
+    for u in player.get_units().iter(){
+        match runs.last_mut(){
+            Some((id, _, n)) if *id == u.get_id() => *n += 1,
+            _ => runs.push((u.get_id(), u.get_name(), 1)),
+        }
+    }
+    let units : Vec<String> = runs.iter().map(|(id, name, n)| {
+        // names that would be read as an id or a count are written as the id
+        let named = roster.find_unit(&EntryRef::Key(String::from(*name))) == Ok(*id)
+            && parse_units_entry(name) == UnitsEntry::Unit(EntryRef::Key(String::from(*name)));
+        let unit = if named{ String::from(*name) } else { id.to_string() };
+        match n{
+            1 => unit,
+            _ => format!("{}x {}", n, unit),
+        }
+    }).collect();
+    let mut sections = vec![format!("{:?}: {}", player.get_faction(), units.join(", "))];
+
+    let general = player.get_general();
+    let equipment : Vec<String> = SLOTS.iter().filter_map(|t| general.get_equipment(*t).map(|e| {
+        let named = treasure.get_item_by_ref(&EntryRef::Key(String::from(e.get_name())), *t).ok().flatten()
+            .is_some_and(|found| found.get_id() == e.get_id());
+        format!("{}={}", format!("{:?}", t).to_lowercase(), if named{ String::from(e.get_name()) } else { e.get_id().to_string() })
+    })).collect();
+    if general.get_rank() != 0 || !equipment.is_empty(){
+        sections.push(format!("general r{}", general.get_rank()) + &equipment.iter().map(|e| format!(" {}", e)).collect::<String>());
+    }
+    if player.get_reinforcements() != 0{
+        sections.push(format!("reinforcements {}", player.get_reinforcements()));
+    }
+    if player.has_advanced_combat_deck(){
+        sections.push(String::from("adv"));
+    }
+    sections.join("; ")
+}
+
+/// Parse battle type notation, e.g. "Normal", "Siege rams=3 catapults=0 towers=4 defenses=StoneWall supplies=2",
+/// "Raid defenses=WoodenWall supplies=1", "Naval attacker=3 defender=2" or "Monster Dragon". Missing values are 0 or None
+pub fn parse_battle_type(text : &str) -> Result<BattleType, String>{
+    let mut words = text.split_whitespace();
+    let name = words.next().ok_or_else(|| String::from("empty battle type"))?;
+    let words : Vec<&str> = words.collect();
+    let keys : &[&str] = match name.to_lowercase().as_str(){
+        "normal" => &[],
+        "siege" => &["rams", "catapults", "towers", "defenses", "supplies"],
+        "raid" => &["defenses", "supplies"],
+        "naval" => &["attacker", "defender"],
+        "monster" => return match words.as_slice(){
+            [m] => MonsterType::ALL.iter().find(|t| format!("{:?}", t).eq_ignore_ascii_case(m)).map(|t| BattleType::Monster{ monster: *t })
+                .ok_or_else(|| format!("invalid monster {}, expected one of {}", m, names(&MonsterType::ALL))),
+            _ => Err(format!("expected one monster, e.g. Monster Dragon, found \"{}\"", words.join(" "))),
+        },
+        _ => return Err(format!("invalid battle type {}, expected one of Normal, Siege, Raid, Naval, Monster", name)),
+    };
+
+    let mut numbers = [0; 4];
+    let mut defenses = TownDefenses::None;
+    for word in words.iter(){
+        let (key, value) = word.split_once('=').map(|(k, v)| (k.to_lowercase(), v))
+            .filter(|(k, _)| keys.contains(&k.as_str()))
+            .ok_or_else(|| format!("invalid {} parameter \"{}\", expected KEY=VALUE with keys {}", name, word, keys.join(", ")))?;
+        match key.as_str(){
+            "defenses" => defenses = *TownDefenses::ALL.iter().find(|d| format!("{:?}", d).eq_ignore_ascii_case(value))
+                .ok_or_else(|| format!("invalid defenses {}, expected one of {}", value, names(&TownDefenses::ALL)))?,
+            _ => {
+                let i = match key.as_str(){ "catapults" | "defender" => 1, "towers" => 2, "supplies" => 3, _ => 0 };
+                numbers[i] = value.parse().ok().filter(|n| *n >= 0).ok_or_else(|| format!("invalid {} {}", key, value))?;
+            },
+        }
+    }
+    Ok(match name.to_lowercase().as_str(){
+        "siege" => BattleType::Siege{ rams: numbers[0], catapults: numbers[1], siege_towers: numbers[2],
+            defenses: TownStats::new(numbers[3], defenses) },
+        "raid" => BattleType::Raid{ defenses: TownStats::new(numbers[3], defenses) },
+        "naval" => BattleType::Naval{ attacker_ships: numbers[0], defender_ships: numbers[1] },
+        _ => BattleType::Normal,
+    })
+}
+
+/// Format a BattleType as battle type notation, the inverse of parse_battle_type
+pub fn format_battle_type(battle_type : &BattleType) -> String{
+    let town = |t : &TownStats| format!("defenses={:?} supplies={}", t.get_defenses(), t.get_supplies());
+    match battle_type{
+        BattleType::Normal => String::from("Normal"),
+        BattleType::Siege{ rams, catapults, siege_towers, defenses } =>
+            format!("Siege rams={} catapults={} towers={} {}", rams, catapults, siege_towers, town(defenses)),
+        BattleType::Raid{ defenses } => format!("Raid {}", town(defenses)),
+        BattleType::Naval{ attacker_ships, defender_ships } => format!("Naval attacker={} defender={}", attacker_ships, defender_ships),
+        BattleType::Monster{ monster } => format!("Monster {:?}", monster),
+    }
+}
+
+/// Join debug names of values
+fn names<T : std::fmt::Debug>(values : &[T]) -> String{
+    values.iter().map(|v| format!("{:?}", v)).collect::<Vec<String>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const ARMY : &str = "Beladimir: 3x Beladimir Spearmen, 2x Archers of Beladimir; general r3 weapon=10 banner=28; reinforcements 1; adv";
+
+    #[test]
+    fn test_round_trip(){
+        let (roster, treasure) = (Roster::embedded(), Treasure::embedded());
+        let p = parse_player(ARMY, &roster, &treasure);
+        assert_eq!(5, p.get_units().len());
+        assert_eq!(3, p.get_unit_count_by_name("Beladimir Spearmen"));
+        assert_eq!((3, 1, true), (p.get_general().get_rank(), p.get_reinforcements(), p.has_advanced_combat_deck()));
+        assert_eq!(Some(28), p.get_general().get_equipment(EquipmentType::Banner).map(|e| e.get_id()));
+        assert_eq!(ARMY, format_player(&p, &roster, &treasure));
+
+        // ids, counts of 1, unambiguous item names and lowercase are written in the same notation
+        let p = parse_player("menoriad: 12, 1x 14 ; GENERAL armor=Crystalline  Armor r2 follower=37", &roster, &treasure);
+        assert_eq!("Menoriad: Beladimir Spearmen, Archers of Beladimir; general r2 armor=Crystalline Armor follower=Barbarian",
+                   format_player(&p, &roster, &treasure));
+        let p = parse_player("Rebel:", &roster, &treasure);
+        assert_eq!("Rebel: ", format_player(&p, &roster, &treasure));
+    }
+
+    #[test]
+    fn test_invalid_army(){
+        assert_eq!(Err(String::from("invalid faction Beladimr, expected one of Rebel, Beladimir, Lerastir, Menoriad")),
+                   parse_army("Beladimr: 12").map(|_| ()));
+        assert_eq!(Err(String::from("invalid section \"adv combat\", expected general, reinforcements or adv")),
+                   parse_army("Rebel: 1; adv combat").map(|_| ()));
+        assert_eq!(Err(String::from("invalid equipment slot shield, expected one of Armor, Weapon, Trinket, Banner, Follower")),
+                   parse_army("Rebel: 1; general shield=3").map(|_| ()));
+        assert!(parse_army("Rebel 1").is_err());
+    }
+
+    #[test]
+    fn test_battle_type(){
+        for text in ["Normal", "Siege rams=3 catapults=0 towers=4 defenses=StoneWall supplies=2", "Raid defenses=WoodenWall supplies=1",
+                     "Naval attacker=3 defender=2", "Monster Dragon"].iter(){
+            assert_eq!(*text, format_battle_type(&parse_battle_type(text).unwrap()));
+        }
+        assert_eq!(Ok(BattleType::Siege{ rams: 2, catapults: 0, siege_towers: 0, defenses: TownStats::default() }),
+                   parse_battle_type("siege RAMS=2"));
+        assert_eq!(Err(String::from("invalid Naval parameter \"rams=2\", expected KEY=VALUE with keys attacker, defender")),
+                   parse_battle_type("Naval rams=2"));
+        assert!(parse_battle_type("Monster Wyvern").is_err());
+    }
+}
@@ -0,0 +1,733 @@
+use crate::battle::{BattleData, BattleOutcome, BattleResults, PlayerData};
+use crate::database::BattleDatabase;
+use crate::general::GeneralState;
+use crate::manifest::RunManifest;
+use crate::roster::Roster;
+use serde::{Serialize, Serializer};
+use serde::ser::SerializeStruct;
+use std::fs;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Format that battle run results are saved in
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OutputFormat{
+    /// One CSV row per battle
+    Csv,
+    /// One JSON record per line per battle
+    JsonLines,
+    /// A single JSON document summarizing all battles of a run
+    JsonSummary,
+    /// Normalized tables in a SQLite database shared by all battle types
+    Sqlite,
+}
+
+impl OutputFormat{
+    /// Get enum from name
+    pub fn from_name(name : &str) -> Self{
+        match name{
+            "csv" => OutputFormat::Csv,
+            "jsonl" => OutputFormat::JsonLines,
+            "json" => OutputFormat::JsonSummary,
+            "sqlite" => OutputFormat::Sqlite,
+            _ => panic!("Invalid output format {}, expected csv, jsonl, json or sqlite", name)
+        }
+    }
+
+    /// Get file extension for output files of the format
+    pub fn get_extension(&self) -> &str{
+        match self{
+            OutputFormat::Csv => "csv",
+            OutputFormat::JsonLines => "jsonl",
+            OutputFormat::JsonSummary => "json",
+            OutputFormat::Sqlite => "db",
+        }
+    }
+}
+
+/// How an existing output file is treated when a run is saved
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum WriteMode{
+    /// Add the run's battles after the battles already in the file
+    Append,
+    /// Replace the battles already in the file
+    Overwrite,
+    /// Move the existing file to the first unused numbered file, e.g. NormalData.1.csv, and start a new one
+    Rotate,
+}
+
+impl WriteMode{
+    /// Names of modes used by CLI
+    pub const NAMES : [&'static str; 3] = ["append", "overwrite", "rotate"];
+
+    /// Get enum from name
+    pub fn from_name(name : &str) -> Self{
+        match name{
+            "append" => WriteMode::Append,
+            "overwrite" => WriteMode::Overwrite,
+            "rotate" => WriteMode::Rotate,
+            _ => panic!("Invalid write mode {}, expected append, overwrite or rotate", name)
+        }
+    }
+}
+
+/// Open file for appending, creating it and its directory if they don't exist, and take an advisory lock on it,
+/// waiting for any other run writing to it. Lines already in the file are checked against the header,
+/// which is written to a new file. A file with a different header, e.g. from an older version, is rotated
+/// and a new file started. Return the locked file and the number of rows after the header
+pub fn open_locked(path : &str, header : Option<&str>) -> (File, u64){
+    read_locked(open_current(path), path, header)
+}
+
+/// Check the lines of a file locked by open_current against the header, as done by open_locked
+fn read_locked(mut f : File, path : &str, header : Option<&str>) -> (File, u64){
+    let mut lines = BufReader::new(&f).lines().map(|l| l.unwrap_or_else(|e| panic!("Unable to read {}: {}", path, e)));
+    let first = lines.next();
+    let rows = lines.count() as u64 + first.is_some() as u64;
+    match (header, first){
+        (Some(h), None) => {
+            println!("Created output file at {} for battle data", path);
+            writeln!(f, "{}", h).unwrap();
+            (f, 0)
+        },
+        (Some(_), Some(existing)) if Some(existing.as_str()) != header => {
+            let rotated = rotate(path);
+            println!("Warning: header of {} does not match the current columns, moved it to {} and started a new file", path, rotated);
+            // lock the new file before releasing the moved one
+            let new = open_current(path);
+            drop(f);
+            read_locked(new, path, header)
+        },
+        (Some(_), Some(_)) => (f, rows - 1),
+        (None, _) => (f, rows),
+    }
+}
+
+/// Open file for appending, creating it and its directory if they don't exist, and lock it. The file is reopened
+/// if another run moved or replaced it while waiting for the lock, so the locked file is always the one at path
+fn open_current(path : &str) -> File{
+    if let Some(dir) = Path::new(path).parent().filter(|d| !d.as_os_str().is_empty()){
+        fs::create_dir_all(dir).unwrap_or_else(|e| panic!("Unable to create directory {}: {}", dir.display(), e));
+    }
+    loop{
+        let f = OpenOptions::new().read(true).append(true).create(true).open(path)
+            .unwrap_or_else(|e| panic!("Unable to open {}: {}", path, e));
+        lock(&f, path);
+        match (f.metadata(), fs::metadata(path)){
+            (Ok(locked), Ok(current)) if same_file(&locked, &current) => return f,
+            _ => {},
+        }
+    }
+}
+
+/// Get if metadata of two open files is of the same file
+#[cfg(unix)]
+fn same_file(a : &fs::Metadata, b : &fs::Metadata) -> bool{
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+/// Get if metadata of two open files is of the same file
+#[cfg(not(unix))]
+fn same_file(a : &fs::Metadata, b : &fs::Metadata) -> bool{
+    a.len() == b.len() && a.created().ok() == b.created().ok() && a.modified().ok() == b.modified().ok()
+}
+
+/// Take an advisory lock on a file, waiting for any other process holding it
+fn lock(f : &File, path : &str){
+    match f.try_lock(){
+        Ok(()) => {},
+        Err(TryLockError::WouldBlock) => {
+            println!("Waiting for another run to finish writing to {}", path);
+            f.lock().unwrap_or_else(|e| panic!("Unable to lock {}: {}", path, e));
+        },
+        Err(TryLockError::Error(e)) => panic!("Unable to lock {}: {}", path, e),
+    }
+}
+
+/// Get first unused numbered path to rotate a file to, e.g. NormalData.1.csv for NormalData.csv
+pub fn rotated_path(path : &str) -> String{
+    let p = Path::new(path);
+    let stem = p.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = p.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (1..).map(|n| p.with_file_name(format!("{}.{}{}", stem, n, ext)).to_string_lossy().to_string())
+        .find(|r| !Path::new(r).exists())
+        .unwrap()
+}
+
+/// Prepare output file for a run's write mode and return it locked, waiting for any other run writing to it.
+/// Overwriting empties the file and removes its manifest, rotating moves the file and its manifest and starts a new one
+fn prepare_file(path : &str, mode : WriteMode) -> File{
+    let f = open_current(path);
+    let empty = f.metadata().map(|m| m.len() == 0).unwrap_or(true);
+    if mode == WriteMode::Append || empty{
+        return f;
+    }
+    match mode{
+        WriteMode::Overwrite => {
+            f.set_len(0).unwrap_or_else(|e| panic!("Unable to overwrite {}: {}", path, e));
+            let manifest = RunManifest::manifest_path(path);
+            if Path::new(&manifest).exists(){
+                fs::remove_file(&manifest).unwrap_or_else(|e| panic!("Unable to remove {}: {}", manifest, e));
+            }
+            println!("Overwriting output file {}", path);
+            f
+        },
+        _ => {
+            println!("Moved existing output file {} to {}", path, rotate(path));
+            // lock the new file before releasing the moved one
+            open_current(path)
+        },
+    }
+}
+
+/// Move a file and its manifest to the first unused numbered path, return the path it was moved to
+fn rotate(path : &str) -> String{
+    let rotated = rotated_path(path);
+    fs::rename(path, &rotated).unwrap_or_else(|e| panic!("Unable to move {} to {}: {}", path, rotated, e));
+    let manifest = RunManifest::manifest_path(path);
+    if Path::new(&manifest).exists(){
+        fs::rename(&manifest, RunManifest::manifest_path(&rotated)).unwrap();
+    }
+    rotated
+}
+
+/// Running count, mean, standard deviation, minimum and maximum of a set of values
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Statistics{
+    count : u64,
+    sum : f64,
+    sum_squares : f64,
+    min : f64,
+    max : f64,
+}
+
+impl Statistics{
+    /// Add a value
+    pub fn add(&mut self, value : f64){
+        if self.count == 0 || value < self.min{
+            self.min = value;
+        }
+        if self.count == 0 || value > self.max{
+            self.max = value;
+        }
+        self.count += 1;
+        self.sum += value;
+        self.sum_squares += value * value;
+    }
+
+    /// Get number of values added
+    pub fn get_count(&self) -> u64{
+        self.count
+    }
+
+    /// Get mean of values, 0 if there are none
+    pub fn get_mean(&self) -> f64{
+        match self.count{
+            0 => 0.0,
+            n => self.sum / n as f64,
+        }
+    }
+
+    /// Get population standard deviation of values, 0 if there are none
+    pub fn get_std_dev(&self) -> f64{
+        match self.count{
+            0 => 0.0,
+            n => (self.sum_squares / n as f64 - self.get_mean().powi(2)).max(0.0).sqrt(),
+        }
+    }
+
+    /// Get smallest value
+    pub fn get_min(&self) -> f64{
+        self.min
+    }
+
+    /// Get largest value
+    pub fn get_max(&self) -> f64{
+        self.max
+    }
+}
+
+impl Serialize for Statistics{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("Statistics", 4)?;
+        s.serialize_field("mean", &self.get_mean())?;
+        s.serialize_field("std_dev", &self.get_std_dev())?;
+        s.serialize_field("min", &self.min)?;
+        s.serialize_field("max", &self.max)?;
+        s.end()
+    }
+}
+
+/// Number of battles with each BattleOutcome
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct OutcomeCounts{
+    decisive_victory : u64,
+    heroic_victory : u64,
+    close_victory : u64,
+    draw : u64,
+    close_defeat : u64,
+    valiant_defeat : u64,
+    crushing_defeat : u64,
+}
+
+impl OutcomeCounts{
+    /// Count a battle's outcome
+    pub fn add(&mut self, outcome : &BattleOutcome){
+        *self.get_mut(outcome) += 1;
+    }
+
+    /// Get number of battles with an outcome
+    pub fn get(&self, outcome : &BattleOutcome) -> u64{
+        match outcome{
+            BattleOutcome::DecisiveVictory => self.decisive_victory,
+            BattleOutcome::HeroicVictory => self.heroic_victory,
+            BattleOutcome::CloseVictory => self.close_victory,
+            BattleOutcome::Draw => self.draw,
+            BattleOutcome::CloseDefeat => self.close_defeat,
+            BattleOutcome::ValiantDefeat => self.valiant_defeat,
+            BattleOutcome::CrushingDefeat => self.crushing_defeat,
+        }
+    }
+
+    /// Add counts from another OutcomeCounts
+    pub fn merge(&mut self, other : &OutcomeCounts){
+        BattleOutcome::ALL.iter().for_each(|o| *self.get_mut(o) += other.get(o));
+    }
+
+    /// Get number of battles counted
+    pub fn get_total(&self) -> u64{
+        BattleOutcome::ALL.iter().map(|o| self.get(o)).sum()
+    }
+
+    fn get_mut(&mut self, outcome : &BattleOutcome) -> &mut u64{
+        match outcome{
+            BattleOutcome::DecisiveVictory => &mut self.decisive_victory,
+            BattleOutcome::HeroicVictory => &mut self.heroic_victory,
+            BattleOutcome::CloseVictory => &mut self.close_victory,
+            BattleOutcome::Draw => &mut self.draw,
+            BattleOutcome::CloseDefeat => &mut self.close_defeat,
+            BattleOutcome::ValiantDefeat => &mut self.valiant_defeat,
+            BattleOutcome::CrushingDefeat => &mut self.crushing_defeat,
+        }
+    }
+}
+
+/// Number of battles ending with the General in each GeneralState
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct GeneralStateCounts{
+    unharmed : u64,
+    wounded : u64,
+    slain : u64,
+}
+
+/// Casualty and treasure statistics for one side of a set of battles
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SideSummary{
+    soldier_casualties : Statistics,
+    unit_casualties : Statistics,
+    upgrades : Statistics,
+    general_states : GeneralStateCounts,
+    treasure_received : u64,
+}
+
+impl SideSummary{
+    /// Add one side's data from a battle
+    fn add(&mut self, data : &PlayerData){
+        self.soldier_casualties.add(data.get_soldier_casualties() as f64);
+        self.unit_casualties.add(data.get_unit_casualties() as f64);
+        self.upgrades.add(data.get_upgrades() as f64);
+        match data.get_general_state(){
+            GeneralState::Unharmed => self.general_states.unharmed += 1,
+            GeneralState::Wounded => self.general_states.wounded += 1,
+            GeneralState::Slain => self.general_states.slain += 1,
+        }
+        if data.get_treasure_received(){
+            self.treasure_received += 1;
+        }
+    }
+
+    /// Get soldier casualty statistics
+    pub fn get_soldier_casualties(&self) -> &Statistics{
+        &self.soldier_casualties
+    }
+
+    /// Get unit casualty statistics
+    pub fn get_unit_casualties(&self) -> &Statistics{
+        &self.unit_casualties
+    }
+}
+
+/// Summary of the outcome distribution and statistics of a set of battles, built up one battle at a time
+#[derive(Debug, Clone, Serialize)]
+pub struct BattleSummary{
+    run_id : Option<String>,
+    seed : u64,
+    battle_type : String,
+    battles : u64,
+    /// If the run was interrupted before all its battles were resolved
+    partial : bool,
+    outcomes : OutcomeCounts,
+    attacker_wins : u64,
+    attacker_win_rate : f64,
+    total : Statistics,
+    attacker : SideSummary,
+    defender : SideSummary,
+}
+
+impl BattleSummary{
+    /// Create new empty BattleSummary for a run
+    pub fn new(seed : u64, battle_type : &str) -> Self{
+        BattleSummary{
+            run_id: None,
+            seed,
+            battle_type: String::from(battle_type),
+            battles: 0,
+            partial: false,
+            outcomes: OutcomeCounts::default(),
+            attacker_wins: 0,
+            attacker_win_rate: 0.0,
+            total: Statistics::default(),
+            attacker: SideSummary::default(),
+            defender: SideSummary::default(),
+        }
+    }
+
+    /// Add a battle to the summary
+    pub fn add(&mut self, data : &BattleData){
+        let outcome = data.get_outcome();
+        self.battles += 1;
+        self.outcomes.add(&outcome);
+        if outcome.is_victory(){
+            self.attacker_wins += 1;
+        }
+        self.attacker_win_rate = self.attacker_wins as f64 / self.battles as f64;
+        self.total.add(data.get_total() as f64);
+        self.attacker.add(data.get_attacker());
+        self.defender.add(data.get_defender());
+    }
+
+    /// Set id of the run manifest the summary is saved with
+    pub fn set_run_id(&mut self, run_id : &str){
+        self.run_id = Some(String::from(run_id));
+    }
+
+    /// Mark the summary as covering only part of an interrupted run
+    pub fn set_partial(&mut self){
+        self.partial = true;
+    }
+
+    /// Get if the summary covers only part of an interrupted run
+    pub fn is_partial(&self) -> bool{
+        self.partial
+    }
+
+    /// Get number of battles added
+    pub fn get_battles(&self) -> u64{
+        self.battles
+    }
+
+    /// Get outcome counts
+    pub fn get_outcomes(&self) -> &OutcomeCounts{
+        &self.outcomes
+    }
+
+    /// Get fraction of battles won by the attacker
+    pub fn get_attacker_win_rate(&self) -> f64{
+        self.attacker_win_rate
+    }
+
+    /// Get ending total statistics
+    pub fn get_total(&self) -> &Statistics{
+        &self.total
+    }
+
+    /// Get attacker statistics
+    pub fn get_attacker(&self) -> &SideSummary{
+        &self.attacker
+    }
+
+    /// Get defender statistics
+    pub fn get_defender(&self) -> &SideSummary{
+        &self.defender
+    }
+
+    /// Convert to pretty printed JSON document
+    pub fn to_json(&self) -> String{
+        serde_json::to_string_pretty(self).unwrap()
+    }
+}
+
+/// Destination of the battles written by a RunWriter
+enum RunSink{
+    /// CSV or JSON Lines file, one line per battle
+    Lines(OutputFormat, BufWriter<File>),
+    /// SQLite database and id of the run being saved
+    Database(BattleDatabase, String),
+    /// JSON summary document, written to the locked file when the run finishes
+    Summary(File),
+}
+
+/// Writes each battle of a run to the output file as it is resolved, so memory use does not grow with the run count
+pub struct RunWriter{
+    path : String,
+    sink : RunSink,
+    first_row : u64,
+    rows : u64,
+}
+
+impl RunWriter{
+    /// Open output file for a run with the write mode, creating it if it doesn't exist.
+    /// Files other than SQLite databases stay locked until the run finishes
+    pub fn open(format : OutputFormat, path : &str, mode : WriteMode, roster : &Roster, manifest : &RunManifest) -> Self{
+        let f = prepare_file(path, mode);
+        let (sink, first_row) = match format{
+            OutputFormat::Csv | OutputFormat::JsonLines => {
+                // header generated from the roster for CSV
+                let header = match format{
+                    OutputFormat::Csv => Some(BattleData::csv_header(roster)),
+                    _ => None,
+                };
+                let (f, existing_rows) = read_locked(f, path, header.as_deref());
+                (RunSink::Lines(format, BufWriter::new(f)), existing_rows + 1)
+            },
+            OutputFormat::Sqlite => {
+                drop(f);
+                let mut db = BattleDatabase::open(path);
+                db.begin_run(manifest);
+                // battle ids continue across runs, so the first row is set by the first battle saved
+                (RunSink::Database(db, manifest.run_id.clone()), 0)
+            },
+            OutputFormat::JsonSummary => (RunSink::Summary(read_locked(f, path, None).0), 1),
+        };
+        RunWriter{
+            path: String::from(path),
+            sink,
+            first_row,
+            rows: 0,
+        }
+    }
+
+    /// Write a battle
+    pub fn write(&mut self, data : &BattleData, results : &BattleResults){
+        self.rows += 1;
+        match &mut self.sink{
+            RunSink::Lines(OutputFormat::Csv, w) => writeln!(w, "{}", data.format_output()).unwrap(),
+            RunSink::Lines(_, w) => writeln!(w, "{}", data.format_json(results)).unwrap(),
+            RunSink::Database(db, run_id) => {
+                let id = db.insert_battle(run_id, self.rows, data, results);
+                if self.rows == 1{
+                    self.first_row = id;
+                }
+            },
+            RunSink::Summary(_) => {},
+        }
+    }
+
+    /// Finish writing the run, writing the summary if using the JSON summary format and updating the run's
+    /// manifest in a SQLite database. Return the first row written and the number of rows written
+    pub fn finish(self, summary : &BattleSummary, manifest : &RunManifest) -> (u64, u64){
+        match self.sink{
+            RunSink::Lines(_, mut w) => w.flush().unwrap(),
+            RunSink::Database(db, _) => {
+                db.update_run(manifest);
+                db.commit();
+            },
+            // summary document replaces any previous summary
+            RunSink::Summary(mut f) => {
+                f.set_len(0).unwrap();
+                writeln!(f, "{}", summary.to_json()).unwrap();
+            },
+        }
+        (self.first_row, self.rows)
+    }
+
+    /// Get output file path
+    pub fn get_path(&self) -> &str{
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::battle::Battle;
+    use crate::roster::Roster;
+    use crate::treasure::Treasure;
+    use crate::generator::GeneratorProfile;
+    use crate::testing::TestDir;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_statistics(){
+        let mut s = Statistics::default();
+        assert_eq!(0.0, s.get_mean());
+        [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0].iter().for_each(|v| s.add(*v));
+        assert_eq!(8, s.get_count());
+        assert_eq!(5.0, s.get_mean());
+        assert_eq!(2.0, s.get_std_dev());
+        assert_eq!(2.0, s.get_min());
+        assert_eq!(9.0, s.get_max());
+
+        let j = serde_json::to_value(s).unwrap();
+        assert_eq!(5.0, j["mean"]);
+        assert_eq!(2.0, j["std_dev"]);
+    }
+
+    #[test]
+    fn test_output_format(){
+        assert_eq!(OutputFormat::Csv, OutputFormat::from_name("csv"));
+        assert_eq!(OutputFormat::JsonLines, OutputFormat::from_name("jsonl"));
+        assert_eq!(OutputFormat::JsonSummary, OutputFormat::from_name("json"));
+        assert_eq!(OutputFormat::Sqlite, OutputFormat::from_name("sqlite"));
+        assert_eq!("jsonl", OutputFormat::JsonLines.get_extension());
+    }
+
+    #[test]
+    fn test_run_writer(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let manifest = RunManifest::new(vec![], 2, 3, r.get_file_path(), t.get_file_path(), None, &GeneratorProfile::default());
+        let mut summary = BattleSummary::new(2, "Random");
+        let dir = TestDir::new("writer");
+        let paths = ["data.csv", "data.jsonl", "data.json"].map(|f| dir.file(f));
+        let formats = [OutputFormat::Csv, OutputFormat::JsonLines, OutputFormat::JsonSummary];
+
+        let mut writers = paths.iter().zip(formats.iter()).map(|(p, f)| RunWriter::open(*f, p, WriteMode::Append, &r, &manifest)).collect::<Vec<RunWriter>>();
+        for _ in 0..3{
+            let mut b = Battle::generate_random_battle(&r, &t, &GeneratorProfile::default(), None);
+            let mut d = BattleData::new(&r);
+            let res = b.autoresolve(&t, &mut d);
+            summary.add(&d);
+            writers.iter_mut().for_each(|w| w.write(&d, &res));
+        }
+        let rows = writers.into_iter().map(|w| w.finish(&summary, &manifest)).collect::<Vec<(u64, u64)>>();
+        assert_eq!(vec![(1, 3), (1, 3), (1, 3)], rows);
+
+        assert_eq!(4, fs::read_to_string(&paths[0]).unwrap().lines().count());
+        assert_eq!(3, fs::read_to_string(&paths[1]).unwrap().lines().count());
+        let j : serde_json::Value = serde_json::from_str(&fs::read_to_string(&paths[2]).unwrap()).unwrap();
+        assert_eq!(3, j["battles"]);
+
+        // appending continues from the existing rows
+        let w = RunWriter::open(OutputFormat::Csv, &paths[0], WriteMode::Append, &r, &manifest);
+        assert_eq!((4, 0), w.finish(&summary, &manifest));
+    }
+
+    #[test]
+    fn test_write_modes(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let manifest = RunManifest::new(vec![], 2, 3, r.get_file_path(), t.get_file_path(), None, &GeneratorProfile::default());
+        let summary = BattleSummary::new(2, "Random");
+        let test_dir = TestDir::new("write_modes");
+        let dir = &test_dir.file("output");
+        let path = format!("{}/data.csv", dir);
+        let mut b = Battle::generate_random_battle(&r, &t, &GeneratorProfile::default(), None);
+        let mut d = BattleData::new(&r);
+        let res = b.autoresolve(&t, &mut d);
+        let write = |mode| {
+            let mut w = RunWriter::open(OutputFormat::Csv, &path, mode, &r, &manifest);
+            w.write(&d, &res);
+            w.finish(&summary, &manifest)
+        };
+
+        // directory is created with the file
+        assert_eq!((1, 1), write(WriteMode::Append));
+        assert_eq!((2, 1), write(WriteMode::Append));
+        fs::write(RunManifest::manifest_path(&path), "").unwrap();
+        assert_eq!((1, 1), write(WriteMode::Overwrite));
+        assert!(!Path::new(&RunManifest::manifest_path(&path)).exists());
+        assert_eq!(2, fs::read_to_string(&path).unwrap().lines().count());
+
+        assert_eq!(format!("{}/data.1.csv", dir), rotated_path(&path));
+        assert_eq!((1, 1), write(WriteMode::Rotate));
+        assert_eq!((1, 1), write(WriteMode::Rotate));
+        assert!(Path::new(&format!("{}/data.1.csv", dir)).exists());
+        assert_eq!(format!("{}/data.3.csv", dir), rotated_path(&path));
+    }
+
+    #[test]
+    fn test_file_lock(){
+        let dir = TestDir::new("lock");
+        let path = &dir.file("data.jsonl");
+        let (f, rows) = open_locked(path, None);
+        assert_eq!(0, rows);
+        assert!(matches!(File::open(path).unwrap().try_lock(), Err(TryLockError::WouldBlock)));
+        drop(f);
+        assert!(File::open(path).unwrap().try_lock().is_ok());
+    }
+
+    #[test]
+    fn test_lock_moved_file(){
+        let dir = TestDir::new("lock_moved");
+        let path = dir.file("data.jsonl");
+        let (f, _) = open_locked(&path, None);
+        let waiting = {
+            let path = path.clone();
+            thread::spawn(move || open_locked(&path, None))
+        };
+        thread::sleep(Duration::from_millis(200));
+
+        // the waiting run locks the file that replaced the one it waited for
+        fs::rename(&path, dir.file("data.1.jsonl")).unwrap();
+        let (new, _) = open_locked(&path, None);
+        drop(f);
+        thread::sleep(Duration::from_millis(200));
+        assert!(!waiting.is_finished());
+        drop(new);
+        let (mut w, _) = waiting.join().unwrap();
+        writeln!(w, "row").unwrap();
+        assert_eq!("row\n", fs::read_to_string(&path).unwrap());
+        assert_eq!("", fs::read_to_string(dir.file("data.1.jsonl")).unwrap());
+    }
+
+    #[test]
+    fn test_header_mismatch(){
+        let test_dir = TestDir::new("header_mismatch");
+        let dir = test_dir.path().display();
+        let path = test_dir.write("data.csv", "AFaction,DFaction\n0,1\n");
+        let header = BattleData::csv_header(&Roster::embedded());
+        fs::write(RunManifest::manifest_path(&path), "").unwrap();
+
+        // a file with an old header is moved with its manifest and a new file started
+        assert_eq!(0, open_locked(&path, Some(&header)).1);
+        assert_eq!(format!("{}\n", header), fs::read_to_string(&path).unwrap());
+        assert_eq!("AFaction,DFaction\n0,1\n", fs::read_to_string(format!("{}/data.1.csv", dir)).unwrap());
+        assert!(Path::new(&RunManifest::manifest_path(&format!("{}/data.1.csv", dir))).exists());
+        assert_eq!(0, open_locked(&path, Some(&header)).1);
+    }
+
+    #[test]
+    fn test_summary(){
+        let r = Roster::embedded();
+        let t = Treasure::embedded();
+        let mut summary = BattleSummary::new(1, "Random");
+        let mut wins = 0;
+        for _ in 0..20{
+            let mut b = Battle::generate_random_battle(&r, &t, &GeneratorProfile::default(), None);
+            let mut d = BattleData::new(&r);
+            b.autoresolve(&t, &mut d);
+            if d.get_outcome().is_victory(){
+                wins += 1;
+            }
+            summary.add(&d);
+        }
+        assert_eq!(20, summary.get_battles());
+        assert!(!summary.is_partial());
+        assert_eq!(wins as f64 / 20.0, summary.get_attacker_win_rate());
+        assert_eq!(20, summary.get_total().get_count());
+
+        let j : serde_json::Value = serde_json::from_str(&summary.to_json()).unwrap();
+        let outcome_total : u64 = j["outcomes"].as_object().unwrap().values().map(|v| v.as_u64().unwrap()).sum();
+        assert_eq!(20, outcome_total);
+        assert_eq!(wins, j["attacker_wins"]);
+        assert_eq!(false, j["partial"]);
+        let states = &j["defender"]["general_states"];
+        assert_eq!(20, states["Unharmed"].as_u64().unwrap() + states["Wounded"].as_u64().unwrap() + states["Slain"].as_u64().unwrap());
+    }
+}
@@ -0,0 +1,430 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Namespace of the units and equipment files that packs are loaded on top of
+pub const BASE_NAMESPACE : &str = "base";
+
+/// Optional manifest in a pack directory
+pub const MANIFEST_FILE : &str = "pack.toml";
+
+/// Directory of extra units and equipment, in units.csv and equipment.csv, loaded on top of the base files
+/// and earlier packs. Its entries are referenced by namespaced id, e.g. frost:3
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ContentPack{
+    /// Namespace of the pack's ids, the directory name if not set
+    #[serde(default)]
+    pub namespace : String,
+    #[serde(skip)]
+    pub dir : String,
+    /// Namespaced ids of units from earlier packs to remove
+    #[serde(default)]
+    pub disable_units : Vec<String>,
+    /// Namespaced ids of equipment from earlier packs to remove
+    #[serde(default)]
+    pub disable_equipment : Vec<String>,
+}
+
+impl ContentPack{
+    /// Read pack from a directory, with its manifest if it has one
+    pub fn read(dir : &str) -> ContentPack{
+        let path = Path::new(dir);
+        if !path.is_dir(){
+            panic!("Content pack {} is not a directory", dir);
+        }
+        let mut pack = match path.join(MANIFEST_FILE).is_file(){
+            true => toml::from_str(&Self::read_file(&path.join(MANIFEST_FILE)))
+                .unwrap_or_else(|e| panic!("Invalid {} in content pack {}: {}", MANIFEST_FILE, dir, e)),
+            false => ContentPack{ namespace: String::new(), dir: String::new(), disable_units: vec![], disable_equipment: vec![] },
+        };
+        pack.dir = String::from(dir);
+        if pack.namespace.is_empty(){
+            pack.namespace = path.canonicalize().ok().and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+                .unwrap_or_else(|| panic!("Unable to get namespace of content pack {}, set namespace in {}", dir, MANIFEST_FILE));
+        }
+        if pack.namespace.contains(':') || pack.namespace.contains(',') || pack.namespace.trim().is_empty(){
+            panic!("Invalid namespace \"{}\" of content pack {}, namespaces can't contain ':' or ','", pack.namespace, dir);
+        }
+        pack
+    }
+
+    /// Read a file of the pack
+    fn read_file(path : &Path) -> String{
+        fs::read_to_string(path).unwrap_or_else(|_| panic!("Unable to read {}", path.display()))
+    }
+
+    /// Get contents of the pack's units file, None if it has none
+    pub fn read_units(&self) -> Option<String>{
+        Some(Path::new(&self.dir).join("units.csv")).filter(|p| p.is_file()).map(|p| Self::read_file(&p))
+    }
+
+    /// Get contents of the pack's equipment file, None if it has none
+    pub fn read_equipment(&self) -> Option<String>{
+        Some(Path::new(&self.dir).join("equipment.csv")).filter(|p| p.is_file()).map(|p| Self::read_file(&p))
+    }
+
+    /// Get SHA-256 hash of the pack's manifest, units and equipment files
+    pub fn hash(&self) -> String{
+        let mut hasher = Sha256::new();
+        for f in [MANIFEST_FILE, "units.csv", "equipment.csv"].iter(){
+            if let Ok(content) = fs::read(Path::new(&self.dir).join(f)){
+                hasher.update(f.as_bytes());
+                hasher.update(content);
+            }
+        }
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+/// Id of a unit or item in battle JSON, either an id after packs are loaded, e.g. 3,
+/// a namespaced id, e.g. "base:3" or "frost:2", or a name, e.g. "Beladimir Spearmen"
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum EntryRef{
+    Id(i64),
+    Key(String),
+}
+
+impl EntryRef{
+    /// Convert a JSON object key to a reference, keys are always strings so numeric keys are ids
+    pub fn from_key(key : &str) -> Self{
+        key.parse().map(EntryRef::Id).unwrap_or_else(|_| EntryRef::Key(String::from(key)))
+    }
+}
+
+impl fmt::Display for EntryRef{
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result{
+        match self{
+            EntryRef::Id(id) => write!(f, "{}", id),
+            EntryRef::Key(k) => write!(f, "{}", k),
+        }
+    }
+}
+
+/// Entry of a units or equipment file that packs can add, override or disable
+pub trait PackEntry : Sized{
+    /// Kind of entry, used in messages
+    const KIND : &'static str;
+    /// Column of the entry's id in its CSV file
+    const ID_COLUMN : usize;
+
+    fn get_entry_id(&self) -> i64;
+    fn set_entry_id(&mut self, id : i64);
+    fn get_entry_name(&self) -> &str;
+    /// Parse a line of the CSV file
+    fn parse_entry(line : &str) -> Result<Self, String>;
+}
+
+/// Namespaced ids of the entries of a roster or treasure and the packs loaded into it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackIndex{
+    /// Id of each entry by namespaced id
+    keys : BTreeMap<String, i64>,
+    /// Namespaces loaded, starting with the base namespace
+    namespaces : Vec<String>,
+    /// Pack that last overrode each entry, by id
+    overrides : BTreeMap<i64, String>,
+    /// Id given to the next entry added by a pack
+    next_id : i64,
+}
+
+impl PackIndex{
+    /// Create index of the base file's entries, only the first entry with an id can be referenced
+    pub fn new<T : PackEntry>(entries : &[T]) -> Self{
+        let mut keys = BTreeMap::new();
+        for e in entries.iter(){
+            keys.entry(format!("{}:{}", BASE_NAMESPACE, e.get_entry_id())).or_insert_with(|| e.get_entry_id());
+        }
+        PackIndex{
+            keys,
+            namespaces: vec![String::from(BASE_NAMESPACE)],
+            overrides: BTreeMap::new(),
+            next_id: entries.iter().map(|e| e.get_entry_id()).max().unwrap_or(0) + 1,
+        }
+    }
+
+    /// Get id of the referenced entry, None if a namespaced id isn't loaded
+    pub fn resolve(&self, r : &EntryRef) -> Option<i64>{
+        match r{
+            EntryRef::Id(id) => Some(*id),
+            EntryRef::Key(k) => self.keys.get(k).copied(),
+        }
+    }
+
+    /// Get namespaced id of the entry with the id, None if it has none
+    pub fn get_key(&self, id : i64) -> Option<&str>{
+        self.keys.iter().find(|(_, v)| **v == id).map(|(k, _)| k.as_str())
+    }
+
+    /// Get namespaces loaded, starting with the base namespace
+    pub fn get_namespaces(&self) -> &[String]{
+        &self.namespaces
+    }
+
+    /// Get if any packs are loaded
+    pub fn has_packs(&self) -> bool{
+        self.namespaces.len() > 1
+    }
+
+    /// Merge a pack's entries and disabled entries into entries, return warnings about conflicts between packs
+    /// and added entries with the name of a loaded entry.
+    /// Entries with a namespaced id override that entry, other entries are added with the next id.
+    /// Panics if the namespace is already loaded or the pack refers to unknown or invalid entries
+    pub fn merge<T : PackEntry>(&mut self, entries : &mut Vec<T>, namespace : &str, content : Option<&str>, disabled : &[String]) -> Vec<String>{
+        if self.namespaces.iter().any(|n| n == namespace){
+            panic!("Content pack namespace {} is loaded more than once", namespace);
+        }
+        self.namespaces.push(String::from(namespace));
+        let mut warnings = vec![];
+
+        for key in disabled.iter(){
+            let id = self.keys.get(key).copied()
+                .unwrap_or_else(|| panic!("Content pack {} disables unknown {} {}", namespace, T::KIND, key));
+            entries.retain(|e| e.get_entry_id() != id);
+            self.keys.retain(|_, v| *v != id);
+            if let Some(p) = self.overrides.remove(&id){
+                warnings.push(format!("{} {} overridden by pack {} is disabled by pack {}", T::KIND, key, p, namespace));
+            }
+        }
+
+        // Read through lines after the column headers, the first entry is on line 2
+        let lines = content.map(|c| c.lines().skip(1).enumerate().collect::<Vec<(usize, &str)>>()).unwrap_or_default();
+        for (i, line) in lines.into_iter().filter(|(_, l)| !l.trim().is_empty()){
+            let invalid = |e : String| panic!("Invalid {} on line {} of content pack {}: {}", T::KIND, i + 2, namespace, e);
+            let mut columns : Vec<&str> = line.split(',').collect();
+            let id_text = columns.get(T::ID_COLUMN).map(|c| c.trim()).unwrap_or("");
+            match id_text.contains(':'){
+                // override an entry from the base file or an earlier pack
+                true => {
+                    let key = String::from(id_text);
+                    let id = self.keys.get(&key).copied()
+                        .unwrap_or_else(|| panic!("Content pack {} overrides unknown {} {} on line {}", namespace, T::KIND, key, i + 2));
+                    columns[T::ID_COLUMN] = "0";
+                    let mut e = T::parse_entry(&columns.join(",")).unwrap_or_else(invalid);
+                    e.set_entry_id(id);
+                    match entries.iter().position(|e| e.get_entry_id() == id){
+                        Some(p) => entries[p] = e,
+                        None => entries.push(e),
+                    }
+                    match self.overrides.insert(id, String::from(namespace)){
+                        Some(p) if p != namespace => warnings.push(format!("{} {} is overridden by packs {} and {}, using {}",
+                                                                           T::KIND, key, p, namespace, namespace)),
+                        _ => (),
+                    }
+                },
+                // add a new entry
+                false => {
+                    let mut e = T::parse_entry(line).unwrap_or_else(invalid);
+                    let key = format!("{}:{}", namespace, e.get_entry_id());
+                    if self.keys.contains_key(&key){
+                        panic!("Content pack {} has more than one {} with id {}", namespace, T::KIND, key);
+                    }
+                    // name references to either entry would be ambiguous
+                    let name = normalize_name(e.get_entry_name());
+                    if let Some(other) = entries.iter().find(|o| normalize_name(o.get_entry_name()) == name){
+                        let other_id = other.get_entry_id();
+                        warnings.push(format!("{} {} \"{}\" has the same name as {} {}, refer to them by id", T::KIND, key,
+                                              e.get_entry_name(), T::KIND, self.get_key(other_id).map(String::from).unwrap_or_else(|| other_id.to_string())));
+                    }
+                    e.set_entry_id(self.next_id);
+                    self.keys.insert(key, self.next_id);
+                    entries.push(e);
+                    self.next_id += 1;
+                },
+            }
+        }
+        warnings
+    }
+}
+
+/// Find the id of the entry with a name, ignoring case, spaces and punctuation. Errors list the ids of the
+/// entries if several have the name, or the closest names if none have it
+pub fn find_by_name<'a, T : PackEntry + 'a>(entries : impl IntoIterator<Item = &'a T>, name : &str) -> Result<i64, String>{
+    let entries : Vec<&T> = entries.into_iter().collect();
+    let target = normalize_name(name);
+    let mut ids : Vec<i64> = entries.iter().filter(|e| normalize_name(e.get_entry_name()) == target).map(|e| e.get_entry_id()).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    match ids.len(){
+        1 => Ok(ids[0]),
+        0 => {
+            let names : Vec<String> = closest_names(entries.iter().map(|e| e.get_entry_name()), name).iter()
+                .map(|n| format!("\"{}\"", n)).collect();
+            Err(match names.len(){
+                0 => format!("No {} named \"{}\"", T::KIND, name),
+                1 => format!("No {} named \"{}\", did you mean {}?", T::KIND, name, names[0]),
+                _ => format!("No {} named \"{}\", did you mean one of {}?", T::KIND, name, names.join(", ")),
+            })
+        },
+        _ => Err(format!("Ambiguous {} name \"{}\", use one of the ids {}", T::KIND, name,
+                         ids.iter().map(|i| i.to_string()).collect::<Vec<String>>().join(", "))),
+    }
+}
+
+/// Get up to 3 names closest to name, within a few edits or containing or contained by it, ignoring case,
+/// spaces and punctuation. Names equal to it ignoring those are the only ones returned.
+pub fn closest_names<'a>(names : impl IntoIterator<Item = &'a str>, name : &str) -> Vec<&'a str>{
+    let target = normalize_name(name);
+    let mut close : Vec<(usize, &str)> = names.into_iter()
+        .map(|n| (edit_distance(&normalize_name(n), &target), n))
+        .filter(|(d, n)| *d <= target.len() / 3 + 1 || normalize_name(n).contains(&target) || target.contains(&normalize_name(n)))
+        .collect();
+    close.sort_unstable();
+    close.dedup_by(|a, b| a.1 == b.1);
+    // names only differing in case or punctuation are the only suggestions
+    let exact = close.iter().filter(|(d, _)| *d == 0).count();
+    close.into_iter().take(if exact > 0 { exact } else { 3 }).map(|(_, n)| n).collect()
+}
+
+/// Convert name to lowercase letters and digits for comparing
+fn normalize_name(name : &str) -> String{
+    name.chars().filter(|c| c.is_alphanumeric()).flat_map(|c| c.to_lowercase()).collect()
+}
+
+/// Get the number of single character insertions, deletions and substitutions to change a into b
+fn edit_distance(a : &str, b : &str) -> usize{
+    let b : Vec<char> = b.chars().collect();
+    let mut row : Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate(){
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate(){
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Pack loaded for a run, recorded in run manifests
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PackRecord{
+    pub namespace : String,
+    pub dir : String,
+    pub sha256 : String,
+}
+
+impl PackRecord{
+    /// Create record of a pack, hashing its files
+    pub fn new(pack : &ContentPack) -> Self{
+        PackRecord{ namespace: pack.namespace.clone(), dir: pack.dir.clone(), sha256: pack.hash() }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Entry(String, i64);
+
+    impl PackEntry for Entry{
+        const KIND : &'static str = "entry";
+        const ID_COLUMN : usize = 1;
+
+        fn get_entry_id(&self) -> i64{
+            self.1
+        }
+
+        fn set_entry_id(&mut self, id : i64){
+            self.1 = id;
+        }
+
+        fn get_entry_name(&self) -> &str{
+            &self.0
+        }
+
+        fn parse_entry(line : &str) -> Result<Self, String>{
+            let (name, id) = line.split_once(',').ok_or_else(|| String::from("missing id"))?;
+            Ok(Entry(String::from(name), id.trim().parse().map_err(|_| format!("invalid id {}", id))?))
+        }
+    }
+
+    fn entries(names : &[(&str, i64)]) -> Vec<Entry>{
+        names.iter().map(|(n, i)| Entry(String::from(*n), *i)).collect()
+    }
+
+    #[test]
+    fn test_merge(){
+        let mut e = entries(&[("a", 1), ("b", 2), ("c", 5)]);
+        let mut index = PackIndex::new(&e);
+        assert_eq!(Some(2), index.resolve(&EntryRef::Key(String::from("base:2"))));
+        assert!(!index.has_packs());
+
+        // frost adds two entries and overrides b
+        let w = index.merge(&mut e, "frost", Some("Name,Id\nd,1\nB,base:2\ne,2\n"), &[]);
+        assert!(w.is_empty());
+        assert_eq!(entries(&[("a", 1), ("B", 2), ("c", 5), ("d", 6), ("e", 7)]), e);
+        assert_eq!(Some(7), index.resolve(&EntryRef::Key(String::from("frost:2"))));
+
+        // ice disables a and frost:1, and overrides b again
+        let w = index.merge(&mut e, "ice", Some("Name,Id\nBB,base:2\n"), &[String::from("base:1"), String::from("frost:1")]);
+        assert_eq!(vec!["entry base:2 is overridden by packs frost and ice, using ice"], w);
+        assert_eq!(entries(&[("BB", 2), ("c", 5), ("e", 7)]), e);
+        assert_eq!(None, index.resolve(&EntryRef::Key(String::from("base:1"))));
+        assert_eq!(Some(3), index.resolve(&EntryRef::Id(3)));
+        assert_eq!(vec!["base", "frost", "ice"], index.get_namespaces());
+
+        // snow adds an entry with the name of frost's e
+        let w = index.merge(&mut e, "snow", Some("Name,Id\nE,1\n"), &[]);
+        assert_eq!(vec!["entry snow:1 \"E\" has the same name as entry frost:2, refer to them by id"], w);
+        assert_eq!(entries(&[("BB", 2), ("c", 5), ("e", 7), ("E", 8)]), e);
+    }
+
+    #[test]
+    fn test_find_by_name(){
+        let e = entries(&[("Warding Armor", 1), ("Warding Armor", 2), ("Beladimir Spearmen", 12), ("Beladimir Cavalry", 17),
+                          ("Guards of Beladimir", 11), ("Spearmen", 30), ("Spearmen", 30)]);
+        assert_eq!(Ok(12), find_by_name(&e, "beladimir  spearmen"));
+        assert_eq!(Ok(30), find_by_name(&e, "Spearmen"));
+        assert_eq!(Err(String::from("Ambiguous entry name \"warding armor\", use one of the ids 1, 2")), find_by_name(&e, "warding armor"));
+        assert_eq!(Err(String::from("No entry named \"Beladimir Spearman\", did you mean \"Beladimir Spearmen\"?")),
+                   find_by_name(&e, "Beladimir Spearman"));
+        assert_eq!(Err(String::from("No entry named \"Beladimir\", did you mean one of \"Beladimir Cavalry\", \"Beladimir Spearmen\", \
+        \"Guards of Beladimir\"?")), find_by_name(&e, "Beladimir"));
+        assert_eq!(Err(String::from("No entry named \"Dragon\"")), find_by_name(&e, "Dragon"));
+        assert_eq!(3, edit_distance("kitten", "sitting"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Content pack frost overrides unknown entry base:9 on line 2")]
+    fn test_unknown_override(){
+        let mut e = entries(&[("a", 1)]);
+        PackIndex::new(&e).merge(&mut e, "frost", Some("Name,Id\nA,base:9\n"), &[]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Content pack namespace base is loaded more than once")]
+    fn test_duplicate_namespace(){
+        let mut e = entries(&[("a", 1)]);
+        PackIndex::new(&e).merge(&mut e, "base", None, &[]);
+    }
+
+    #[test]
+    fn test_read_pack(){
+        let test_dir = TestDir::new("pack");
+        // the namespace defaults to the pack's directory name
+        let dir = &test_dir.file("test_pack");
+        fs::create_dir(dir).unwrap();
+        let pack = ContentPack::read(dir);
+        assert_eq!(("test_pack", None), (pack.namespace.as_str(), pack.read_units()));
+
+        test_dir.write(&format!("test_pack/{}", MANIFEST_FILE), "namespace = \"frost\"\ndisable_units = [\"base:1\"]\n");
+        test_dir.write("test_pack/units.csv", "Faction,Name,Type,Autoresolve Bonus,Unit Size,id\n");
+        let pack = ContentPack::read(dir);
+        let hash = pack.hash();
+        fs::remove_dir_all(dir).unwrap();
+        assert_eq!("frost", pack.namespace);
+        assert_eq!(vec!["base:1"], pack.disable_units);
+        assert!(pack.read_units().is_none());
+        assert_eq!(64, hash.len());
+        assert_eq!(EntryRef::Key(String::from("frost:1")), serde_json::from_str("\"frost:1\"").unwrap());
+        assert_eq!(EntryRef::Id(3), serde_json::from_str("3").unwrap());
+    }
+}
@@ -1,10 +1,15 @@
 use crate::unit::{Unit, UnitType};
 use crate::general::{General, GeneralJSONObject};
 use crate::faction::Faction;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::{MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use std::fmt;
 use crate::roster::Roster;
 use rand::Rng;
 use crate::treasure::Treasure;
+use crate::generator::{GeneratorProfile, Randomizable};
+use crate::pack::EntryRef;
 
 #[derive(Debug, Clone)]
 pub struct Player{
@@ -140,52 +145,198 @@ impl Player{
     }
 
     /// Generate a Player with random values
-    pub fn generate_random_player(equipment_ratio: u32, rank_cap:u32, roster : &Roster, reinforcement_cap : u32, treasure : &Treasure) -> Self{
-        let mut rng = rand::thread_rng();
+    pub fn generate_random_player(profile : &GeneratorProfile, roster : &Roster, treasure : &Treasure) -> Self{
+        let mut rng = crate::rng::thread_rng();
 
-        let gen = General::generate_random_general(equipment_ratio, rank_cap, treasure);
-        let fac = Faction::generate_random_faction();
-        let rein = rng.gen_range(0..=reinforcement_cap);
-        let adv = rng.gen::<bool>();
-        let mut units: Vec<Unit> = vec![];
+        let gen = General::generate_random_general(profile, treasure);
+        let fac = profile.random_faction();
+        let rein = rng.gen_range(0..=profile.reinforcement_cap);
+        let adv = profile.random_adv_combat();
+        let units = Self::generate_random_units(profile, roster, fac);
 
-        let faction_roster = roster.get_faction_roster(fac);
+        Player::new_filled(units, gen, fac, rein as i32, adv)
+    }
 
-        for _ in 1..rng.gen_range(2..=20){
-            units.push(faction_roster[rng.gen_range(0..faction_roster.len())].clone());
-        }
+    /// Generate a random set of units from a Faction's roster using the profile's army size and unit type weights.
+    /// Without unit type weights each unit is chosen uniformly from the roster
+    pub fn generate_random_units(profile : &GeneratorProfile, roster : &Roster, faction : Faction) -> Vec<Unit>{
+        let mut rng = crate::rng::thread_rng();
+        let faction_roster = roster.get_faction_roster(faction);
+        let available = faction_roster.iter().map(|u| *u.get_type()).collect::<Vec<UnitType>>();
+        let mut units: Vec<Unit> = vec![];
 
-        Player::new_filled(units, gen, fac, rein as i32, adv)
+        for _ in 0..profile.random_army_size(){
+            if profile.unit_type_weights.is_none(){
+                if faction_roster.is_empty(){
+                    break;
+                }
+                units.push(faction_roster[rng.gen_range(0..faction_roster.len())].clone());
+                continue;
+            }
+            // choose unit type first so the profile's unit mix is respected
+            let unit_type = match profile.random_unit_type(&available){
+                Some(t) => t,
+                None => break,
+            };
+            let type_roster = faction_roster.iter()
+                .filter(|u| *u.get_type() == unit_type)
+                .collect::<Vec<&&Unit>>();
+            units.push((*type_roster[rng.gen_range(0..type_roster.len())]).clone());
+        }
+        units
     }
 }
 
 /// Holds Player struct in a format for serializing/deserializing
-#[derive(Debug,Deserialize,Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PlayerJSONObject{
-    general : GeneralJSONObject,
-    units : Vec<u32>,
-    reinforcements : i32,
-    adv_combat : bool,
-    faction : Faction,
+    general : Randomizable<GeneralJSONObject>,
+    units : Randomizable<Vec<UnitsEntry>>,
+    reinforcements : Randomizable<i32>,
+    adv_combat : Randomizable<bool>,
+    faction : Randomizable<Faction>,
+    /// Units removed from units, one per reference, e.g. an army file's units minus one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    remove_units : Vec<EntryRef>,
 }
 
 impl PlayerJSONObject{
-    /// Produce Player object from self
-    pub fn produce_player(self, roster : &Roster, treasure : &Treasure) -> Player{
+    /// Create fixed PlayerJSONObject from references to its units and general's equipment
+    pub fn new(general : GeneralJSONObject, units : Vec<UnitsEntry>, reinforcements : i32, adv_combat : bool, faction : Faction) -> Self{
+        PlayerJSONObject{
+            general: Randomizable::Fixed(general),
+            units: Randomizable::Fixed(units),
+            reinforcements: Randomizable::Fixed(reinforcements),
+            adv_combat: Randomizable::Fixed(adv_combat),
+            faction: Randomizable::Fixed(faction),
+            remove_units: vec![],
+        }
+    }
+
+    /// Produce Player object from self, randomized fields are generated using profile
+    pub fn produce_player(&self, roster : &Roster, treasure : &Treasure, profile : &GeneratorProfile) -> Player{
+        let mut rng = crate::rng::thread_rng();
+        // faction is resolved first as random units are drawn from its roster
+        let faction = self.faction.resolve(|| profile.random_faction());
         Player::new_filled(
-            self.units.iter().map(|i| roster.get_unit_by_id(*i).clone()).collect::<Vec<Unit>>(),
-            self.general.produce_general(treasure),
-            self.faction,
-            self.reinforcements,
-            self.adv_combat
+            self.produce_units(roster, profile, faction),
+            match &self.general{
+                Randomizable::Random(_) => General::generate_random_general(profile, treasure),
+                Randomizable::Fixed(g) => g.produce_general(treasure, profile),
+            },
+            faction,
+            self.reinforcements.resolve(|| rng.gen_range(0..=profile.reinforcement_cap) as i32),
+            self.adv_combat.resolve(|| profile.random_adv_combat())
         )
     }
+
+    /// Produce units from self minus the removed units, randomized units are generated using profile
+    fn produce_units(&self, roster : &Roster, profile : &GeneratorProfile, faction : Faction) -> Vec<Unit>{
+        let mut units = match &self.units{
+            Randomizable::Random(_) if self.remove_units.is_empty() => return Player::generate_random_units(profile, roster, faction),
+            Randomizable::Random(_) => panic!("Unable to remove units from randomized units"),
+            Randomizable::Fixed(entries) => entries.iter().flat_map(|e| e.get_refs()).map(|r| roster.get_unit_by_ref(&r).clone()).collect::<Vec<Unit>>(),
+        };
+        for r in self.remove_units.iter(){
+            let id = roster.get_unit_by_ref(r).get_id();
+            match units.iter().position(|u| u.get_id() == id){
+                Some(i) => { units.remove(i); },
+                None => panic!("Unable to remove unit {}, the army has none left", r),
+            }
+        }
+        units
+    }
+
+    /// Create fixed PlayerJSONObject from a Player
+    pub fn from_player(player : &Player) -> Self{
+        PlayerJSONObject{
+            general: Randomizable::Fixed(GeneralJSONObject::from_general(player.get_general())),
+            units: Randomizable::Fixed(player.get_units().iter().map(|u| UnitsEntry::Unit(EntryRef::Id(i64::from(u.get_id())))).collect()),
+            reinforcements: Randomizable::Fixed(player.get_reinforcements()),
+            adv_combat: Randomizable::Fixed(player.has_advanced_combat_deck()),
+            faction: Randomizable::Fixed(*player.get_faction()),
+            remove_units: vec![],
+        }
+    }
+
+    /// Get references to each unit, repeated for units with counts, None if randomized
+    pub fn get_unit_refs(&self) -> Option<Vec<EntryRef>>{
+        self.units.fixed().map(|entries| entries.iter().flat_map(|e| e.get_refs()).collect())
+    }
+
+    /// Get faction, None if randomized
+    pub fn get_faction(&self) -> Option<Faction>{
+        self.faction.fixed().copied()
+    }
+
+    /// Get if any field is randomized
+    pub fn has_random(&self) -> bool{
+        self.units.is_random() || self.reinforcements.is_random() || self.adv_combat.is_random() ||
+            self.faction.is_random() || self.general.fixed().map(|g| g.has_random()).unwrap_or(true)
+    }
+}
+
+/// Entry of a player's units in battle JSON, either one unit by id, namespaced id or name, e.g. 12, "base:12" or
+/// "Beladimir Spearmen", or counts of units, e.g. {"Beladimir Spearmen": 3}
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum UnitsEntry{
+    Unit(EntryRef),
+    Counts(UnitCounts),
+}
+
+impl UnitsEntry{
+    /// Get reference to each unit of the entry
+    pub fn get_refs(&self) -> Vec<EntryRef>{
+        match self{
+            UnitsEntry::Unit(r) => vec![r.clone()],
+            UnitsEntry::Counts(c) => c.0.iter().flat_map(|(r, n)| std::iter::repeat_n(r.clone(), *n as usize)).collect(),
+        }
+    }
 }
 
+/// Number of each referenced unit, in the order they are written
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnitCounts(pub Vec<(EntryRef, u32)>);
+
+impl Serialize for UnitCounts{
+    fn serialize<S : Serializer>(&self, serializer : S) -> Result<S::Ok, S::Error>{
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (r, n) in self.0.iter(){
+            map.serialize_entry(&r.to_string(), n)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for UnitCounts{
+    fn deserialize<D : Deserializer<'de>>(deserializer : D) -> Result<Self, D::Error>{
+        struct CountsVisitor;
+
+        impl<'de> Visitor<'de> for CountsVisitor{
+            type Value = UnitCounts;
+
+            fn expecting(&self, f : &mut fmt::Formatter) -> fmt::Result{
+                f.write_str("a map of unit ids or names to counts")
+            }
+
+            fn visit_map<A : MapAccess<'de>>(self, mut map : A) -> Result<UnitCounts, A::Error>{
+                let mut counts = vec![];
+                while let Some((key, n)) = map.next_entry::<String, u32>()?{
+                    counts.push((EntryRef::from_key(&key), n));
+                }
+                Ok(UnitCounts(counts))
+            }
+        }
+
+        deserializer.deserialize_map(CountsVisitor)
+    }
+}
 
 #[cfg(test)]
 mod tests{
     use super::*;
+    use crate::equipment::EquipmentType;
 
     #[test]
     fn test_soldier_count(){
@@ -255,4 +406,68 @@ mod tests{
         assert_eq!(0,p.get_unit_count_by_name("test3"));
 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_units_by_name(){
+        let roster = Roster::embedded();
+        let treasure = Treasure::embedded();
+        let json = r#"{"general" : {"armor" : "crystalline  ARMOR", "weapon" : 0, "banner" : "base:31", "follower" : "Sapper",
+            "trinket" : 26, "rank" : 2},
+            "units" : ["Guards of Beladimir", {"Beladimir Spearmen" : 2, "14" : 1}, 16, "base:17"],
+            "reinforcements" : 0, "adv_combat" : false, "faction" : "Beladimir"}"#;
+        let j : PlayerJSONObject = serde_json::from_str(json).unwrap();
+        assert_eq!(Some(vec![11, 12, 12, 14, 16, 17]),
+                   j.get_unit_refs().map(|refs| refs.iter().map(|r| roster.find_unit(r).unwrap()).collect::<Vec<u32>>()));
+
+        let p = j.produce_player(&roster, &treasure, &GeneratorProfile::default());
+        assert_eq!(2, p.get_unit_count_by_name("Beladimir Spearmen"));
+        let g = p.get_general();
+        assert_eq!(Some(6), g.get_equipment(EquipmentType::Armor).map(|e| e.get_id()));
+        assert_eq!(Some(39), g.get_equipment(EquipmentType::Follower).map(|e| e.get_id()));
+        assert_eq!(None, g.get_equipment(EquipmentType::Weapon).map(|e| e.get_id()));
+        assert_eq!(r#"{"Beladimir Spearmen":2,"14":1}"#, serde_json::to_string(&j.units.fixed().unwrap()[1]).unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "No unit named \"Beladimir Spearman\", did you mean \"Beladimir Spearmen\"?")]
+    fn test_unknown_unit_name(){
+        let j : PlayerJSONObject = serde_json::from_str(r#"{"general" : "Random", "units" : ["Beladimir Spearman"],
+            "reinforcements" : 0, "adv_combat" : false, "faction" : "Beladimir"}"#).unwrap();
+        j.produce_player(&Roster::embedded(), &Treasure::embedded(), &GeneratorProfile::default());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid Armor in battle JSON: Ambiguous item name \"Warding Armor\", use one of the ids 1, 2")]
+    fn test_ambiguous_item_name(){
+        let j : PlayerJSONObject = serde_json::from_str(r#"{"general" : {"armor" : "Warding Armor", "weapon" : 0, "banner" : 0,
+            "follower" : 0, "trinket" : 0, "rank" : 1}, "units" : [1], "reinforcements" : 0, "adv_combat" : false,
+            "faction" : "Rebel"}"#).unwrap();
+        j.produce_player(&Roster::embedded(), &Treasure::embedded(), &GeneratorProfile::default());
+    }
+
+    #[test]
+    fn test_remove_units(){
+        let j : PlayerJSONObject = serde_json::from_str(r#"{"general" : "Random", "units" : [{"Beladimir Spearmen" : 2}, 14],
+            "reinforcements" : 0, "adv_combat" : false, "faction" : "Beladimir", "remove_units" : [12, "base:14"]}"#).unwrap();
+        let p = j.produce_player(&Roster::embedded(), &Treasure::embedded(), &GeneratorProfile::default());
+        assert_eq!(vec![12], p.get_units().iter().map(|u| u.get_id()).collect::<Vec<u32>>());
+        assert!(!serde_json::to_string(&PlayerJSONObject::from_player(&p)).unwrap().contains("remove_units"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unable to remove unit 11, the army has none left")]
+    fn test_remove_missing_unit(){
+        let j : PlayerJSONObject = serde_json::from_str(r#"{"general" : "Random", "units" : [12],
+            "reinforcements" : 0, "adv_combat" : false, "faction" : "Beladimir", "remove_units" : [11]}"#).unwrap();
+        j.produce_player(&Roster::embedded(), &Treasure::embedded(), &GeneratorProfile::default());
+    }
+
+    #[test]
+    fn test_random_units_uniform(){
+        // without unit type weights each unit of the roster is equally likely, 6 of 10 Lerastir units are melee
+        let profile = GeneratorProfile{ army_size: (2000, 2000), ..GeneratorProfile::default() };
+        let units = Player::generate_random_units(&profile, &Roster::embedded(), Faction::Lerastir);
+        let melee = units.iter().filter(|u| *u.get_type() == UnitType::Melee).count() as f64 / units.len() as f64;
+        assert!((melee - 0.6).abs() < 0.05, "melee share {}", melee);
+    }
+}
@@ -0,0 +1,134 @@
+use crate::battle::BattleOutcome;
+use crate::output::OutcomeCounts;
+
+/// z score of a 95% confidence interval
+const Z_95 : f64 = 1.959964;
+
+/// Minimum number of battles before precision is checked, so a run cannot stop on a few lucky battles
+pub const MIN_RUNS : u64 = 512;
+
+/// Probabilities a run's precision is measured on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PrecisionTarget{
+    /// Probability of an attacker victory
+    WinProbability,
+    /// Probability of every BattleOutcome
+    Outcomes,
+}
+
+impl PrecisionTarget{
+    /// Names of targets used by CLI
+    pub const NAMES : [&'static str; 2] = ["win", "outcomes"];
+
+    /// Get PrecisionTarget from name
+    pub fn from_name(name : &str) -> Self{
+        match name{
+            "win" => PrecisionTarget::WinProbability,
+            "outcomes" => PrecisionTarget::Outcomes,
+            _ => panic!("Invalid precision target {}", name),
+        }
+    }
+}
+
+/// Run battles until the 95% confidence interval of the target probabilities is at most width wide,
+/// or max_count battles have been run
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Precision{
+    pub width : f64,
+    pub target : PrecisionTarget,
+    pub max_count : u32,
+}
+
+impl Precision{
+    /// Get the 95% Wilson score interval of a probability from successes out of total
+    pub fn wilson_interval(successes : u64, total : u64) -> (f64, f64){
+        if total == 0{
+            return (0.0, 1.0);
+        }
+        let n = total as f64;
+        let p = successes as f64 / n;
+        let z2 = Z_95 * Z_95;
+        let center = (p + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let half = Z_95 * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / (1.0 + z2 / n);
+        ((center - half).max(0.0), (center + half).min(1.0))
+    }
+
+    /// Get the probabilities measured by the target, with the number of battles each counts
+    fn target_counts(&self, counts : &OutcomeCounts) -> Vec<(String, u64)>{
+        match self.target{
+            PrecisionTarget::WinProbability => {
+                let wins = BattleOutcome::ALL.iter().filter(|o| o.is_victory()).map(|o| counts.get(o)).sum();
+                vec![(String::from("AttackerVictory"), wins)]
+            },
+            PrecisionTarget::Outcomes => BattleOutcome::ALL.iter().map(|o| (format!("{:?}", o), counts.get(o))).collect(),
+        }
+    }
+
+    /// Get width of the widest confidence interval of the target probabilities
+    pub fn get_widest(&self, counts : &OutcomeCounts) -> f64{
+        let total = counts.get_total();
+        self.target_counts(counts).iter()
+            .map(|(_, c)| Self::wilson_interval(*c, total))
+            .map(|(low, high)| high - low)
+            .fold(0.0, f64::max)
+    }
+
+    /// Get if enough battles have been run to reach the precision
+    pub fn is_reached(&self, counts : &OutcomeCounts) -> bool{
+        counts.get_total() >= MIN_RUNS && self.get_widest(counts) <= self.width
+    }
+
+    /// Convert to printable lines of the number of runs used and the interval of each target probability
+    pub fn precision_output(&self, counts : &OutcomeCounts) -> String{
+        let total = counts.get_total();
+        let mut out = match self.is_reached(counts){
+            true => format!("Reached confidence interval width {} after {} runs", self.width, total),
+            false => format!("Did not reach confidence interval width {} after {} runs (widest {:.4})",
+                             self.width, total, self.get_widest(counts)),
+        };
+        for (name, c) in self.target_counts(counts){
+            let (low, high) = Self::wilson_interval(c, total);
+            out += &format!("\n{}: {:.4} (95% CI {:.4} - {:.4})", name, c as f64 / total.max(1) as f64, low, high);
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    fn counts(wins : u64, losses : u64) -> OutcomeCounts{
+        let mut c = OutcomeCounts::default();
+        (0..wins).for_each(|_| c.add(&BattleOutcome::CloseVictory));
+        (0..losses).for_each(|_| c.add(&BattleOutcome::CloseDefeat));
+        c
+    }
+
+    #[test]
+    fn test_wilson_interval(){
+        assert_eq!((0.0, 1.0), Precision::wilson_interval(0, 0));
+        let (low, high) = Precision::wilson_interval(50, 100);
+        assert!((low - 0.4038).abs() < 1e-4);
+        assert!((high - 0.5962).abs() < 1e-4);
+        // no successes still has a positive width
+        let (low, high) = Precision::wilson_interval(0, 1000);
+        assert!(low < 1e-12);
+        assert!(high > 0.0);
+    }
+
+    #[test]
+    fn test_is_reached(){
+        let p = Precision{ width: 0.05, target: PrecisionTarget::WinProbability, max_count: 10000 };
+        // narrow enough but too few runs
+        assert!(!p.is_reached(&counts(100, 0)));
+        assert!(!p.is_reached(&counts(500, 500)));
+        assert!(p.is_reached(&counts(1000, 1000)));
+
+        // every outcome bucket must be narrow enough
+        let p = Precision{ target: PrecisionTarget::Outcomes, ..p };
+        assert!(p.is_reached(&counts(1000, 1000)));
+        assert!(!Precision{ width: 0.01, ..p }.is_reached(&counts(1000, 1000)));
+        assert_eq!("CloseVictory: 0.5000 (95% CI 0.4781 - 0.5219)", p.precision_output(&counts(1000, 1000)).lines().nth(3).unwrap());
+    }
+}
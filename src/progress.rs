@@ -0,0 +1,127 @@
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// Time between progress updates
+const INTERVAL : Duration = Duration::from_millis(500);
+
+/// Prints how many of a run's battles have been resolved, how fast and the estimated time remaining,
+/// at most once per INTERVAL
+#[derive(Debug, Clone)]
+pub struct Progress{
+    total : u64,
+    /// If the run can stop before total battles, e.g. once a precision is reached
+    capped : bool,
+    done : u64,
+    enabled : bool,
+    start : Instant,
+    last_print : Instant,
+}
+
+impl Progress{
+    /// Create new Progress for a run of total battles, nothing is printed if not enabled
+    pub fn new(total : u64, enabled : bool) -> Self{
+        Progress{
+            total,
+            capped: false,
+            done: 0,
+            enabled,
+            start: Instant::now(),
+            last_print: Instant::now(),
+        }
+    }
+
+    /// Create new Progress for a run of at most max battles that can stop early, nothing is printed if not enabled
+    pub fn capped(max : u64, enabled : bool) -> Self{
+        Progress{ capped: true, ..Self::new(max, enabled) }
+    }
+
+    /// Add resolved battles, printing progress if enough time has passed since the last update
+    pub fn add(&mut self, battles : u64){
+        self.done += battles;
+        if self.enabled && self.last_print.elapsed() >= INTERVAL{
+            self.last_print = Instant::now();
+            // pad to clear the end of a longer previous line
+            eprint!("\r{:<80}", self.progress_output(self.start.elapsed()));
+            std::io::stderr().flush().unwrap();
+        }
+    }
+
+    /// Print final progress
+    pub fn finish(&self){
+        if self.enabled{
+            eprintln!("\r{:<80}", self.progress_output(self.start.elapsed()));
+        }
+    }
+
+    /// Get number of battles resolved
+    pub fn get_done(&self) -> u64{
+        self.done
+    }
+
+    /// Get number of battles resolved per second after elapsed time
+    pub fn get_rate(&self, elapsed : Duration) -> f64{
+        match elapsed.as_secs_f64(){
+            s if s > 0.0 => self.done as f64 / s,
+            _ => 0.0,
+        }
+    }
+
+    /// Get estimated time until all battles are resolved at the current rate, the longest the run can take if capped.
+    /// None before any are resolved
+    pub fn get_eta(&self, elapsed : Duration) -> Option<Duration>{
+        match self.get_rate(elapsed){
+            r if r > 0.0 => Some(Duration::from_secs_f64(self.total.saturating_sub(self.done) as f64 / r)),
+            _ => None,
+        }
+    }
+
+    /// Convert to printable progress line after elapsed time
+    pub fn progress_output(&self, elapsed : Duration) -> String{
+        let eta = match self.get_eta(elapsed){
+            Some(d) => format_duration(d),
+            None => String::from("--:--"),
+        };
+        match self.capped{
+            true => format!("Resolved {} battles (at most {}) {:.0} battles/s ETA at most {}", self.done, self.total,
+                            self.get_rate(elapsed), eta),
+            false => format!("Resolved {}/{} battles ({:.1}%) {:.0} battles/s ETA {}", self.done, self.total,
+                             100.0 * self.done as f64 / self.total.max(1) as f64, self.get_rate(elapsed), eta),
+        }
+    }
+}
+
+/// Format duration as minutes and seconds, with hours if needed
+fn format_duration(d : Duration) -> String{
+    let s = d.as_secs();
+    match s / 3600{
+        0 => format!("{:02}:{:02}", s / 60, s % 60),
+        h => format!("{}:{:02}:{:02}", h, s / 60 % 60, s % 60),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_progress(){
+        let mut p = Progress::new(200, false);
+        assert_eq!(None, p.get_eta(Duration::from_secs(1)));
+        p.add(1);
+        p.add(49);
+        assert_eq!(50, p.get_done());
+        assert_eq!(25.0, p.get_rate(Duration::from_secs(2)));
+        assert_eq!(Some(Duration::from_secs(6)), p.get_eta(Duration::from_secs(2)));
+        assert_eq!("Resolved 50/200 battles (25.0%) 25 battles/s ETA 00:06", p.progress_output(Duration::from_secs(2)));
+
+        let mut p = Progress::capped(200, false);
+        p.add(50);
+        assert_eq!("Resolved 50 battles (at most 200) 25 battles/s ETA at most 00:06", p.progress_output(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn test_format_duration(){
+        assert_eq!("01:05", format_duration(Duration::from_secs(65)));
+        assert_eq!("2:00:07", format_duration(Duration::from_secs(7207)));
+    }
+}
@@ -0,0 +1,182 @@
+use crate::battle::BattleOutcome;
+use crate::output::OutcomeCounts;
+use rand::Rng;
+
+/// Number of d10 rolled for each side's battle randoms
+const DICE : usize = 10;
+
+/// Number of sides on each die
+const SIDES : usize = 10;
+
+/// Table for sampling from a discrete distribution in constant time using Vose's alias method
+#[derive(Debug, Clone, PartialEq)]
+pub struct AliasTable{
+    /// Value of the first entry
+    offset : i32,
+    probability : Vec<f64>,
+    alias : Vec<usize>,
+}
+
+impl AliasTable{
+    /// Create new AliasTable for consecutive values starting at offset with relative weights
+    pub fn new(offset : i32, weights : &[f64]) -> Self{
+        let n = weights.len();
+        let sum : f64 = weights.iter().sum();
+        if n == 0 || sum <= 0.0 {
+            panic!("AliasTable needs at least one positive weight");
+        }
+
+        // scale weights so the mean is 1, then pair each small entry with a large entry
+        let mut scaled = weights.iter().map(|w| w * n as f64 / sum).collect::<Vec<f64>>();
+        let mut probability = vec![1.0; n];
+        let mut alias = (0..n).collect::<Vec<usize>>();
+        let (mut small, mut large) : (Vec<usize>, Vec<usize>) = (0..n).partition(|i| scaled[*i] < 1.0);
+
+        while let (Some(s), Some(l)) = (small.pop(), large.pop()){
+            probability[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] -= 1.0 - scaled[s];
+            match scaled[l] < 1.0{
+                true => small.push(l),
+                false => large.push(l),
+            }
+        }
+        // remaining entries are 1 apart from rounding error
+        small.into_iter().chain(large).for_each(|i| probability[i] = 1.0);
+
+        AliasTable{ offset, probability, alias }
+    }
+
+    /// Sample a value
+    pub fn sample<R : Rng>(&self, rng : &mut R) -> i32{
+        let i = rng.gen_range(0..self.probability.len());
+        let i = match rng.gen::<f64>() < self.probability[i]{
+            true => i,
+            false => self.alias[i],
+        };
+        i as i32 + self.offset
+    }
+
+    /// Get probability of each value, starting at the offset
+    pub fn get_distribution(&self) -> Vec<f64>{
+        let n = self.probability.len() as f64;
+        let mut d = self.probability.iter().map(|p| p / n).collect::<Vec<f64>>();
+        self.alias.iter().zip(self.probability.iter()).for_each(|(a, p)| d[*a] += (1.0 - p) / n);
+        d
+    }
+}
+
+/// Get number of ways to roll each sum of DICE dice with SIDES sides, starting at a sum of DICE
+fn dice_sum_counts() -> Vec<u64>{
+    let mut counts = vec![1u64];
+    for _ in 0..DICE{
+        let mut next = vec![0u64; counts.len() + SIDES - 1];
+        for (i, c) in counts.iter().enumerate(){
+            next[i..i + SIDES].iter_mut().for_each(|n| *n += c);
+        }
+        counts = next;
+    }
+    counts
+}
+
+/// Get relative weight of each difference between the attacker's and defender's battle randoms,
+/// starting at the smallest difference
+pub fn randoms_difference_weights() -> Vec<f64>{
+    let sums = dice_sum_counts();
+    let mut diffs = vec![0u128; sums.len() * 2 - 1];
+    let mid = sums.len() - 1;
+    for (a, ca) in sums.iter().enumerate(){
+        for (d, cd) in sums.iter().enumerate(){
+            diffs[mid + a - d] += *ca as u128 * *cd as u128;
+        }
+    }
+    diffs.into_iter().map(|c| c as f64).collect()
+}
+
+/// Resolves only the outcome of battles by adding a sampled battle randoms difference to a
+/// precomputed deterministic total, without casualties, treasure or BattleData
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchResolver{
+    table : AliasTable,
+}
+
+impl Default for BatchResolver{
+    fn default() -> Self {
+        let max = ((SIDES - 1) * DICE) as i32;
+        BatchResolver{ table: AliasTable::new(-max, &randoms_difference_weights()) }
+    }
+}
+
+impl BatchResolver{
+    /// Sample the outcome of a battle with the deterministic total
+    pub fn sample_outcome<R : Rng>(&self, deterministic_total : f32, rng : &mut R) -> BattleOutcome{
+        BattleOutcome::determine_outcome(deterministic_total + self.table.sample(rng) as f32)
+    }
+
+    /// Sample the outcomes of count battles with the same deterministic total
+    pub fn resolve<R : Rng>(&self, deterministic_total : f32, count : u32, rng : &mut R, counts : &mut OutcomeCounts){
+        for _ in 0..count{
+            counts.add(&self.sample_outcome(deterministic_total, rng));
+        }
+    }
+
+    /// Get the table battle randoms differences are sampled from
+    pub fn get_table(&self) -> &AliasTable{
+        &self.table
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    #[test]
+    fn test_dice_sums(){
+        let sums = dice_sum_counts();
+        assert_eq!(91, sums.len());
+        assert_eq!(10u64.pow(10), sums.iter().sum::<u64>());
+        assert_eq!(1, sums[0]);
+        assert_eq!(10, sums[1]);
+    }
+
+    #[test]
+    fn test_difference_weights(){
+        let w = randoms_difference_weights();
+        assert_eq!(181, w.len());
+        // symmetric and most likely to be a tie
+        assert_eq!(w[0], w[180]);
+        assert_eq!(w[10], w[170]);
+        assert!(w.iter().all(|x| *x <= w[90]));
+        assert!((w.iter().sum::<f64>() / 1.0e20 - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_alias_table(){
+        let t = AliasTable::new(5, &[1.0, 2.0, 0.0, 5.0]);
+        let d = t.get_distribution();
+        [0.125, 0.25, 0.0, 0.625].iter().zip(d.iter()).for_each(|(e, a)| assert!((e - a).abs() < 1e-12));
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let samples = (0..8000).map(|_| t.sample(&mut rng)).collect::<Vec<i32>>();
+        assert!(samples.iter().all(|s| [5, 6, 8].contains(s)));
+        let eights = samples.iter().filter(|s| **s == 8).count();
+        assert!((4700..5300).contains(&eights));
+    }
+
+    #[test]
+    fn test_resolver_matches_distribution(){
+        let r = BatchResolver::default();
+        let d = r.get_table().get_distribution();
+        let w = randoms_difference_weights();
+        let sum : f64 = w.iter().sum();
+        d.iter().zip(w.iter()).for_each(|(a, e)| assert!((a - e / sum).abs() < 1e-12));
+
+        // a large deterministic total always wins
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut counts = OutcomeCounts::default();
+        r.resolve(200.0, 100, &mut rng, &mut counts);
+        assert_eq!(100, counts.get(&BattleOutcome::DecisiveVictory));
+    }
+}
@@ -0,0 +1,224 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Prefix of paths to the copies of resource files embedded in the binary, e.g. embedded:units.csv
+pub const EMBEDDED_PREFIX : &str = "embedded:";
+
+/// Environment variable overriding the user config directory
+pub const CONFIG_DIR_VAR : &str = "AUTORESOLVE_CONFIG_DIR";
+
+/// Suffix of embedded battle templates that can be run as scenarios
+const SCENARIO_SUFFIX : &str = "_battle_template.json";
+
+/// Copies of the files in ResourceFiles embedded in the binary, by file name
+const EMBEDDED : [(&str, &str); 12] = [
+    ("units.csv", include_str!("../ResourceFiles/units.csv")),
+    ("equipment.csv", include_str!("../ResourceFiles/equipment.csv")),
+    ("generator_profile.json", include_str!("../ResourceFiles/generator_profile_template.json")),
+    ("data_capture_template.txt", include_str!("../ResourceFiles/data_capture_template.txt")),
+    ("normal_battle_template.json", include_str!("../ResourceFiles/normal_battle_template.json")),
+    ("siege_battle_template.json", include_str!("../ResourceFiles/siege_battle_template.json")),
+    ("raid_battle_template.json", include_str!("../ResourceFiles/raid_battle_template.json")),
+    ("naval_battle_template.json", include_str!("../ResourceFiles/naval_battle_template.json")),
+    ("monster_battle_template.json", include_str!("../ResourceFiles/monster_battle_template.json")),
+    ("partial_random_battle_template.json", include_str!("../ResourceFiles/partial_random_battle_template.json")),
+    ("armies/beladimir_vanguard.json", include_str!("../ResourceFiles/armies/beladimir_vanguard.json")),
+    ("armies/menoriad_garrison.json", include_str!("../ResourceFiles/armies/menoriad_garrison.json")),
+];
+
+/// Resource files read for every run, which can be overridden
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Resource{
+    Units,
+    Equipment,
+    GeneratorProfile,
+}
+
+impl Resource{
+    /// All resources
+    pub const ALL : [Resource; 3] = [Resource::Units, Resource::Equipment, Resource::GeneratorProfile];
+
+    /// Get file name of the resource in the user config directory and the embedded copies
+    pub fn get_file_name(&self) -> &'static str{
+        match self{
+            Resource::Units => "units.csv",
+            Resource::Equipment => "equipment.csv",
+            Resource::GeneratorProfile => "generator_profile.json",
+        }
+    }
+
+    /// Get environment variable overriding the resource
+    pub fn get_env_var(&self) -> &'static str{
+        match self{
+            Resource::Units => "AUTORESOLVE_UNITS",
+            Resource::Equipment => "AUTORESOLVE_EQUIPMENT",
+            Resource::GeneratorProfile => "AUTORESOLVE_PROFILE",
+        }
+    }
+
+    /// Get CLI flag overriding the resource
+    pub fn get_flag(&self) -> &'static str{
+        match self{
+            Resource::Units => "--unit",
+            Resource::Equipment => "--treasure",
+            Resource::GeneratorProfile => "--profile",
+        }
+    }
+
+    /// Find the file to use, from the CLI flag, then the environment variable, then the config file option,
+    /// then the user config directory, then the embedded copy
+    pub fn locate(&self, flag : Option<&str>, setting : Option<&str>) -> ResourceLocation{
+        self.locate_in(flag, env::var(self.get_env_var()).ok().filter(|v| !v.is_empty()), setting, config_dir())
+    }
+
+    /// Find the file to use from the given CLI flag, environment variable value, config file option
+    /// and user config directory
+    fn locate_in(&self, flag : Option<&str>, env_value : Option<String>, setting : Option<&str>, config_dir : Option<PathBuf>) -> ResourceLocation{
+        let (origin, path) = match (flag, env_value, setting){
+            (Some(f), _, _) => (ResourceOrigin::Flag, String::from(f)),
+            (None, Some(v), _) => (ResourceOrigin::Environment, v),
+            (None, None, Some(s)) => (ResourceOrigin::ConfigFile, String::from(s)),
+            (None, None, None) => match config_dir.map(|d| d.join(self.get_file_name())).filter(|p| p.is_file()){
+                Some(p) => (ResourceOrigin::ConfigDir, p.to_string_lossy().to_string()),
+                None => (ResourceOrigin::Embedded, format!("{}{}", EMBEDDED_PREFIX, self.get_file_name())),
+            },
+        };
+        ResourceLocation{ resource: *self, origin, path }
+    }
+}
+
+/// Where the file used for a resource was found
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ResourceOrigin{
+    Flag,
+    Environment,
+    ConfigFile,
+    ConfigDir,
+    Embedded,
+}
+
+/// File used for a resource and where it was found
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ResourceLocation{
+    pub resource : Resource,
+    pub origin : ResourceOrigin,
+    pub path : String,
+}
+
+impl ResourceLocation{
+    /// Convert to printable line of the resource, its file and where it was found
+    pub fn location_output(&self) -> String{
+        let origin = match self.origin{
+            ResourceOrigin::Flag => format!("from {}", self.resource.get_flag()),
+            ResourceOrigin::Environment => format!("from {}", self.resource.get_env_var()),
+            ResourceOrigin::ConfigFile => String::from("from config file"),
+            ResourceOrigin::ConfigDir => String::from("from user config directory"),
+            ResourceOrigin::Embedded => String::from("embedded default"),
+        };
+        format!("{}: {} ({})", self.resource.get_file_name(), self.path, origin)
+    }
+}
+
+/// Get user config directory resources are looked for in, from AUTORESOLVE_CONFIG_DIR,
+/// then autoresolve in XDG_CONFIG_HOME, ~/.config or APPDATA
+pub fn config_dir() -> Option<PathBuf>{
+    let var = |v : &str| env::var_os(v).filter(|s| !s.is_empty()).map(PathBuf::from);
+    var(CONFIG_DIR_VAR).or_else(|| {
+        var("XDG_CONFIG_HOME")
+            .or_else(|| var("HOME").map(|h| h.join(".config")))
+            .or_else(|| var("APPDATA"))
+            .map(|d| d.join("autoresolve"))
+    })
+}
+
+/// Get embedded copy of a resource file by file name
+pub fn embedded(name : &str) -> Option<&'static str>{
+    EMBEDDED.iter().find(|(n, _)| *n == name).map(|(_, c)| *c)
+}
+
+/// Get names of embedded resource files
+pub fn embedded_names() -> Vec<&'static str>{
+    EMBEDDED.iter().map(|(n, _)| *n).collect()
+}
+
+/// Get names of scenarios, the embedded battle templates, e.g. normal
+pub fn scenario_names() -> Vec<&'static str>{
+    EMBEDDED.iter().filter_map(|(n, _)| n.strip_suffix(SCENARIO_SUFFIX)).collect()
+}
+
+/// Get path of the embedded battle template for a scenario
+pub fn scenario_path(name : &str) -> String{
+    if !scenario_names().contains(&name){
+        panic!("Unknown scenario {}, expected one of {}", name, scenario_names().join(", "));
+    }
+    format!("{}{}{}", EMBEDDED_PREFIX, name, SCENARIO_SUFFIX)
+}
+
+/// Read a resource file, paths starting with EMBEDDED_PREFIX read the embedded copy
+pub fn read_to_string(path : &str) -> String{
+    match path.strip_prefix(EMBEDDED_PREFIX){
+        Some(name) => String::from(embedded(name).unwrap_or_else(|| panic!("No embedded resource {}, expected one of {}",
+                                                                            name, embedded_names().join(", ")))),
+        None => fs::read_to_string(Path::new(path)).unwrap_or_else(|_| panic!("Unable to read {}", path)),
+    }
+}
+
+/// Print every resource's file, where it was found, and where overrides are looked for
+pub fn print_resources(locations : &[ResourceLocation]){
+    println!("Resource files used:");
+    locations.iter().for_each(|l| println!("{}", l.location_output()));
+    match config_dir(){
+        Some(d) => println!("User config directory: {}", d.display()),
+        None => println!("User config directory: none, set {}", CONFIG_DIR_VAR),
+    }
+    println!("Embedded resources: {}", embedded_names().iter().map(|n| format!("{}{}", EMBEDDED_PREFIX, n)).collect::<Vec<String>>().join(", "));
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::testing::TestDir;
+
+    #[test]
+    fn test_embedded(){
+        for (name, content) in EMBEDDED.iter(){
+            let file = match *name{
+                "generator_profile.json" => "generator_profile_template.json",
+                n => n,
+            };
+            assert_eq!(fs::read_to_string(format!("./ResourceFiles/{}", file)).unwrap(), *content);
+        }
+        assert_eq!(embedded("units.csv").unwrap(), read_to_string("embedded:units.csv"));
+        assert!(embedded("missing.csv").is_none());
+        assert_eq!(vec!["normal", "siege", "raid", "naval", "monster", "partial_random"], scenario_names());
+        assert_eq!("embedded:siege_battle_template.json", scenario_path("siege"));
+    }
+
+    #[test]
+    fn test_lookup_order(){
+        let test_dir = TestDir::new("resources");
+        let dir = test_dir.path().to_path_buf();
+        let r = Resource::Units;
+
+        // embedded if the config directory has no file
+        let l = r.locate_in(None, None, None, Some(dir.clone()));
+        assert_eq!((ResourceOrigin::Embedded, "embedded:units.csv"), (l.origin, l.path.as_str()));
+        assert_eq!(ResourceOrigin::Embedded, r.locate_in(None, None, None, None).origin);
+
+        test_dir.write("units.csv", "");
+        assert_eq!(ResourceOrigin::ConfigDir, r.locate_in(None, None, None, Some(dir.clone())).origin);
+
+        let l = r.locate_in(None, None, Some("setting.csv"), Some(dir.clone()));
+        assert_eq!((ResourceOrigin::ConfigFile, "setting.csv"), (l.origin, l.path.as_str()));
+        assert_eq!("units.csv: setting.csv (from config file)", l.location_output());
+
+        let l = r.locate_in(None, Some(String::from("env.csv")), Some("setting.csv"), Some(dir.clone()));
+        assert_eq!((ResourceOrigin::Environment, "env.csv"), (l.origin, l.path.as_str()));
+        assert_eq!("units.csv: env.csv (from AUTORESOLVE_UNITS)", l.location_output());
+
+        let l = r.locate_in(Some("flag.csv"), Some(String::from("env.csv")), Some("setting.csv"), Some(dir.clone()));
+        assert_eq!((ResourceOrigin::Flag, "flag.csv"), (l.origin, l.path.as_str()));
+        assert_eq!("units.csv: flag.csv (from --unit)", l.location_output());
+    }
+}
@@ -0,0 +1,78 @@
+use rand::{RngCore, SeedableRng, Error};
+use rand::rngs::StdRng;
+use std::cell::RefCell;
+
+thread_local! {
+    static THREAD_RNG : RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+/// Handle to the current thread's random number generator, which can be seeded for reproducible runs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SeededThreadRng;
+
+impl RngCore for SeededThreadRng{
+    fn next_u32(&mut self) -> u32 {
+        THREAD_RNG.with(|r| r.borrow_mut().next_u32())
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        THREAD_RNG.with(|r| r.borrow_mut().next_u64())
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        THREAD_RNG.with(|r| r.borrow_mut().fill_bytes(dest))
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        THREAD_RNG.with(|r| r.borrow_mut().try_fill_bytes(dest))
+    }
+}
+
+/// Get the current thread's random number generator
+pub fn thread_rng() -> SeededThreadRng{
+    SeededThreadRng
+}
+
+/// Seed the current thread's random number generator
+pub fn seed(seed : u64){
+    THREAD_RNG.with(|r| *r.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
+/// Derive an independent seed for a numbered stream of a run, e.g. a chunk of battles
+pub fn derive_seed(seed : u64, stream : u64) -> u64{
+    // SplitMix64 finalizer so nearby seeds and streams give unrelated results
+    let mix = |mut z : u64| {
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    };
+    mix(seed ^ mix(stream.wrapping_add(0x9e3779b97f4a7c15)))
+}
+
+/// Generate a new seed from entropy
+pub fn random_seed() -> u64{
+    rand::random()
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn test_seeded_sequence(){
+        seed(42);
+        let a = (0..10).map(|_| thread_rng().gen_range(0..1000)).collect::<Vec<i32>>();
+        seed(42);
+        let b = (0..10).map(|_| thread_rng().gen_range(0..1000)).collect::<Vec<i32>>();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_derive_seed(){
+        assert_eq!(derive_seed(42, 3), derive_seed(42, 3));
+        assert_ne!(derive_seed(42, 3), derive_seed(42, 4));
+        assert_ne!(derive_seed(42, 3), derive_seed(43, 3));
+        assert_ne!(derive_seed(42, 1), derive_seed(43, 0));
+    }
+}
@@ -1,21 +1,49 @@
-use crate::unit::Unit;
+use crate::unit::{Unit, UnitType};
 use crate::faction::Faction;
+use crate::battle::BattleJSONObject;
+use crate::database::QueryTable;
+use crate::resources::{self, Resource, EMBEDDED_PREFIX};
+use crate::pack::{self, ContentPack, EntryRef, PackEntry, PackIndex};
+use std::convert::TryFrom;
 use std::fs;
 
+/// Header of units CSV files, used if a file has none
+const DEFAULT_HEADER : &str = "Faction,Name,Type,Autoresolve Bonus,Unit Size,id";
+
 #[derive(Debug, Clone)]
 pub struct Roster{
     file_path : String,
+    header : String,
     units : Vec<Unit>,
+    /// Lines of the file that couldn't be read as units
+    problems : Vec<String>,
+    /// Namespaced ids of the units and content packs loaded
+    index : PackIndex,
 }
 impl Roster{
-    /// Create new Roster, defaults to ./ResourceFiles/units.csv if None provided
+    /// Create new Roster, looks for units.csv in the environment, user config directory, then embedded copy if None provided.
+    /// Panics if any line isn't a valid unit
     pub fn new(file_path : Option<&str>) -> Self {
+        let r = Self::load(file_path);
+        if let Some(p) = r.problems.first(){
+            panic!("Invalid unit in {}: {}", r.file_path, p);
+        }
+        r
+    }
+
+    /// Create new Roster from the embedded units.csv, ignoring the environment and user config directory
+    pub fn embedded() -> Self{
+        Self::new(Some(&format!("{}{}", EMBEDDED_PREFIX, Resource::Units.get_file_name())))
+    }
+
+    /// Create new Roster like new, skipping and recording lines that aren't valid units
+    pub fn load(file_path : Option<&str>) -> Self {
         let mut r = Roster{
-            file_path: match file_path{
-                None => String::from("./ResourceFiles/units.csv"),
-                Some(s) => String::from(s),
-            },
+            file_path: Resource::Units.locate(file_path, None).path,
+            header: String::from(DEFAULT_HEADER),
             units : vec![],
+            problems : vec![],
+            index : PackIndex::default(),
         };
         r.init();
         r
@@ -23,27 +51,50 @@ impl Roster{
 
     /// Read in data from file_path and parse into unit objects
     fn init(&mut self) {
-        let file = fs::read_to_string(&self.file_path)
-            .expect(&format!{"Unable to read {}",self.file_path});
+        let file = resources::read_to_string(&self.file_path);
+        let mut lines = file.lines();
+        if let Some(h) = lines.next(){
+            self.header = String::from(h);
+        }
 
-        // Read through lines, skip first as it is the column headers
-        for line in file.lines().skip(1){
-            self.units.push(self.read_unit(line));
+        // Read through lines after the column headers, the first unit is on line 2
+        for (i, line) in lines.enumerate().filter(|(_, l)| !l.trim().is_empty()){
+            match Self::parse_unit(line){
+                Ok(u) => self.units.push(u),
+                Err(e) => self.problems.push(format!("line {}: {}", i + 2, e)),
+            }
         }
+        self.index = PackIndex::new(&self.units);
+    }
+
+    /// Merge the units of content packs in order, return warnings about conflicts between packs.
+    /// Panics if a pack has invalid units or refers to units that aren't loaded
+    pub fn add_packs(&mut self, packs : &[ContentPack]) -> Vec<String>{
+        packs.iter()
+            .flat_map(|p| self.index.merge(&mut self.units, &p.namespace, p.read_units().as_deref(), &p.disable_units))
+            .collect()
     }
 
     /// Parse string into Unit object
-    pub fn read_unit(&self, line : &str) -> Unit{
-        let values : Vec<&str> = line.split(",").collect();
-        // println!("{:?}", values);
-        Unit::new(
-            values[0].trim(),
-            values[1].trim().parse().unwrap(),
-            values[2].trim(),
-            values[3].trim().parse().unwrap(),
-            values[4].trim().parse().unwrap(),
-            values[5].trim().parse().unwrap()
-        )
+    pub fn parse_unit(line : &str) -> Result<Unit, String>{
+        let values : Vec<&str> = line.split(',').map(|v| v.trim()).collect();
+        if values.len() != 6{
+            return Err(format!("expected 6 columns, found {}", values.len()));
+        }
+        if Faction::from_name(values[0]).is_none(){
+            return Err(format!("invalid faction {}, expected one of {}", values[0], Faction::NAMES.join(", ")));
+        }
+        if UnitType::from_name(values[2]).is_none(){
+            return Err(format!("invalid unit type {}, expected one of {}", values[2], UnitType::NAMES.join(", ")));
+        }
+        let number = |i : usize, column : &str| values[i].parse().map_err(|_| format!("invalid {} {}", column, values[i]));
+        Ok(Unit::new(values[0], String::from(values[1]), values[2], number(3, "autoresolve bonus")?, number(4, "unit size")?,
+                     values[5].parse().map_err(|_| format!("invalid id {}", values[5]))?))
+    }
+
+    /// Get path of file units were read from
+    pub fn get_file_path(&self) -> &str{
+        &self.file_path
     }
 
     /// Print all items in units vector
@@ -54,6 +105,21 @@ impl Roster{
         }
     }
 
+    /// Get table of units, only including those of the faction and unit type if given
+    pub fn units_table(&self, faction : Option<Faction>, unit_type : Option<UnitType>) -> QueryTable{
+        let mut columns : Vec<String> = ["Id", "Faction", "Name", "Type", "Bonus", "Size"].iter().map(|c| String::from(*c)).collect();
+        let mut rows : Vec<Vec<String>> = self.units.iter()
+            .filter(|u| faction.is_none_or(|f| *u.get_faction() == f) && unit_type.is_none_or(|t| *u.get_type() == t))
+            .map(|u| vec![u.get_id().to_string(), format!("{:?}", u.get_faction()), String::from(u.get_name()),
+                          format!("{:?}", u.get_type()), u.get_bonus().to_string(), u.get_size().to_string()])
+            .collect();
+        if self.index.has_packs(){
+            columns.insert(1, String::from("Pack Id"));
+            rows.iter_mut().for_each(|r| r.insert(1, String::from(self.index.get_key(r[0].parse().unwrap()).unwrap_or(""))));
+        }
+        QueryTable{ columns, rows }
+    }
+
     /// Get all units of a given Faction
     pub fn get_faction_roster(&self, faction: Faction) -> Vec<&Unit>{
         self.units.iter().filter(|u| *u.get_faction() == faction).collect()
@@ -66,21 +132,280 @@ impl Roster{
         v
     }
 
-    /// Get a unit by its id, will only return the first unit if there are multiple with the same id
+    /// Get a unit by its id, will only return the first unit if there are multiple with the same id,
+    /// see check for finding duplicate ids
     pub fn get_unit_by_id(&self, id: u32) -> &Unit{
-        self.units.iter().filter(|u| u.get_id() == id).collect::<Vec<&Unit>>()[0]
+        self.units.iter().find(|u| u.get_id() == id)
+            .unwrap_or_else(|| panic!("No unit with id {} in {}", id, self.file_path))
+    }
+
+    /// Get a unit by id, namespaced id or name, panics if there is no such unit or the name is ambiguous
+    pub fn get_unit_by_ref(&self, r : &EntryRef) -> &Unit{
+        self.get_unit_by_id(self.find_unit(r).unwrap_or_else(|e| panic!("{}", e)))
+    }
+
+    /// Get id of the unit referenced by id, namespaced id or name
+    pub fn find_unit(&self, r : &EntryRef) -> Result<u32, String>{
+        let id = match (r, self.index.resolve(r)){
+            (_, Some(id)) => id,
+            (EntryRef::Key(name), None) => pack::find_by_name(self.units.iter(), name)?,
+            (EntryRef::Id(id), None) => *id,
+        };
+        u32::try_from(id).ok().filter(|id| !self.get_units_by_id(*id).is_empty())
+            .ok_or_else(|| format!("No unit with id {} in {}", id, self.file_path))
+    }
+
+    /// Get namespaces of the content packs loaded, starting with the base namespace
+    pub fn get_namespaces(&self) -> &[String]{
+        self.index.get_namespaces()
+    }
+
+    /// Get every unit with the id
+    pub fn get_units_by_id(&self, id : u32) -> Vec<&Unit>{
+        self.units.iter().filter(|u| u.get_id() == id).collect()
     }
 
+    /// Get id after the highest id in the roster
+    pub fn get_next_id(&self) -> u32{
+        self.units.iter().map(|u| u.get_id()).max().unwrap_or(0) + 1
+    }
+
+    /// Add a unit from the fields, using the next id if none is given, and return it.
+    /// Panics if any other field is missing
+    pub fn add_unit(&mut self, fields : &UnitFields) -> Unit{
+        fn missing<T>(field : &str) -> T{
+            panic!("Missing {} for new unit", field)
+        }
+        let unit = Unit::new(
+            &format!("{:?}", fields.faction.unwrap_or_else(|| missing("faction"))),
+            fields.name.clone().unwrap_or_else(|| missing("name")),
+            &format!("{:?}", fields.unit_type.unwrap_or_else(|| missing("unit type"))),
+            fields.bonus.unwrap_or_else(|| missing("autoresolve bonus")),
+            fields.size.unwrap_or_else(|| missing("unit size")),
+            fields.id.unwrap_or_else(|| self.get_next_id()),
+        );
+        self.units.push(unit.clone());
+        unit
+    }
+
+    /// Change the given fields of the unit with the id and return it, panics if no unit or several units have the id
+    pub fn edit_unit(&mut self, id : u32, fields : &UnitFields) -> Unit{
+        let count = self.get_units_by_id(id).len();
+        if count != 1{
+            panic!("Expected one unit with id {} in {}, found {}", id, self.file_path, count);
+        }
+        let u = self.units.iter_mut().find(|u| u.get_id() == id).unwrap();
+        *u = Unit::new(
+            &format!("{:?}", fields.faction.unwrap_or(*u.get_faction())),
+            fields.name.clone().unwrap_or_else(|| String::from(u.get_name())),
+            &format!("{:?}", fields.unit_type.unwrap_or(*u.get_type())),
+            fields.bonus.unwrap_or(u.get_bonus()),
+            fields.size.unwrap_or(u.get_size()),
+            fields.id.unwrap_or(id),
+        );
+        u.clone()
+    }
+
+    /// Check the roster for lines that aren't valid units, duplicate ids, invalid names and non-positive sizes,
+    /// return a description of each problem
+    pub fn check(&self) -> Vec<String>{
+        let mut problems = self.problems.clone();
+        let mut ids : Vec<u32> = self.units.iter().map(|u| u.get_id()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        for id in ids{
+            let units = self.get_units_by_id(id);
+            if units.len() > 1{
+                let names : Vec<&str> = units.iter().map(|u| u.get_name()).collect();
+                problems.push(format!("id {} is used by {} units: {}", id, units.len(), names.join(", ")));
+            }
+        }
+        for u in self.units.iter(){
+            if u.get_name().is_empty() || u.get_name().contains(','){
+                problems.push(format!("unit {} has an invalid name \"{}\", names must be non-empty without commas", u.get_id(), u.get_name()));
+            }
+            if u.get_size() <= 0{
+                problems.push(format!("unit {} {} has size {}, sizes must be positive", u.get_id(), u.get_name(), u.get_size()));
+            }
+        }
+        problems
+    }
+
+    /// Get names of the shipped battle templates that reference the unit id
+    pub fn get_referencing_templates(&self, id : u32) -> Vec<&'static str>{
+        shipped_templates().into_iter()
+            .filter(|(_, t)| t.get_players().iter().flatten()
+                .any(|p| p.get_unit_refs().is_some_and(|refs| refs.iter().any(|r| self.find_unit(r) == Ok(id)))))
+            .map(|(n, _)| n)
+            .collect()
+    }
+
+    /// Get unit ids referenced by shipped battle templates that aren't in the roster, with the template name
+    pub fn get_missing_references(&self) -> Vec<(&'static str, String)>{
+        let mut missing = vec![];
+        for (name, t) in shipped_templates(){
+            for refs in t.get_players().iter().flatten().filter_map(|p| p.get_unit_refs()){
+                missing.extend(refs.iter().filter(|r| self.find_unit(r).is_err()).map(|r| (name, r.to_string())));
+            }
+        }
+        missing.sort_unstable();
+        missing.dedup();
+        missing
+    }
+
+    /// Get units no shipped battle template references, either by id, which only uses the first unit with the id,
+    /// or as a random unit of a player's fixed faction. Players with a random faction and units don't reference any unit
+    pub fn get_unreferenced_units(&self) -> Vec<&Unit>{
+        let mut referenced = vec![false; self.units.len()];
+        for (_, t) in shipped_templates(){
+            for p in t.get_players().iter().flatten(){
+                match (p.get_unit_refs(), p.get_faction()){
+                    (Some(refs), _) => refs.iter().filter_map(|r| self.find_unit(r).ok())
+                        .filter_map(|i| self.units.iter().position(|u| u.get_id() == i))
+                        .for_each(|i| referenced[i] = true),
+                    (None, Some(f)) => self.units.iter().enumerate().filter(|(_, u)| *u.get_faction() == f)
+                        .for_each(|(i, _)| referenced[i] = true),
+                    (None, None) => (),
+                }
+            }
+        }
+        self.units.iter().zip(referenced).filter(|(_, r)| !r).map(|(u, _)| u).collect()
+    }
+
+    /// Write units back to the file, keeping its header. Panics if the roster has problems, is embedded
+    /// or has content packs merged into it
+    pub fn save(&self){
+        if self.file_path.starts_with(EMBEDDED_PREFIX){
+            panic!("Unable to change the embedded roster, use --unit FILE to choose a units file");
+        }
+        if self.index.has_packs(){
+            panic!("Unable to change roster {} with content packs loaded", self.file_path);
+        }
+        let problems = self.check();
+        if !problems.is_empty(){
+            panic!("Unable to save roster {}:\n{}", self.file_path, problems.join("\n"));
+        }
+        let mut lines = vec![self.header.clone()];
+        lines.extend(self.units.iter().map(|u| u.to_csv_line()));
+        fs::write(&self.file_path, lines.join("\n") + "\n")
+            .unwrap_or_else(|e| panic!("Unable to write {}: {}", self.file_path, e));
+    }
+}
+
+impl PackEntry for Unit{
+    const KIND : &'static str = "unit";
+    const ID_COLUMN : usize = 5;
+
+    fn get_entry_id(&self) -> i64{
+        i64::from(self.get_id())
+    }
+
+    fn set_entry_id(&mut self, id : i64){
+        self.set_id(u32::try_from(id).unwrap_or_else(|_| panic!("Invalid unit id {}", id)));
+    }
+
+    fn get_entry_name(&self) -> &str{
+        self.get_name()
+    }
+
+    fn parse_entry(line : &str) -> Result<Self, String>{
+        Roster::parse_unit(line)
+    }
+}
+
+/// Get the battle templates embedded in the binary, by scenario name
+fn shipped_templates() -> Vec<(&'static str, BattleJSONObject)>{
+    resources::scenario_names().into_iter().map(|n| (n, BattleJSONObject::from_json(&resources::scenario_path(n)))).collect()
+}
+
+/// Unit fields given to roster add and edit, None fields are unchanged when editing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnitFields{
+    pub faction : Option<Faction>,
+    pub name : Option<String>,
+    pub unit_type : Option<UnitType>,
+    pub bonus : Option<i32>,
+    pub size : Option<i32>,
+    pub id : Option<u32>,
+}
+
+/// Action performed by the roster subcommand
+#[derive(Debug, Clone, PartialEq)]
+pub enum RosterCommand{
+    /// List units, only those of the faction and unit type if given
+    List(Option<Faction>, Option<UnitType>),
+    /// Show every unit with the id
+    Show(u32),
+    Add(UnitFields),
+    /// Change fields of the unit with the id
+    Edit(u32, UnitFields),
+    /// Check the roster and which units shipped battle templates can reference
+    Validate,
+}
+
+impl RosterCommand{
+    /// Perform the action on the roster, return false if validation found problems
+    pub fn run(&self, mut roster : Roster) -> bool{
+        match self{
+            RosterCommand::List(faction, unit_type) => println!("{}", roster.units_table(*faction, *unit_type).table_output()),
+            RosterCommand::Show(id) => {
+                let units = roster.get_units_by_id(*id);
+                if units.is_empty(){
+                    panic!("No unit with id {} in {}", id, roster.get_file_path());
+                }
+                for u in units.iter(){
+                    println!("Id: {}\nFaction: {:?}\nName: {}\nType: {:?}\nAutoresolve Bonus: {}\nUnit Size: {}",
+                             u.get_id(), u.get_faction(), u.get_name(), u.get_type(), u.get_bonus(), u.get_size());
+                }
+                if units.len() > 1{
+                    println!("Warning: id {} is used by {} units, battles use the first", id, units.len());
+                }
+                let templates = roster.get_referencing_templates(*id);
+                println!("Battle templates: {}", if templates.is_empty(){ String::from("none") } else { templates.join(", ") });
+            },
+            RosterCommand::Add(fields) => {
+                let u = roster.add_unit(fields);
+                roster.save();
+                println!("Added unit {} to {}", u.to_csv_line(), roster.get_file_path());
+            },
+            RosterCommand::Edit(id, fields) => {
+                let u = roster.edit_unit(*id, fields);
+                roster.save();
+                println!("Changed unit {} in {} to {}", id, roster.get_file_path(), u.to_csv_line());
+            },
+            RosterCommand::Validate => {
+                let problems = roster.check();
+                match problems.is_empty(){
+                    true => println!("{}: valid", roster.get_file_path()),
+                    false => println!("{}: {} problems\n{}", roster.get_file_path(), problems.len(), problems.join("\n")),
+                }
+                for (name, id) in roster.get_missing_references(){
+                    println!("Warning: battle template {} references missing unit id {}", name, id);
+                }
+                for u in roster.get_unreferenced_units(){
+                    println!("Warning: no shipped battle template can reference unit {} {}", u.get_id(), u.get_name());
+                }
+                return problems.is_empty();
+            },
+        }
+        true
+    }
 }
 
 
 #[cfg(test)]
 mod tests{
     use super::*;
+    use crate::testing::TestDir;
+
+    /// Write units to a file in a test directory and load it
+    fn test_roster(dir : &TestDir, lines : &[&str]) -> Roster{
+        Roster::load(Some(&dir.write("units.csv", &format!("{}\n{}\n", DEFAULT_HEADER, lines.join("\n")))))
+    }
 
     #[test]
     fn test_get_faction_roster(){
-        let r = Roster::new(Option::None);
+        let r = Roster::embedded();
 
         for i in r.get_faction_roster(Faction::Menoriad).iter(){
             assert_eq!(*i.get_faction(), Faction::Menoriad);
@@ -98,9 +423,95 @@ mod tests{
 
     #[test]
     fn test_get_by_id(){
-        let r = Roster::new(Option::None);
+        let r = Roster::embedded();
 
         let u = r.get_unit_by_id(1);
         assert_eq!(1, u.get_id());
+        assert_eq!(39, r.get_next_id());
+    }
+
+    #[test]
+    fn test_shipped_roster(){
+        let r = Roster::embedded();
+        assert_eq!("embedded:units.csv", r.get_file_path());
+        assert!(r.check().is_empty());
+        assert!(r.get_missing_references().is_empty());
+        // random players don't reference any unit
+        let unreferenced = r.get_unreferenced_units().iter().map(|u| u.get_id()).collect::<Vec<u32>>();
+        assert!(!unreferenced.contains(&1));
+        assert!(unreferenced.contains(&38));
+        assert_eq!(vec!["normal", "siege", "raid", "naval", "monster", "partial_random"], r.get_referencing_templates(1));
+        assert_eq!(9, r.units_table(Some(Faction::Rebel), None).rows.len());
+        assert_eq!(3, r.units_table(Some(Faction::Rebel), Some(UnitType::Cavalry)).rows.len());
+    }
+
+    #[test]
+    fn test_check(){
+        let dir = TestDir::new("roster_check");
+        let r = test_roster(&dir, &["Rebel,A,Melee,4,4,1", "Rebl,B,Melee,4,4,2", "Rebel,C,Melee,4,0,1",
+                                                      "Rebel,D,Foot,4,4,3", "Rebel,E,Melee,4,4"]);
+        assert_eq!(vec![
+            "line 3: invalid faction Rebl, expected one of Rebel, Beladimir, Lerastir, Menoriad",
+            "line 5: invalid unit type Foot, expected one of Melee, Cavalry, Ranged",
+            "line 6: expected 6 columns, found 5",
+            "id 1 is used by 2 units: A, C",
+            "unit 1 C has size 0, sizes must be positive",
+        ], r.check());
+
+        assert_eq!(vec!["C"], r.get_unreferenced_units().iter().map(|u| u.get_name()).collect::<Vec<&str>>());
+        assert!(r.get_missing_references().contains(&("normal", String::from("11"))));
+    }
+
+    #[test]
+    fn test_add_edit(){
+        let dir = TestDir::new("roster_add");
+        let mut r = test_roster(&dir, &["Rebel,A,Melee,4,4,1"]);
+        let fields = UnitFields{ faction: Some(Faction::Lerastir), name: Some(String::from("B")), unit_type: Some(UnitType::Ranged),
+            bonus: Some(2), size: Some(3), id: None };
+        assert_eq!("Lerastir,B,Ranged,2,3,2", r.add_unit(&fields).to_csv_line());
+        r.save();
+        let mut r = Roster::new(Some(r.get_file_path()));
+        let edit = UnitFields{ size: Some(5), id: Some(7), ..UnitFields::default() };
+        assert_eq!("Lerastir,B,Ranged,2,5,7", r.edit_unit(2, &edit).to_csv_line());
+        r.save();
+
+        assert_eq!(format!("{}\nRebel,A,Melee,4,4,1\nLerastir,B,Ranged,2,5,7\n", DEFAULT_HEADER),
+                   fs::read_to_string(r.get_file_path()).unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "id 1 is used by 2 units: A, B")]
+    fn test_save_duplicate_id(){
+        let dir = TestDir::new("roster_duplicate");
+        let mut r = test_roster(&dir, &["Rebel,A,Melee,4,4,1"]);
+        r.add_unit(&UnitFields{ faction: Some(Faction::Rebel), name: Some(String::from("B")), unit_type: Some(UnitType::Melee),
+            bonus: Some(1), size: Some(1), id: Some(1) });
+        r.save();
+    }
+
+    #[test]
+    fn test_packs(){
+        let dir = TestDir::new("roster_packs");
+        let mut r = test_roster(&dir, &["Rebel,A,Melee,4,4,1", "Rebel,B,Melee,4,4,2"]);
+        // the pack's namespace is its directory name
+        dir.write("test_roster_pack/units.csv", &format!("{}\nBeladimir,C,Cavalry,2,3,1\nRebel,AA,Melee,5,4,base:1\n", DEFAULT_HEADER));
+        let warnings = r.add_packs(&[ContentPack::read(&dir.file("test_roster_pack"))]);
+        assert!(warnings.is_empty());
+        assert_eq!(vec!["base", "test_roster_pack"], r.get_namespaces());
+
+        let u = r.get_unit_by_ref(&EntryRef::Key(String::from("test_roster_pack:1")));
+        assert_eq!(("C", 3), (u.get_name(), u.get_id()));
+        assert_eq!("AA", r.get_unit_by_ref(&EntryRef::Id(1)).get_name());
+        assert_eq!(vec!["1", "base:1", "Rebel", "AA"], r.units_table(None, None).rows[0][..4].to_vec());
+    }
+
+    #[test]
+    #[should_panic(expected = "with content packs loaded")]
+    fn test_save_with_packs(){
+        let dir = TestDir::new("roster_save_pack");
+        let mut r = test_roster(&dir, &["Rebel,A,Melee,4,4,1"]);
+        fs::create_dir(dir.path().join("pack")).unwrap();
+        r.add_packs(&[ContentPack::read(&dir.file("pack"))]);
+        r.save();
     }
 }
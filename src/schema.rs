@@ -0,0 +1,410 @@
+use crate::army::ARMY_KEY;
+use crate::battle::{BattleType, TownDefenses};
+use crate::faction::Faction;
+use crate::monster::MonsterType;
+use crate::pack;
+use serde_json::{json, Map, Value};
+use std::fmt::Debug;
+
+/// JSON Schema draft the battle schema is written in
+pub const SCHEMA_DRAFT : &str = "https://json-schema.org/draft/2020-12/schema";
+
+/// Prefix of references to the schema's definitions
+const DEFS_PREFIX : &str = "#/$defs/";
+
+/// JSON path of the whole document
+pub const ROOT_PATH : &str = "$";
+
+/// Problem found in a JSON document and the JSON path of the value it was found in, e.g. $.attacker.units[2]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem{
+    pub path : String,
+    pub message : String,
+}
+
+impl Problem{
+    pub fn new(path : &str, message : impl Into<String>) -> Self{
+        Problem{ path: String::from(path), message: message.into() }
+    }
+
+    /// Convert to printable line of the path and message
+    pub fn problem_output(&self) -> String{
+        format!("{}: {}", self.path, self.message)
+    }
+}
+
+/// Get JSON path of an object's field
+pub fn field_path(path : &str, key : &str) -> String{
+    let identifier = key.chars().next().map(|c| c.is_alphabetic() || c == '_').unwrap_or(false)
+        && key.chars().all(|c| c.is_alphanumeric() || c == '_');
+    match identifier{
+        true => format!("{}.{}", path, key),
+        false => format!("{}[{}]", path, Value::from(key)),
+    }
+}
+
+/// Get JSON path of an array's element
+pub fn index_path(path : &str, index : usize) -> String{
+    format!("{}[{}]", path, index)
+}
+
+/// Get reference to a definition
+fn def(name : &str) -> Value{
+    json!({ "$ref": format!("{}{}", DEFS_PREFIX, name) })
+}
+
+/// Get schema of a value that can be "Random" instead
+fn randomizable(schema : Value) -> Value{
+    json!({ "anyOf": [def("Random"), schema] })
+}
+
+/// Get schema of a non-negative integer
+fn count() -> Value{
+    json!({ "type": "integer", "minimum": 0 })
+}
+
+/// Get schema of a string that is one of names
+fn one_of(names : impl IntoIterator<Item = String>) -> Value{
+    json!({ "type": "string", "enum": names.into_iter().collect::<Vec<String>>() })
+}
+
+/// Get serialized names of enum values
+fn names<T : Debug>(values : &[T]) -> Vec<String>{
+    values.iter().map(|v| format!("{:?}", v)).collect()
+}
+
+/// Get schema of an object with only the given fields
+fn object(properties : Value, required : &[&str]) -> Value{
+    json!({ "type": "object", "properties": properties, "required": required, "additionalProperties": false })
+}
+
+/// Get the general's fields
+fn general_properties() -> Value{
+    let slot = randomizable(def("EntryRef"));
+    json!({
+        "armor": slot, "weapon": slot, "banner": slot, "follower": slot, "trinket": slot,
+        "rank": randomizable(count()),
+    })
+}
+
+/// Get a player's fields, with the general's schema
+fn player_properties(general : Value) -> Map<String, Value>{
+    serde_json::from_value(json!({
+        "general": { "anyOf": [def("Random"), general] },
+        "units": randomizable(json!({ "type": "array", "items": def("UnitsEntry") })),
+        "reinforcements": randomizable(count()),
+        "adv_combat": randomizable(json!({ "type": "boolean" })),
+        "faction": randomizable(one_of(Faction::NAMES.iter().map(|n| String::from(*n)))),
+        "remove_units": { "type": "array", "items": def("EntryRef"), "description": "Units removed from units, one per reference" },
+    })).unwrap()
+}
+
+/// Get JSON Schema of battle JSON files, with definitions of BattleJSONObject, PlayerJSONObject,
+/// GeneralJSONObject and BattleType
+pub fn battle_schema() -> Value{
+    let general_fields = ["armor", "weapon", "banner", "follower", "trinket", "rank"];
+    let player_fields = ["general", "units", "reinforcements", "adv_combat", "faction"];
+    let mut army_properties = player_properties(object(general_properties(), &[]));
+    army_properties.insert(String::from(ARMY_KEY), json!({ "type": "string", "description": "Army name or path to override fields of" }));
+    let town = def("TownStats");
+    json!({
+        "$schema": SCHEMA_DRAFT,
+        "title": "AutoResolve battle",
+        "$ref": format!("{}BattleJSONObject", DEFS_PREFIX),
+        "$defs": {
+            "Random": { "type": "string", "enum": ["Random"], "description": "Generated at every run using the generator profile" },
+            "EntryRef": {
+                "anyOf": [{ "type": "integer" }, { "type": "string" }],
+                "description": "Id, namespaced id such as base:3, or name of a unit or item, 0 is an empty equipment slot",
+            },
+            "UnitsEntry": {
+                "anyOf": [def("EntryRef"), { "type": "object", "additionalProperties": count(), "description": "Units by id or name with counts" }],
+            },
+            "BattleJSONObject": object(json!({
+                "$schema": { "type": "string" },
+                "battle_type": { "anyOf": [
+                    def("Random"),
+                    object(json!({ "Random": { "type": "string", "enum": BattleType::NAMES } }), &["Random"]),
+                    def("BattleType"),
+                ]},
+                "attacker": def("PlayerSlot"),
+                "defender": def("PlayerSlot"),
+            }), &["battle_type", "attacker", "defender"]),
+            "PlayerSlot": { "anyOf": [
+                def("Random"),
+                // objects with an army field override an army, others are a whole player
+                { "if": { "required": [ARMY_KEY] }, "then": def("ArmyReference"), "else": def("PlayerJSONObject") },
+                { "type": "string", "description": "Army name or path" },
+            ]},
+            "PlayerJSONObject": object(Value::Object(player_properties(def("GeneralJSONObject"))), &player_fields),
+            "ArmyReference": object(Value::Object(army_properties), &["army"]),
+            "GeneralJSONObject": object(general_properties(), &general_fields),
+            "BattleType": { "anyOf": [
+                { "type": "string", "enum": ["Normal"] },
+                {
+                    "type": "object",
+                    "properties": {
+                        "Siege": object(json!({ "rams": count(), "catapults": count(), "siege_towers": count(), "defenses": town }),
+                                        &["rams", "catapults", "siege_towers", "defenses"]),
+                        "Raid": object(json!({ "defenses": town }), &["defenses"]),
+                        "Naval": object(json!({ "attacker_ships": count(), "defender_ships": count() }), &["attacker_ships", "defender_ships"]),
+                        "Monster": object(json!({ "monster": one_of(names(&MonsterType::ALL)) }), &["monster"]),
+                    },
+                    "additionalProperties": false,
+                    "minProperties": 1,
+                    "maxProperties": 1,
+                },
+            ]},
+            "TownStats": object(json!({ "supplies": count(), "defenses": one_of(names(&TownDefenses::ALL)) }), &["supplies", "defenses"]),
+        },
+    })
+}
+
+/// Kind of problem found checking a value against a schema
+#[derive(Debug, Clone, PartialEq)]
+enum ErrorKind{
+    /// Expected types or values, and the value found
+    Expected(Vec<String>, Vec<String>, Value),
+    Missing(String),
+    /// Unknown field and the known fields
+    Unknown(String, Vec<String>),
+    Other(String),
+}
+
+/// Problem found checking a value against a schema
+#[derive(Debug, Clone, PartialEq)]
+struct SchemaError{
+    path : String,
+    kind : ErrorKind,
+}
+
+impl SchemaError{
+    /// Convert to Problem with a message describing it
+    fn to_problem(&self) -> Problem{
+        // suggest the closest allowed names to a misspelled name
+        let suggest = |what : &str, value : &Value, allowed : &[String]| {
+            let close : Vec<String> = match value{
+                Value::String(s) => pack::closest_names(allowed.iter().map(|a| a.as_str()), s).iter().map(|n| Value::from(*n).to_string()).collect(),
+                _ => vec![],
+            };
+            match close.len(){
+                0 => None,
+                1 => Some(format!("unknown {} {}, did you mean {}?", what, value, close[0])),
+                _ => Some(format!("unknown {} {}, did you mean one of {}?", what, value, close.join(", "))),
+            }
+        };
+        let message = match &self.kind{
+            ErrorKind::Expected(types, values, found) => suggest("value", found, values).unwrap_or_else(|| match (types.is_empty(), values.is_empty()){
+                (true, _) => format!("unknown value {}, expected one of {}", found, values.join(", ")),
+                (false, true) => format!("expected {}, found {}", types.join(" or "), type_name(found)),
+                (false, false) => format!("expected {} or {}, found {}", types.join(" or "),
+                                          values.iter().map(|v| Value::from(v.as_str()).to_string()).collect::<Vec<String>>().join(" or "), found),
+            }),
+            ErrorKind::Missing(field) => format!("missing field {}", Value::from(field.as_str())),
+            ErrorKind::Unknown(field, known) => suggest("field", &Value::from(field.as_str()), known)
+                .unwrap_or_else(|| format!("unknown field {}, expected one of {}", Value::from(field.as_str()), known.join(", "))),
+            ErrorKind::Other(m) => m.clone(),
+        };
+        Problem::new(&self.path, message)
+    }
+}
+
+/// Get name of the JSON type of a value
+fn type_name(value : &Value) -> &'static str{
+    match value{
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Check value against schema, returning every problem found. Supports the keywords used by battle_schema.
+pub fn validate(value : &Value, schema : &Value) -> Vec<Problem>{
+    let mut errors = vec![];
+    check(value, schema, schema, ROOT_PATH, &mut errors);
+    errors.iter().map(|e| e.to_problem()).collect()
+}
+
+/// Check value at path against schema, adding problems found to errors. root holds the definitions.
+fn check(value : &Value, schema : &Value, root : &Value, path : &str, errors : &mut Vec<SchemaError>){
+    let error = |kind : ErrorKind| SchemaError{ path: String::from(path), kind };
+    if let Some(r) = schema.get("$ref").and_then(Value::as_str){
+        let name = r.strip_prefix(DEFS_PREFIX).unwrap_or_else(|| panic!("Unsupported schema reference {}", r));
+        check(value, &root["$defs"][name], root, path, errors);
+    }
+    if let Some(t) = schema.get("type").and_then(Value::as_str){
+        let found = type_name(value);
+        if t != found && !(t == "number" && found == "integer"){
+            errors.push(error(ErrorKind::Expected(vec![String::from(t)], vec![], value.clone())));
+            return;
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array){
+        if !allowed.contains(value){
+            let allowed = allowed.iter().map(|a| a.as_str().map(String::from).unwrap_or_else(|| a.to_string())).collect();
+            errors.push(error(ErrorKind::Expected(vec![], allowed, value.clone())));
+        }
+    }
+    if let (Some(min), Some(n)) = (schema.get("minimum").and_then(Value::as_f64), value.as_f64()){
+        if n < min{
+            errors.push(error(ErrorKind::Other(format!("{} is less than the minimum {}", value, min))));
+        }
+    }
+    if let Value::Object(fields) = value{
+        check_object(fields, schema, root, path, errors);
+    }
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")){
+        items.iter().enumerate().for_each(|(i, v)| check(v, item_schema, root, &index_path(path, i), errors));
+    }
+    if let Some(branches) = schema.get("anyOf").and_then(Value::as_array){
+        check_any_of(value, branches, root, path, errors);
+    }
+    if let Some(condition) = schema.get("if"){
+        let mut condition_errors = vec![];
+        check(value, condition, root, path, &mut condition_errors);
+        let branch = if condition_errors.is_empty(){ "then" } else { "else" };
+        if let Some(s) = schema.get(branch){
+            check(value, s, root, path, errors);
+        }
+    }
+}
+
+/// Check an object's fields against the properties, required, additionalProperties, minProperties and
+/// maxProperties of schema
+fn check_object(fields : &Map<String, Value>, schema : &Value, root : &Value, path : &str, errors : &mut Vec<SchemaError>){
+    let error = |kind : ErrorKind| SchemaError{ path: String::from(path), kind };
+    let properties = schema.get("properties").and_then(Value::as_object);
+    for field in schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str){
+        if !fields.contains_key(field){
+            errors.push(error(ErrorKind::Missing(String::from(field))));
+        }
+    }
+    for (key, v) in fields.iter(){
+        let field = field_path(path, key);
+        match (properties.and_then(|p| p.get(key)), schema.get("additionalProperties")){
+            (Some(s), _) => check(v, s, root, &field, errors),
+            (None, Some(Value::Bool(false))) => {
+                let known = properties.map(|p| p.keys().filter(|k| !k.starts_with('$')).cloned().collect()).unwrap_or_default();
+                errors.push(SchemaError{ path: field, kind: ErrorKind::Unknown(key.clone(), known) });
+            },
+            (None, Some(s @ Value::Object(_))) => check(v, s, root, &field, errors),
+            (None, _) => (),
+        }
+    }
+    let min = schema.get("minProperties").and_then(Value::as_u64).unwrap_or(0) as usize;
+    let max = schema.get("maxProperties").and_then(Value::as_u64).map(|m| m as usize).unwrap_or(usize::MAX);
+    let names = || properties.map(|p| p.keys().cloned().collect::<Vec<String>>().join(", ")).unwrap_or_default();
+    if fields.len() < min{
+        errors.push(error(ErrorKind::Other(format!("expected at least {} of {}", min, names()))));
+    }
+    if fields.len() > max{
+        errors.push(error(ErrorKind::Other(format!("expected at most {} of {}, found {}", max, names(), fields.len()))));
+    }
+}
+
+/// Check value matches one of the branches. If every branch only expects another type or value, a single
+/// problem lists them all, otherwise the problems of the branch with the fewest are added.
+fn check_any_of(value : &Value, branches : &[Value], root : &Value, path : &str, errors : &mut Vec<SchemaError>){
+    let results : Vec<Vec<SchemaError>> = branches.iter().map(|b| {
+        let mut e = vec![];
+        check(value, b, root, path, &mut e);
+        e
+    }).collect();
+    if results.iter().any(|e| e.is_empty()){
+        return;
+    }
+    let expected = |e : &[SchemaError]| match e{
+        [SchemaError{ path: p, kind: ErrorKind::Expected(types, values, _) }] if p == path => Some((types.clone(), values.clone())),
+        _ => None,
+    };
+    if results.iter().all(|e| expected(e).is_some()){
+        let (mut types, mut values) : (Vec<String>, Vec<String>) = (vec![], vec![]);
+        for (t, v) in results.iter().filter_map(|e| expected(e)){
+            t.into_iter().for_each(|x| if !types.contains(&x){ types.push(x) });
+            v.into_iter().for_each(|x| if !values.contains(&x){ values.push(x) });
+        }
+        errors.push(SchemaError{ path: String::from(path), kind: ErrorKind::Expected(types, values, value.clone()) });
+        return;
+    }
+    if let Some(closest) = results.iter().filter(|e| expected(e).is_none()).min_by_key(|e| e.len()){
+        errors.extend(closest.iter().cloned());
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::fs;
+
+    fn problems(battle : Value) -> Vec<String>{
+        validate(&battle, &battle_schema()).iter().map(|p| p.problem_output()).collect()
+    }
+
+    #[test]
+    fn test_schema_file(){
+        // the shipped schema for editors is kept up to date with the battle JSON types
+        let shipped : Value = serde_json::from_str(&fs::read_to_string("./ResourceFiles/battle_schema.json").unwrap()).unwrap();
+        assert!(battle_schema() == shipped, "ResourceFiles/battle_schema.json is out of date, regenerate it with the schema command");
+    }
+
+    #[test]
+    fn test_valid(){
+        for name in ["normal", "siege", "raid", "naval", "monster", "partial_random"].iter(){
+            let path = format!("./ResourceFiles/{}_battle_template.json", name);
+            assert_eq!(Vec::<String>::new(), problems(crate::battle::BattleJSONObject::read_value(&path)));
+        }
+        let b = json!({"battle_type" : {"Random" : "Siege"}, "attacker" : "beladimir_vanguard", "defender" : {"army" : "x",
+            "general" : {"rank" : 4}, "remove_units" : [3]}});
+        assert_eq!(Vec::<String>::new(), problems(b));
+    }
+
+    #[test]
+    fn test_problems(){
+        let general = json!({"armor" : 0, "weapon" : "Random", "banner" : 28, "follower" : 0, "trinket" : 19, "rank" : -1});
+        let b = json!({"battle_type" : {"Seige" : {}}, "defender" : "Random",
+            "attacker" : {"general" : general, "units" : [1, {"Beladimir Spearmen" : true}], "reinforcements" : 1,
+                "adv_combat" : "yes", "faction" : "Beladimr"}});
+        assert_eq!(vec![
+            "$.attacker.adv_combat: expected boolean or \"Random\", found \"yes\"",
+            "$.attacker.faction: unknown value \"Beladimr\", did you mean \"Beladimir\"?",
+            "$.attacker.general.rank: -1 is less than the minimum 0",
+            "$.attacker.units[1][\"Beladimir Spearmen\"]: expected integer, found boolean",
+            "$.battle_type.Seige: unknown field \"Seige\", did you mean \"Siege\"?",
+        ], problems(b));
+
+        let b = json!({"battle_type" : {"Raid" : {"defenses" : {"supplies" : 1, "defenses" : "Stonewall"}}},
+            "attacker" : "Random", "defender" : "Random", "extra" : 1});
+        assert_eq!(vec![
+            "$.battle_type.Raid.defenses.defenses: unknown value \"Stonewall\", did you mean \"StoneWall\"?",
+            "$.extra: unknown field \"extra\", expected one of attacker, battle_type, defender",
+        ], problems(b));
+
+        let b = json!({"battle_type" : "Nromal", "attacker" : 3});
+        assert_eq!(vec![
+            "$: missing field \"defender\"",
+            "$.attacker: expected string or object, found integer",
+            "$.battle_type: unknown value \"Nromal\", did you mean \"Normal\"?",
+        ], problems(b));
+    }
+
+    #[test]
+    fn test_player_branch(){
+        // a player missing fields is checked as a whole player, not as an army reference
+        let b = json!({"battle_type" : "Normal", "attacker" : "Random",
+            "defender" : {"general" : "Random", "units" : [1], "faction" : "Rebel"}});
+        assert_eq!(vec!["$.defender: missing field \"reinforcements\"", "$.defender: missing field \"adv_combat\""], problems(b));
+        let b = json!({"battle_type" : "Normal", "attacker" : "Random", "defender" : {"army" : "x", "faction" : "Rebl"}});
+        assert_eq!(vec!["$.defender.faction: unknown value \"Rebl\", did you mean \"Rebel\"?"], problems(b));
+    }
+
+    #[test]
+    fn test_paths(){
+        assert_eq!("$.attacker.units", field_path("$.attacker", "units"));
+        assert_eq!("$[\"$schema\"]", field_path(ROOT_PATH, "$schema"));
+        assert_eq!("$.units[2][\"base:3\"]", field_path(&index_path("$.units", 2), "base:3"));
+    }
+}
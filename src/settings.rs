@@ -0,0 +1,237 @@
+use crate::resources;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use toml::Value;
+
+/// Config file looked for in the directory the application is run from
+pub const PROJECT_FILE : &str = "autoresolve.toml";
+
+/// Config file looked for in the user config directory
+pub const USER_FILE : &str = "config.toml";
+
+/// Table of named profiles in config files
+const PROFILES_KEY : &str = "profiles";
+
+/// Where the value of an option was set, from lowest to highest precedence
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SettingOrigin{
+    /// Default value of the CLI arg
+    Default,
+    /// Config file the value was read from
+    File(String),
+    /// Named profile and the config file it was read from
+    Profile(String, String),
+    Cli,
+}
+
+impl SettingOrigin{
+    /// Get precedence of values set here, higher overrides lower
+    pub fn get_precedence(&self) -> u8{
+        match self{
+            SettingOrigin::Default => 0,
+            SettingOrigin::File(_) => 1,
+            SettingOrigin::Profile(_, _) => 2,
+            SettingOrigin::Cli => 3,
+        }
+    }
+}
+
+/// Value of an option and where it was set
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Setting{
+    pub value : String,
+    pub origin : SettingOrigin,
+}
+
+impl Setting{
+    /// Get if the value is a flag that is set
+    pub fn is_true(&self) -> bool{
+        self.value == "true"
+    }
+
+    /// Convert to printable description of where the value was set
+    pub fn origin_output(&self) -> String{
+        match &self.origin{
+            SettingOrigin::Default => String::from("default"),
+            SettingOrigin::File(f) => format!("from {}", f),
+            SettingOrigin::Profile(p, f) => format!("from profile {} in {}", p, f),
+            SettingOrigin::Cli => String::from("from command line"),
+        }
+    }
+
+    /// Convert to printable TOML line of the option's value and where it was set
+    pub fn setting_output(&self, key : &str) -> String{
+        let value = match self.value.parse::<f64>().is_ok() || self.value.parse::<bool>().is_ok(){
+            true => self.value.clone(),
+            false => Value::String(self.value.clone()).to_string(),
+        };
+        format!("{} = {}  # {}", key, value, self.origin_output())
+    }
+}
+
+/// Option values read from TOML config files, keyed by long CLI flag without the dashes, e.g. count = 10000,
+/// and named profiles of values that override them, e.g. [profiles.quick]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigFile{
+    files : Vec<String>,
+    values : BTreeMap<String, Setting>,
+    profiles : BTreeMap<String, BTreeMap<String, Setting>>,
+}
+
+impl ConfigFile{
+    /// Read the given config file, otherwise the user config directory's config file overridden by
+    /// the project directory's, if they exist
+    pub fn load(path : Option<&str>) -> ConfigFile{
+        match path{
+            Some(p) => Self::read(p),
+            None => resources::config_dir().map(|d| d.join(USER_FILE)).into_iter()
+                .chain(std::iter::once(PathBuf::from(".").join(PROJECT_FILE)))
+                .filter(|p| p.is_file())
+                .fold(ConfigFile::default(), |mut cfg, p| {
+                    cfg.merge(Self::read(&p.to_string_lossy()));
+                    cfg
+                }),
+        }
+    }
+
+    /// Read a config file
+    pub fn read(path : &str) -> ConfigFile{
+        let content = fs::read_to_string(path).unwrap_or_else(|_| panic!("Unable to read config file {}", path));
+        Self::parse(&content, path)
+    }
+
+    /// Parse config file content read from path
+    pub fn parse(content : &str, path : &str) -> ConfigFile{
+        let table : toml::Table = content.parse().unwrap_or_else(|e| panic!("Invalid config file {}: {}", path, e));
+        let mut cfg = ConfigFile{ files: vec![String::from(path)], ..ConfigFile::default() };
+        for (key, value) in table.iter(){
+            match (key.as_str(), value){
+                (PROFILES_KEY, Value::Table(profiles)) => for (name, profile) in profiles.iter(){
+                    let values = match profile{
+                        Value::Table(t) => t,
+                        _ => panic!("Profile {} in config file {} is not a table", name, path),
+                    };
+                    let origin = SettingOrigin::Profile(name.clone(), String::from(path));
+                    cfg.profiles.insert(name.clone(), Self::parse_values(values, path, origin));
+                },
+                (PROFILES_KEY, _) => panic!("{} in config file {} is not a table", PROFILES_KEY, path),
+                _ => {
+                    let setting = Setting{ value: Self::parse_value(key, value, path), origin: SettingOrigin::File(String::from(path)) };
+                    cfg.values.insert(key.clone(), setting);
+                },
+            }
+        }
+        cfg
+    }
+
+    /// Parse table of option values set in origin
+    fn parse_values(table : &toml::Table, path : &str, origin : SettingOrigin) -> BTreeMap<String, Setting>{
+        table.iter().map(|(k, v)| (k.clone(), Setting{ value: Self::parse_value(k, v, path), origin: origin.clone() })).collect()
+    }
+
+    /// Convert option value to the text it would be given as on the command line, lists are comma separated
+    fn parse_value(key : &str, value : &Value, path : &str) -> String{
+        match value{
+            Value::String(s) => s.clone(),
+            Value::Integer(i) => i.to_string(),
+            Value::Float(f) => f.to_string(),
+            Value::Boolean(b) => b.to_string(),
+            Value::Array(a) => a.iter().map(|v| Self::parse_value(key, v, path)).collect::<Vec<String>>().join(","),
+            _ => panic!("Invalid value for {} in config file {}", key, path),
+        }
+    }
+
+    /// Override values and profiles with another config file's
+    pub fn merge(&mut self, other : ConfigFile){
+        self.files.extend(other.files);
+        self.values.extend(other.values);
+        for (name, values) in other.profiles{
+            self.profiles.entry(name).or_default().extend(values);
+        }
+    }
+
+    /// Get config files read, from lowest to highest precedence
+    pub fn get_files(&self) -> &[String]{
+        &self.files
+    }
+
+    /// Get names of the profiles
+    pub fn get_profile_names(&self) -> Vec<&str>{
+        self.profiles.keys().map(|k| k.as_str()).collect()
+    }
+
+    /// Get option values, with the named profile's values overriding the others
+    pub fn select(&self, profile : Option<&str>) -> BTreeMap<String, Setting>{
+        let mut values = self.values.clone();
+        if let Some(name) = profile{
+            match self.profiles.get(name){
+                Some(p) => values.extend(p.clone()),
+                None => panic!("Unknown config profile {}, expected one of {}", name, self.get_profile_names().join(", ")),
+            }
+        }
+        values
+    }
+}
+
+/// Print config files read, the profile used and every option's merged value and where it was set
+pub fn print_settings(files : &[String], profile : Option<&str>, settings : &[(String, Setting)]){
+    match files.is_empty(){
+        true => println!("Config files: none, looked for {} and {} in the user config directory", PROJECT_FILE, USER_FILE),
+        false => println!("Config files: {}", files.join(", ")),
+    }
+    println!("Profile: {}", profile.unwrap_or("none"));
+    settings.iter().for_each(|(k, s)| println!("{}", s.setting_output(k)));
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    const CONTENT : &str = "count = 10000\nmultithread = true\nfaction-weights = [1, 2, 3, 4]\n\
+    [profiles.quick]\ncount = 100\n\
+    [profiles.precise]\nprecision = 0.01\nprecision-target = \"outcomes\"\n";
+
+    #[test]
+    fn test_parse(){
+        let cfg = ConfigFile::parse(CONTENT, "a.toml");
+        assert_eq!(vec!["precise", "quick"], cfg.get_profile_names());
+        let values = cfg.select(None);
+        assert_eq!(3, values.len());
+        assert_eq!(Setting{ value: String::from("10000"), origin: SettingOrigin::File(String::from("a.toml")) }, values["count"]);
+        assert!(values["multithread"].is_true());
+        assert_eq!("1,2,3,4", values["faction-weights"].value);
+
+        let values = cfg.select(Some("quick"));
+        assert_eq!(Setting{ value: String::from("100"), origin: SettingOrigin::Profile(String::from("quick"), String::from("a.toml")) }, values["count"]);
+        let values = cfg.select(Some("precise"));
+        assert_eq!(("10000", "0.01"), (values["count"].value.as_str(), values["precision"].value.as_str()));
+    }
+
+    #[test]
+    fn test_merge(){
+        let mut cfg = ConfigFile::parse(CONTENT, "user.toml");
+        cfg.merge(ConfigFile::parse("count = 50\n[profiles.quick]\nseed = 3\n", "project.toml"));
+        assert_eq!(vec!["user.toml", "project.toml"], cfg.get_files());
+        let values = cfg.select(Some("quick"));
+        // profile values from every file override the files' values
+        assert_eq!(("100", "3"), (values["count"].value.as_str(), values["seed"].value.as_str()));
+        assert_eq!("50", cfg.select(None)["count"].value);
+    }
+
+    #[test]
+    fn test_setting_output(){
+        let s = Setting{ value: String::from("./units.csv"), origin: SettingOrigin::File(String::from("a.toml")) };
+        assert_eq!("unit = \"./units.csv\"  # from a.toml", s.setting_output("unit"));
+        let s = Setting{ value: String::from("100"), origin: SettingOrigin::Profile(String::from("quick"), String::from("a.toml")) };
+        assert_eq!("count = 100  # from profile quick in a.toml", s.setting_output("count"));
+        assert!(SettingOrigin::Cli.get_precedence() > s.origin.get_precedence());
+        assert!(SettingOrigin::File(String::new()).get_precedence() > SettingOrigin::Default.get_precedence());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown config profile slow, expected one of precise, quick")]
+    fn test_unknown_profile(){
+        ConfigFile::parse(CONTENT, "a.toml").select(Some("slow"));
+    }
+}
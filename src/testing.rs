@@ -0,0 +1,47 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Count of test directories created by this process, keeps their names unique
+static COUNT : AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary directory for a test's files, removed when dropped
+pub struct TestDir{
+    path : PathBuf,
+}
+
+impl TestDir{
+    /// Create an empty directory under the system temp directory, unique to this test and process
+    pub fn new(name : &str) -> Self{
+        let path = env::temp_dir().join(format!("autoresolve-{}-{}-{}", process::id(), name, COUNT.fetch_add(1, Ordering::SeqCst)));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("Unable to create test directory");
+        TestDir{path}
+    }
+
+    /// Get the directory's path
+    pub fn path(&self) -> &Path{
+        &self.path
+    }
+
+    /// Get path of a file in the directory
+    pub fn file(&self, name : &str) -> String{
+        self.path.join(name).to_string_lossy().to_string()
+    }
+
+    /// Write a file in the directory, creating its parent directories, return its path
+    pub fn write(&self, name : &str, content : &str) -> String{
+        let path = self.path.join(name);
+        fs::create_dir_all(path.parent().unwrap()).expect("Unable to create test directory");
+        fs::write(&path, content).expect("Unable to write test file");
+        path.to_string_lossy().to_string()
+    }
+}
+
+impl Drop for TestDir{
+    fn drop(&mut self){
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -1,23 +1,50 @@
 use crate::equipment::{Equipment, EquipmentType};
-use std::fs;
+use crate::database::QueryTable;
+use crate::resources::{self, Resource, EMBEDDED_PREFIX};
+use crate::pack::{self, ContentPack, EntryRef, PackEntry, PackIndex};
 use rand::seq::SliceRandom;
 use rand::Rng;
+use std::convert::TryFrom;
+use std::fs;
+
+/// Header of equipment CSV files, used if a file has none
+const DEFAULT_HEADER : &str = "Type,Name,Effect,Coin Value,Index,Autoresolve Bonus,Range,Dragon";
 
 #[derive(Clone)]
 pub struct Treasure{
     file_path : String,
+    header : String,
     items : Vec<Equipment>,
+    /// Lines of the file that couldn't be read as equipment
+    problems : Vec<String>,
+    /// Namespaced ids of the items and content packs loaded
+    index : PackIndex,
 }
 
 impl Treasure{
-    /// Create new Treasure, defaults to ./ResourceFiles/equipment.csv if None provided
+    /// Create new Treasure, looks for equipment.csv in the environment, user config directory, then embedded copy if None provided.
+    /// Panics if any line isn't valid equipment
     pub fn new(file_path : Option<&str>) -> Self{
+        let t = Self::load(file_path);
+        if let Some(p) = t.problems.first(){
+            panic!("Invalid equipment in {}: {}", t.file_path, p);
+        }
+        t
+    }
+
+    /// Create new Treasure from the embedded equipment.csv, ignoring the environment and user config directory
+    pub fn embedded() -> Self{
+        Self::new(Some(&format!("{}{}", EMBEDDED_PREFIX, Resource::Equipment.get_file_name())))
+    }
+
+    /// Create new Treasure like new, skipping and recording lines that aren't valid equipment
+    pub fn load(file_path : Option<&str>) -> Self{
         let mut t = Treasure{
-            file_path: match file_path {
-                None => String::from("./ResourceFiles/equipment.csv"),
-                Some(s) => String::from(s),
-            },
+            file_path: Resource::Equipment.locate(file_path, None).path,
+            header: String::from(DEFAULT_HEADER),
             items : vec![],
+            problems : vec![],
+            index : PackIndex::default(),
         };
         t.init();
         t
@@ -26,28 +53,55 @@ impl Treasure{
 
     /// Read in data from file_path and parse into new Equipment objects
     fn init(&mut self){
-        let file = fs::read_to_string(&self.file_path)
-            .expect(&format!{"Unable to read {}",self.file_path});
+        let file = resources::read_to_string(&self.file_path);
+        let mut lines = file.lines();
+        if let Some(h) = lines.next(){
+            self.header = String::from(h);
+        }
 
-        // Read through lines, skip first as it is the column headers
-        for line in file.lines().skip(1){
-            self.items.push(self.read_equipment(line));
+        // Read through lines after the column headers, the first item is on line 2
+        for (i, line) in lines.enumerate().filter(|(_, l)| !l.trim().is_empty()){
+            match Self::parse_equipment(line){
+                Ok(e) => self.items.push(e),
+                Err(e) => self.problems.push(format!("line {}: {}", i + 2, e)),
+            }
         }
+        self.index = PackIndex::new(&self.items);
+    }
+
+    /// Merge the equipment of content packs in order, return warnings about conflicts between packs.
+    /// Panics if a pack has invalid equipment or refers to items that aren't loaded
+    pub fn add_packs(&mut self, packs : &[ContentPack]) -> Vec<String>{
+        packs.iter()
+            .flat_map(|p| self.index.merge(&mut self.items, &p.namespace, p.read_equipment().as_deref(), &p.disable_equipment))
+            .collect()
     }
 
     /// Parse string into new Equipment object
-    fn read_equipment(&self, line: &str) -> Equipment{
-        let values : Vec<&str> = line.split(",").collect();
-        Equipment::new(
-            values[0].trim(),
-            values[1].trim().parse().unwrap(),
-            values[2].trim().parse().unwrap(),
-            values[3].trim().parse().unwrap(),
-            values[4].trim().parse().unwrap(),
-            values[5].trim().parse().unwrap(),
-            values[6].trim().parse().unwrap(),
-            values[7].trim().to_lowercase().parse().unwrap(),
-        )
+    pub fn parse_equipment(line: &str) -> Result<Equipment, String>{
+        let values : Vec<&str> = line.split(',').map(|v| v.trim()).collect();
+        if values.len() != 8{
+            return Err(format!("expected 8 columns, found {}", values.len()));
+        }
+        if EquipmentType::from_name(values[0]).is_none(){
+            return Err(format!("invalid equipment type {}, expected one of {}", values[0], EquipmentType::NAMES.join(", ")));
+        }
+        let number = |i : usize, column : &str| values[i].parse().map_err(|_| format!("invalid {} {}", column, values[i]));
+        Ok(Equipment::new(
+            values[0],
+            String::from(values[1]),
+            String::from(values[2]),
+            number(3, "coin value")?,
+            number(4, "id")?,
+            number(5, "autoresolve bonus")?,
+            number(6, "range")?,
+            values[7].to_lowercase().parse().map_err(|_| format!("invalid dragon flag {}, expected true or false", values[7]))?,
+        ))
+    }
+
+    /// Get path of file items were read from
+    pub fn get_file_path(&self) -> &str{
+        &self.file_path
     }
 
     /// Print all items in items vector
@@ -58,12 +112,82 @@ impl Treasure{
         }
     }
 
+    /// Get table of the items matching the filter
+    pub fn items_table(&self, filter : &ItemFilter) -> QueryTable{
+        let mut columns : Vec<String> = ["Id", "Type", "Name", "Effect", "CoinValue", "Bonus", "Range", "Dragon"].iter()
+            .map(|c| String::from(*c)).collect();
+        let mut rows : Vec<Vec<String>> = self.items.iter()
+            .filter(|e| filter.matches(e))
+            .map(|e| vec![e.get_id().to_string(), format!("{:?}", e.equip_type()), String::from(e.get_name()), String::from(e.get_effect()),
+                          e.get_coin_value().to_string(), e.get_bonus().to_string(), e.get_range().to_string(), e.get_is_dragon().to_string()])
+            .collect();
+        if self.index.has_packs(){
+            columns.insert(1, String::from("Pack Id"));
+            rows.iter_mut().for_each(|r| r.insert(1, String::from(self.index.get_key(r[0].parse().unwrap()).unwrap_or(""))));
+        }
+        QueryTable{ columns, rows }
+    }
+
+    /// Get id after the highest id in the treasure
+    pub fn get_next_id(&self) -> i32{
+        self.items.iter().map(|e| e.get_id()).max().unwrap_or(0) + 1
+    }
+
+    /// Add an item from the fields, using the next id if none is given, and return it
+    pub fn add_item(&mut self, f : &ItemFields) -> Equipment{
+        let item = Equipment::new(&format!("{:?}", f.equipment_type), f.name.clone(), f.effect.clone(), f.coin_value,
+                                  f.id.unwrap_or_else(|| self.get_next_id()), f.bonus, f.range, f.dragon);
+        self.items.push(item.clone());
+        item
+    }
+
+    /// Check the treasure for lines that aren't valid equipment, duplicate ids and invalid names,
+    /// return a description of each problem
+    pub fn check(&self) -> Vec<String>{
+        let mut problems = self.problems.clone();
+        let mut ids : Vec<i32> = self.items.iter().map(|e| e.get_id()).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        for id in ids{
+            let items : Vec<&str> = self.items.iter().filter(|e| e.get_id() == id).map(|e| e.get_name()).collect();
+            if items.len() > 1{
+                problems.push(format!("id {} is used by {} items: {}", id, items.len(), items.join(", ")));
+            }
+        }
+        for e in self.items.iter(){
+            if e.get_name().is_empty() || e.get_name().contains(',') || e.get_effect().contains(','){
+                problems.push(format!("item {} has an invalid name \"{}\" or effect \"{}\", names must be non-empty \
+                and neither can contain commas", e.get_id(), e.get_name(), e.get_effect()));
+            }
+        }
+        problems
+    }
+
+    /// Write items back to the file, keeping its header. Panics if the treasure has problems, is embedded
+    /// or has content packs merged into it
+    pub fn save(&self){
+        if self.file_path.starts_with(EMBEDDED_PREFIX){
+            panic!("Unable to change the embedded treasure, use --treasure FILE to choose an equipment file");
+        }
+        if self.index.has_packs(){
+            panic!("Unable to change treasure {} with content packs loaded", self.file_path);
+        }
+        let problems = self.check();
+        if !problems.is_empty(){
+            panic!("Unable to save treasure {}:\n{}", self.file_path, problems.join("\n"));
+        }
+        let mut lines = vec![self.header.clone()];
+        lines.extend(self.items.iter().map(|e| e.to_csv_line()));
+        fs::write(&self.file_path, lines.join("\n") + "\n")
+            .unwrap_or_else(|e| panic!("Unable to write {}: {}", self.file_path, e));
+    }
+
     /// Get random equipment of equip_type
     pub fn get_item_by_type(&self, equip_type: EquipmentType) -> &Equipment{
         let v = self.items.iter()
             .filter(|e| *e.equip_type() == equip_type)
             .collect::<Vec<&Equipment>>();
-        v.choose(&mut rand::thread_rng()).unwrap()
+        v.choose(&mut crate::rng::thread_rng()).unwrap()
     }
 
     /// Get a random equipment that is Dragon
@@ -71,7 +195,7 @@ impl Treasure{
         let v = self.items.iter()
             .filter(|e| e.get_is_dragon())
             .collect::<Vec<&Equipment>>();
-        v.choose(&mut rand::thread_rng()).unwrap()
+        v.choose(&mut crate::rng::thread_rng()).unwrap()
     }
 
     /// Get item by id, returns first item if there are multiple with the same id
@@ -82,9 +206,26 @@ impl Treasure{
             .get(0).map(|e| e.clone())
     }
 
+    /// Get item by id, namespaced id or name, ids of 0 or less are no item. Names are only matched against
+    /// items of the equipment type
+    pub fn get_item_by_ref(&self, r : &EntryRef, equipment_type : EquipmentType) -> Result<Option<&Equipment>, String>{
+        let id = match (r, self.index.resolve(r)){
+            (EntryRef::Id(id), _) if *id <= 0 => return Ok(None),
+            (_, Some(id)) => id,
+            (EntryRef::Key(name), None) => pack::find_by_name(self.items.iter().filter(|e| *e.equip_type() == equipment_type), name)?,
+            (EntryRef::Id(id), None) => *id,
+        };
+        Ok(i32::try_from(id).ok().and_then(|id| self.get_item_by_id(id)))
+    }
+
+    /// Get namespaces of the content packs loaded, starting with the base namespace
+    pub fn get_namespaces(&self) -> &[String]{
+        self.index.get_namespaces()
+    }
+
     /// Find equipment for battle results
     pub fn battle_find_equipment(&self) -> &Equipment{
-        let mut rng = rand::thread_rng();
+        let mut rng = crate::rng::thread_rng();
         match rng.gen_range(1..6) {
             1 => self.get_item_by_type(EquipmentType::Armor),
             2 => self.get_item_by_type(EquipmentType::Weapon),
@@ -96,13 +237,108 @@ impl Treasure{
     }
 }
 
+impl PackEntry for Equipment{
+    const KIND : &'static str = "item";
+    const ID_COLUMN : usize = 4;
+
+    fn get_entry_id(&self) -> i64{
+        i64::from(self.get_id())
+    }
+
+    fn set_entry_id(&mut self, id : i64){
+        self.set_id(i32::try_from(id).unwrap_or_else(|_| panic!("Invalid item id {}", id)));
+    }
+
+    fn get_entry_name(&self) -> &str{
+        self.get_name()
+    }
+
+    fn parse_entry(line : &str) -> Result<Self, String>{
+        Treasure::parse_equipment(line)
+    }
+}
+
+/// Filters on the items listed by treasure list and search, None fields match every item
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ItemFilter{
+    /// Text the name or effect contains, ignoring case
+    pub text : Option<String>,
+    pub equipment_type : Option<EquipmentType>,
+    pub dragon : Option<bool>,
+    /// Inclusive range of autoresolve bonuses
+    pub bonus : Option<(i32, i32)>,
+    /// Inclusive range of coin values
+    pub coin_value : Option<(i32, i32)>,
+}
+
+impl ItemFilter{
+    /// Get if the item passes every filter
+    pub fn matches(&self, e : &Equipment) -> bool{
+        let in_range = |range : Option<(i32, i32)>, v : i32| range.is_none_or(|(min, max)| v >= min && v <= max);
+        let text = self.text.as_ref().map(|t| t.to_lowercase());
+        text.is_none_or(|t| e.get_name().to_lowercase().contains(&t) || e.get_effect().to_lowercase().contains(&t)) &&
+            self.equipment_type.is_none_or(|t| *e.equip_type() == t) &&
+            self.dragon.is_none_or(|d| e.get_is_dragon() == d) &&
+            in_range(self.bonus, e.get_bonus()) &&
+            in_range(self.coin_value, e.get_coin_value())
+    }
+}
+
+/// Fields of an item added by treasure add
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemFields{
+    pub equipment_type : EquipmentType,
+    pub name : String,
+    pub effect : String,
+    pub coin_value : i32,
+    /// Id of the item, the next id if None
+    pub id : Option<i32>,
+    pub bonus : i32,
+    pub range : i32,
+    pub dragon : bool,
+}
+
+/// Action performed by the treasure subcommand
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreasureCommand{
+    /// List the items matching the filter, used by both list and search
+    List(ItemFilter),
+    Add(ItemFields),
+    /// Check the treasure for duplicate ids and invalid types and dragon flags
+    Validate,
+}
+
+impl TreasureCommand{
+    /// Perform the action on the treasure, return false if validation found problems
+    pub fn run(&self, mut treasure : Treasure) -> bool{
+        match self{
+            TreasureCommand::List(filter) => println!("{}", treasure.items_table(filter).table_output()),
+            TreasureCommand::Add(f) => {
+                let e = treasure.add_item(f);
+                treasure.save();
+                println!("Added item {} to {}", e.to_csv_line(), treasure.get_file_path());
+            },
+            TreasureCommand::Validate => {
+                let problems = treasure.check();
+                match problems.is_empty(){
+                    true => println!("{}: valid", treasure.get_file_path()),
+                    false => println!("{}: {} problems\n{}", treasure.get_file_path(), problems.len(), problems.join("\n")),
+                }
+                return problems.is_empty();
+            },
+        }
+        true
+    }
+}
+
 #[cfg(test)]
 mod tests{
     use super::*;
+    use crate::testing::TestDir;
 
     #[test]
     fn get_item_test(){
-        let t = Treasure::new(Option::None);
+        let t = Treasure::embedded();
 
         assert_eq!(EquipmentType::Armor, *t.get_item_by_type(EquipmentType::Armor).equip_type());
         assert_eq!(EquipmentType::Weapon, *t.get_item_by_type(EquipmentType::Weapon).equip_type());
@@ -113,14 +349,57 @@ mod tests{
 
     #[test]
     fn get_dragon_test(){
-        let t = Treasure::new(Option::None);
+        let t = Treasure::embedded();
 
         assert!(t.get_dragon_equipment().get_is_dragon());
     }
 
+    #[test]
+    fn test_items_table(){
+        let t = Treasure::embedded();
+        assert!(t.check().is_empty());
+        let armor = t.items_table(&ItemFilter{ text: Some(String::from("warding")), ..ItemFilter::default() });
+        // duplicate items are told apart by id
+        assert_eq!(vec![vec!["1", "Armor", "Warding Armor", "+1 R Def", "100", "0", "0", "false"],
+                        vec!["2", "Armor", "Warding Armor", "+1 R Def", "100", "0", "0", "false"]], armor.rows);
+        let dragon = t.items_table(&ItemFilter{ dragon: Some(true), ..ItemFilter::default() });
+        assert_eq!(4, dragon.rows.len());
+        let filter = ItemFilter{ equipment_type: Some(EquipmentType::Weapon), bonus: Some((1, 10)), coin_value: Some((0, 200)),
+            ..ItemFilter::default() };
+        assert!(t.items_table(&filter).rows.iter().all(|r| r[1] == "Weapon" && r[5] != "0" && r[4].parse::<i32>().unwrap() <= 200));
+        let cursed = Equipment::new("Weapon", String::from("Cursed Blade"), String::from("-2"), 10, 50, -2, 0, false);
+        assert!(ItemFilter{ bonus: Some((-3, -1)), ..ItemFilter::default() }.matches(&cursed));
+    }
+
+    #[test]
+    fn test_check_and_add(){
+        let dir = TestDir::new("treasure_check");
+        let path = &dir.write("equipment.csv", &format!("{}\nArmor,A,+1 R Def,100,1,0,0,false\nArmour,B,+1,100,2,0,0,false\n\
+        Weapon,C,+1,100,1,1,0,false\nBanner,D,+1,100,3,0,0,yes\n", DEFAULT_HEADER));
+        let t = Treasure::load(Some(path));
+        assert_eq!(vec![
+            "line 3: invalid equipment type Armour, expected one of Armor, Weapon, Trinket, Banner, Follower",
+            "line 5: invalid dragon flag yes, expected true or false",
+            "id 1 is used by 2 items: A, C",
+        ], t.check());
+
+        fs::write(path, format!("{}\nArmor,A,+1 R Def,100,1,0,0,false\n", DEFAULT_HEADER)).unwrap();
+        let mut t2 = Treasure::new(Some(path));
+        let fields = ItemFields{ equipment_type: EquipmentType::Trinket, name: String::from("E"), effect: String::from("+2 Rank"),
+            coin_value: 50, id: None, bonus: 2, range: 0, dragon: true };
+        assert_eq!("Trinket,E,+2 Rank,50,2,2,0,true", t2.add_item(&fields).to_csv_line());
+        t2.save();
+        assert_eq!(format!("{}\nArmor,A,+1 R Def,100,1,0,0,false\nTrinket,E,+2 Rank,50,2,2,0,true\n", DEFAULT_HEADER),
+                   fs::read_to_string(path).unwrap());
+
+        // items with duplicate ids aren't saved
+        t2.add_item(&ItemFields{ id: Some(1), ..fields });
+        assert!(std::panic::catch_unwind(|| t2.save()).is_err());
+    }
+
     #[test]
     fn test_get_by_id(){
-        let t = Treasure::new(None);
+        let t = Treasure::embedded();
         let e = t.get_item_by_id(0);
         assert_eq!(None, e);
         let e = t.get_item_by_id(1);
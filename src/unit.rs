@@ -20,20 +20,9 @@ impl Default for Unit{
 impl Unit{
     pub fn new(faction_str: &str, name: String, unit_type_str: &str, bonus : i32, size: i32, id: u32) -> Self{
         Unit{
-            faction :  match faction_str.to_lowercase().as_ref(){
-                "rebel" => Faction::Rebel,
-                "beladimir" => Faction::Beladimir,
-                "lerastir" => Faction::Lerastir,
-                "menoriad" => Faction::Menoriad,
-                _ => panic!("Invalid integer to Faction {}!", faction_str)
-            },
+            faction : Faction::from_name(faction_str).unwrap_or_else(|| panic!("Invalid integer to Faction {}!", faction_str)),
             name: name,
-            unit_type: match unit_type_str.to_lowercase().as_ref(){
-                "melee" => UnitType::Melee,
-                "cavalry" => UnitType::Cavalry,
-                "ranged" => UnitType::Ranged,
-                _ => panic!("Invalid string to UnitType: {}", unit_type_str)
-            },
+            unit_type: UnitType::from_name(unit_type_str).unwrap_or_else(|| panic!("Invalid string to UnitType: {}", unit_type_str)),
             autoresolve_bonus: bonus,
             unit_size: size,
             id,
@@ -70,6 +59,16 @@ impl Unit{
         self.id
     }
 
+    /// Set unit id, used when merging content packs
+    pub fn set_id(&mut self, id : u32){
+        self.id = id;
+    }
+
+    /// Convert to line of the units CSV file
+    pub fn to_csv_line(&self) -> String{
+        format!("{:?},{},{:?},{},{},{}", self.faction, self.name, self.unit_type, self.autoresolve_bonus, self.unit_size, self.id)
+    }
+
     /// Assign casualties to unit, return if operation successful
     pub fn assign_casualties(&mut self, cas : i32) -> bool{
         if cas > self.unit_size{
@@ -87,3 +86,18 @@ pub enum UnitType {
     Cavalry,
     Ranged,
 }
+
+impl UnitType{
+    /// Names of all unit types
+    pub const NAMES : [&'static str; 3] = ["Melee", "Cavalry", "Ranged"];
+
+    /// Get UnitType from its name, ignoring case
+    pub fn from_name(name : &str) -> Option<Self>{
+        match name.to_lowercase().as_ref(){
+            "melee" => Some(UnitType::Melee),
+            "cavalry" => Some(UnitType::Cavalry),
+            "ranged" => Some(UnitType::Ranged),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,166 @@
+use crate::army::{self, SIDES};
+use crate::battle::BattleJSONObject;
+use crate::equipment::EquipmentType;
+use crate::pack::EntryRef;
+use crate::resources::{self, EMBEDDED_PREFIX};
+use crate::roster::Roster;
+use crate::schema::{self, field_path, index_path, Problem, ROOT_PATH};
+use crate::treasure::Treasure;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Fields of the general's equipment in battle JSON and the type of item each holds
+const SLOTS : [(&str, EquipmentType); 5] = [("armor", EquipmentType::Armor), ("weapon", EquipmentType::Weapon),
+    ("banner", EquipmentType::Banner), ("follower", EquipmentType::Follower), ("trinket", EquipmentType::Trinket)];
+
+/// Check a battle JSON file can be read, its armies can be found, it matches the battle schema and its units
+/// and equipment are in the roster and treasure, return every problem found
+pub fn validate_battle_file(path : &str, roster : &Roster, treasure : &Treasure) -> Vec<Problem>{
+    let content = match path.starts_with(EMBEDDED_PREFIX){
+        true => resources::read_to_string(path),
+        false => match fs::read_to_string(path){
+            Ok(c) => c,
+            Err(e) => return vec![Problem::new(ROOT_PATH, format!("unable to read file: {}", e))],
+        },
+    };
+    let mut battle : Value = match serde_json::from_str(&content){
+        Ok(j) => j,
+        Err(e) => return vec![Problem::new(ROOT_PATH, format!("invalid JSON: {}", e))],
+    };
+    let base_dir = army::base_dir(path);
+    let mut problems : Vec<Problem> = SIDES.iter()
+        .filter_map(|side| army::expand_side(&mut battle, side, base_dir.as_deref()).err().map(|e| Problem::new(&field_path(ROOT_PATH, side), e)))
+        .collect();
+    problems.extend(schema::validate(&battle, &schema::battle_schema()));
+    for side in SIDES.iter(){
+        if let Some(player) = battle.get(*side){
+            check_references(player, &field_path(ROOT_PATH, side), roster, treasure, &mut problems);
+        }
+    }
+    // anything the schema doesn't cover
+    if problems.is_empty(){
+        if let Err(e) = serde_json::from_value::<BattleJSONObject>(battle){
+            problems.push(Problem::new(ROOT_PATH, e.to_string()));
+        }
+    }
+    problems
+}
+
+/// Check the units and equipment a player references are in the roster and treasure, and the units it removes
+/// are in its units
+fn check_references(player : &Value, path : &str, roster : &Roster, treasure : &Treasure, problems : &mut Vec<Problem>){
+    let entry = |v : &Value| serde_json::from_value::<EntryRef>(v.clone()).ok();
+    let general = field_path(path, "general");
+    for (slot, t) in SLOTS.iter(){
+        if let Some(r) = player.get("general").and_then(|g| g.get(*slot)).and_then(entry){
+            let problem = match treasure.get_item_by_ref(&r, *t){
+                Ok(Some(e)) if e.equip_type() != t => Some(format!("{} is the {:?} {}, not a {:?}", r, e.equip_type(), e.get_name(), t)),
+                Ok(Some(_)) => None,
+                // ids of 0 or less are an empty slot
+                Ok(None) if matches!(r, EntryRef::Id(id) if id <= 0) => None,
+                Ok(None) => Some(format!("No item with id {} in {}", r, treasure.get_file_path())),
+                Err(e) => Some(e),
+            };
+            if let Some(p) = problem{
+                problems.push(Problem::new(&field_path(&general, slot), p));
+            }
+        }
+    }
+
+    // count of each unit id, to check removed units against
+    let mut counts : BTreeMap<u32, u64> = BTreeMap::new();
+    let units = field_path(path, "units");
+    for (i, u) in player.get("units").and_then(Value::as_array).into_iter().flatten().enumerate(){
+        let unit_path = index_path(&units, i);
+        let refs : Vec<(String, EntryRef, u64)> = match u{
+            Value::Object(c) => c.iter().map(|(k, n)| (field_path(&unit_path, k), EntryRef::from_key(k), n.as_u64().unwrap_or(0))).collect(),
+            v => entry(v).map(|r| (unit_path.clone(), r, 1)).into_iter().collect(),
+        };
+        for (p, r, n) in refs{
+            match roster.find_unit(&r){
+                Ok(id) => *counts.entry(id).or_default() += n,
+                Err(e) => problems.push(Problem::new(&p, e)),
+            }
+        }
+    }
+    let removed = field_path(path, "remove_units");
+    let fixed_units = player.get("units").map(Value::is_array).unwrap_or(false);
+    for (i, r) in player.get("remove_units").and_then(Value::as_array).into_iter().flatten().enumerate(){
+        let p = index_path(&removed, i);
+        match entry(r).map(|r| roster.find_unit(&r)){
+            Some(Ok(id)) => match counts.get_mut(&id){
+                Some(n) if *n > 0 => *n -= 1,
+                _ if fixed_units => problems.push(Problem::new(&p, format!("unable to remove unit {}, the army has none left", r))),
+                _ => problems.push(Problem::new(&p, "unable to remove units from randomized units")),
+            },
+            Some(Err(e)) => problems.push(Problem::new(&p, e)),
+            None => (),
+        }
+    }
+}
+
+/// Print if each battle JSON file is valid, with every problem of invalid files, return if all are valid
+pub fn validate_files(files : &[String], roster : &Roster, treasure : &Treasure) -> bool{
+    files.iter().map(|f| {
+        let problems = validate_battle_file(f, roster, treasure);
+        match problems.is_empty(){
+            true => println!("{}: valid", f),
+            false => {
+                println!("{}: invalid", f);
+                problems.iter().for_each(|p| println!("  {}", p.problem_output()));
+            },
+        }
+        problems.is_empty()
+    }).filter(|valid| !valid).count() == 0
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::testing::TestDir;
+
+    fn validate(path : &str) -> Vec<String>{
+        validate_battle_file(path, &Roster::embedded(), &Treasure::embedded()).iter().map(|p| p.problem_output()).collect()
+    }
+
+    #[test]
+    fn test_validate(){
+        assert!(validate("./ResourceFiles/siege_battle_template.json").is_empty());
+        assert!(validate("embedded:partial_random_battle_template.json").is_empty());
+        assert!(validate("./ResourceFiles/missing.json")[0].starts_with("$: unable to read file"));
+
+        let dir = TestDir::new("validate");
+        let path = dir.write("battle.json", "{\"battle_type\" : \"Normal\"}");
+        assert_eq!(vec!["$: missing field \"attacker\"", "$: missing field \"defender\""], validate(&path));
+        assert!(!validate_files(&[String::from("./ResourceFiles/missing.json")], &Roster::embedded(), &Treasure::embedded()));
+    }
+
+    #[test]
+    fn test_references(){
+        let dir = TestDir::new("validate_references");
+        let path = dir.write("battle.json", r#"{"battle_type" : {"Siege" : {"rams" : 1, "catapults" : 0, "siege_towers" : 0,
+            "defenses" : {"supplies" : 2, "defenses" : "Stonewall"}}},
+            "attacker" : {"army" : "beladimir_vanguard", "general" : {"armor" : "Warding Armor"}, "remove_units" : [3, 3]},
+            "defender" : {"general" : "Random", "units" : [{"Beladimir Spearman" : 2}, 900], "reinforcements" : 0,
+                "adv_combat" : false, "faction" : "Menoriad"}}"#);
+        assert_eq!(vec![
+            "$.battle_type.Siege.defenses.defenses: unknown value \"Stonewall\", did you mean \"StoneWall\"?",
+            "$.attacker.general.armor: Ambiguous item name \"Warding Armor\", use one of the ids 1, 2",
+            "$.attacker.remove_units[1]: unable to remove unit 3, the army has none left",
+            "$.defender.units[0][\"Beladimir Spearman\"]: No unit named \"Beladimir Spearman\", did you mean \"Beladimir Spearmen\"?",
+            "$.defender.units[1]: No unit with id 900 in embedded:units.csv",
+        ], validate(&path));
+    }
+
+    #[test]
+    fn test_equipment_slots(){
+        let dir = TestDir::new("validate_slots");
+        let path = dir.write("battle.json", r#"{"battle_type" : "Normal", "defender" : "Random",
+            "attacker" : {"army" : "beladimir_vanguard", "general" : {"weapon" : 900, "banner" : 10, "armor" : 0}}}"#);
+        assert_eq!(vec![
+            "$.attacker.general.weapon: No item with id 900 in embedded:equipment.csv",
+            "$.attacker.general.banner: 10 is the Weapon Piercing Blade, not a Banner",
+        ], validate(&path));
+    }
+}